@@ -0,0 +1,95 @@
+use std::io::{Read, Seek};
+
+use anyhow::Result;
+use sfbinpack::{CompressedTrainingDataEntryReader, TrainingDataEntry};
+
+/// Outcome of replaying every continuation of a binpack.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Continuity {
+    pub entries: u64,
+    /// Entries flagged as continuing the previous one
+    pub continuations: u64,
+    /// Index of the first entry whose move does not lead to the next entry's
+    /// position although the next entry is flagged as its continuation
+    pub first_violation: Option<u64>,
+}
+
+/// Checks that the move of every entry followed by a continuation leads to the
+/// continuation's position. `entries` yields each entry together with whether
+/// the reader flagged the entry after it as a continuation.
+pub fn check_continuity<I>(entries: I) -> Result<Continuity>
+where
+    I: IntoIterator<Item = (TrainingDataEntry, bool)>,
+{
+    let mut report = Continuity::default();
+    let mut expected: Option<String> = None;
+
+    for (index, (entry, next_continues)) in entries.into_iter().enumerate() {
+        let index = index as u64;
+        report.entries += 1;
+
+        if let Some(expected) = expected.take() {
+            report.continuations += 1;
+            if report.first_violation.is_none() && fen(&entry)? != expected {
+                report.first_violation = Some(index - 1);
+            }
+        }
+
+        if next_continues {
+            let mut pos = entry.pos;
+            pos.do_move(entry.mv);
+            expected = Some(
+                pos.fen()
+                    .map_err(|err| anyhow::anyhow!("failed to render FEN: {err:?}"))?,
+            );
+        }
+    }
+
+    Ok(report)
+}
+
+/// Runs [`check_continuity`] over the entries of a binpack.
+pub fn check_continuity_of_file<T: Read + Seek>(file: T) -> Result<Continuity> {
+    let mut reader = CompressedTrainingDataEntryReader::new(file)?;
+    let entries = std::iter::from_fn(move || {
+        if !reader.has_next() {
+            return None;
+        }
+        let entry = reader.next();
+        let next_continues = reader.has_next() && reader.is_next_entry_continuation();
+        Some((entry, next_continues))
+    });
+    check_continuity(entries)
+}
+
+fn fen(entry: &TrainingDataEntry) -> Result<String> {
+    entry
+        .pos
+        .fen()
+        .map_err(|err| anyhow::anyhow!("failed to render FEN for entry: {err:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{check_continuity, check_continuity_of_file};
+    use crate::binpack::test_util::{game_entries, write_entries};
+
+    #[test]
+    fn broken_continuation_is_reported() {
+        let mut entries = game_entries(&[(12, 28, 10), (52, 36, 20), (6, 21, 30)], 0);
+        entries.extend(game_entries(&[(11, 27, 40), (51, 35, 50)], 0));
+
+        let report = check_continuity_of_file(Cursor::new(write_entries(&entries))).unwrap();
+        assert_eq!(report.entries, 5);
+        assert_eq!(report.continuations, 3);
+        assert_eq!(report.first_violation, None);
+
+        // flag the start of the second game as continuing the first one
+        let flags = [true, true, true, true, false];
+        let report = check_continuity(entries.into_iter().zip(flags)).unwrap();
+        assert_eq!(report.continuations, 4);
+        assert_eq!(report.first_violation, Some(2));
+    }
+}
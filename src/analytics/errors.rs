@@ -0,0 +1,218 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use super::summary::quote;
+
+/// Broad cause of a file failing to convert.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorCategory {
+    SanParse,
+    FenParse,
+    UnsupportedVariant,
+    Utf8,
+    EvalParse,
+    Other,
+}
+
+impl ErrorCategory {
+    pub const ALL: [ErrorCategory; 6] = [
+        ErrorCategory::SanParse,
+        ErrorCategory::FenParse,
+        ErrorCategory::UnsupportedVariant,
+        ErrorCategory::Utf8,
+        ErrorCategory::EvalParse,
+        ErrorCategory::Other,
+    ];
+
+    /// Sorts an error into a category by its message, including its context chain
+    /// (as rendered by `{:#}`).
+    pub fn classify(message: &str) -> Self {
+        let lower = message.to_ascii_lowercase();
+        // SAN errors mention the FEN of the position, so they go first
+        if lower.contains("parsing san") {
+            ErrorCategory::SanParse
+        } else if lower.contains("variant") {
+            ErrorCategory::UnsupportedVariant
+        } else if lower.contains("utf-8") {
+            ErrorCategory::Utf8
+        } else if lower.contains("parse evaluation") {
+            ErrorCategory::EvalParse
+        } else if lower.contains("fen") {
+            ErrorCategory::FenParse
+        } else {
+            ErrorCategory::Other
+        }
+    }
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorCategory::SanParse => "SAN parse",
+            ErrorCategory::FenParse => "FEN parse",
+            ErrorCategory::UnsupportedVariant => "unsupported variant",
+            ErrorCategory::Utf8 => "UTF-8",
+            ErrorCategory::EvalParse => "eval parse",
+            ErrorCategory::Other => "other",
+        })
+    }
+}
+
+/// Failed files per [`ErrorCategory`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ErrorSummary {
+    counts: BTreeMap<ErrorCategory, usize>,
+}
+
+impl ErrorSummary {
+    pub fn add(&mut self, message: &str) {
+        *self
+            .counts
+            .entry(ErrorCategory::classify(message))
+            .or_default() += 1;
+    }
+
+    pub fn count(&self, category: ErrorCategory) -> usize {
+        self.counts.get(&category).copied().unwrap_or(0)
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    pub fn print(&self) {
+        let _ = self.write_report(&mut io::stdout().lock());
+    }
+
+    /// Categories by descending count.
+    pub fn write_report<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut counts: Vec<_> = ErrorCategory::ALL
+            .into_iter()
+            .map(|category| (category, self.count(category)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        // stable, so equal counts keep the order of ALL
+        counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+
+        writeln!(out, "Errors by category ({} files):", self.total())?;
+        for (category, count) in counts {
+            writeln!(out, "  {:<20} {:>8}", category.to_string(), count)?;
+        }
+        Ok(())
+    }
+}
+
+/// One failure listed by [`ErrorReport`].
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ErrorEntry {
+    pub path: PathBuf,
+    /// Index of the failed game within its file, `None` if the file as a whole failed
+    pub game: Option<u64>,
+    pub message: String,
+}
+
+/// Every failed file and skipped game of a build, for `--error-report`.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorReport {
+    entries: Vec<ErrorEntry>,
+}
+
+impl ErrorReport {
+    pub fn add(&mut self, path: &Path, game: Option<u64>, message: String) {
+        self.entries.push(ErrorEntry {
+            path: path.to_path_buf(),
+            game,
+            message,
+        });
+    }
+
+    /// Entries by file, file failures before the games of that file.
+    pub fn entries(&self) -> Vec<&ErrorEntry> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort();
+        entries
+    }
+
+    /// One tab separated `path game message` line per entry, `-` for the game of a
+    /// failed file.
+    pub fn write_text<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for entry in self.entries() {
+            let game = entry.game.map_or("-".to_string(), |game| game.to_string());
+            writeln!(
+                out,
+                "{}\t{}\t{}",
+                entry.path.display(),
+                game,
+                entry.message.replace('\n', " ")
+            )?;
+        }
+        Ok(())
+    }
+
+    /// A JSON array of `{"file", "game", "message"}` objects, `game` null for a failed file.
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .entries()
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{{\"file\": {}, \"game\": {}, \"message\": {}}}",
+                    quote(&entry.path.display().to_string()),
+                    entry
+                        .game
+                        .map_or("null".to_string(), |game| game.to_string()),
+                    quote(&entry.message)
+                )
+            })
+            .collect();
+        format!("[{}]", entries.join(", "))
+    }
+
+    /// Writes the report as JSON if `path` ends in `.json`, as text otherwise.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = path.extension().is_some_and(|ext| ext == "json");
+        let write = || -> io::Result<()> {
+            let mut out = io::BufWriter::new(std::fs::File::create(path)?);
+            if json {
+                writeln!(out, "{}", self.to_json())?;
+            } else {
+                self.write_text(&mut out)?;
+            }
+            out.flush()
+        };
+        write().with_context(|| format!("writing error report to {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorCategory, ErrorSummary};
+
+    #[test]
+    fn report_sorts_categories_by_count() {
+        let mut summary = ErrorSummary::default();
+        summary.add("processing game moves: failed to parse evaluation from comment: ??");
+        summary.add("processing game moves: failed to parse evaluation from comment: !!");
+        summary.add("processing game moves: variant tag not supported");
+
+        let mut report = Vec::new();
+        summary.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        let lines: Vec<_> = report
+            .lines()
+            .map(str::split_whitespace)
+            .map(Iterator::collect::<Vec<_>>)
+            .collect();
+
+        assert_eq!(lines[0], ["Errors", "by", "category", "(3", "files):"]);
+        assert_eq!(lines[1], ["eval", "parse", "2"]);
+        assert_eq!(lines[2], ["unsupported", "variant", "1"]);
+        assert_eq!(summary.count(ErrorCategory::SanParse), 0);
+    }
+}
@@ -0,0 +1,301 @@
+use std::str::FromStr;
+
+/// Per-entry values an [`EntryFilter`] can refer to.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EntryFields {
+    pub score: i64,
+    /// Game result from the side to move's point of view (1, 0, -1)
+    pub result: i64,
+    pub ply: i64,
+    /// Number of pieces on the board, kings included
+    pub pieces: i64,
+    /// 1 if the entry continues the previous entry's game, 0 otherwise
+    pub cont: i64,
+}
+
+/// A small boolean expression over [`EntryFields`], e.g.
+/// `score>100 && result==1 && ply<40`.
+///
+/// Supports the comparisons `== != < <= > >=` between a field and an integer,
+/// `&&`, `||` (with `&&` binding tighter) and parentheses.
+#[derive(Clone, Debug)]
+pub struct EntryFilter {
+    expr: Expr,
+}
+
+impl EntryFilter {
+    pub fn matches(&self, fields: &EntryFields) -> bool {
+        self.expr.eval(fields)
+    }
+}
+
+impl FromStr for EntryFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected token {:?} in filter", token));
+        }
+
+        Ok(Self { expr })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Score,
+    Result,
+    Ply,
+    Pieces,
+    Cont,
+}
+
+impl Field {
+    fn value(self, fields: &EntryFields) -> i64 {
+        match self {
+            Field::Score => fields.score,
+            Field::Result => fields.result,
+            Field::Ply => fields.ply,
+            Field::Pieces => fields.pieces,
+            Field::Cont => fields.cont,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Compare(Field, Op, i64),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, fields: &EntryFields) -> bool {
+        match self {
+            Expr::Compare(field, op, rhs) => {
+                let lhs = field.value(fields);
+                match op {
+                    Op::Eq => lhs == *rhs,
+                    Op::Ne => lhs != *rhs,
+                    Op::Lt => lhs < *rhs,
+                    Op::Le => lhs <= *rhs,
+                    Op::Gt => lhs > *rhs,
+                    Op::Ge => lhs >= *rhs,
+                }
+            }
+            Expr::And(a, b) => a.eval(fields) && b.eval(fields),
+            Expr::Or(a, b) => a.eval(fields) || b.eval(fields),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Field(Field),
+    Number(i64),
+    Op(Op),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            '&' if next == Some('&') => {
+                tokens.push(Token::And);
+                i += 2;
+            }
+            '|' if next == Some('|') => {
+                tokens.push(Token::Or);
+                i += 2;
+            }
+            '=' if next == Some('=') => {
+                tokens.push(Token::Op(Op::Eq));
+                i += 2;
+            }
+            '!' if next == Some('=') => {
+                tokens.push(Token::Op(Op::Ne));
+                i += 2;
+            }
+            '<' | '>' => {
+                let inclusive = next == Some('=');
+                tokens.push(Token::Op(match (c, inclusive) {
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    _ => Op::Ge,
+                }));
+                i += if inclusive { 2 } else { 1 };
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let value = text
+                    .parse()
+                    .map_err(|_| format!("invalid number {:?} in filter", text))?;
+                tokens.push(Token::Number(value));
+            }
+            c if c.is_ascii_alphabetic() => {
+                let start = i;
+                while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                    i += 1;
+                }
+                let name: String = chars[start..i].iter().collect();
+                let field = match name.as_str() {
+                    "score" => Field::Score,
+                    "result" => Field::Result,
+                    "ply" => Field::Ply,
+                    "pieces" => Field::Pieces,
+                    "cont" => Field::Cont,
+                    _ => {
+                        return Err(format!(
+                            "unknown field {:?} (expected score, result, ply, pieces or cont)",
+                            name
+                        ))
+                    }
+                };
+                tokens.push(Token::Field(field));
+            }
+            _ => return Err(format!("unexpected character {:?} in filter", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_primary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("missing closing parenthesis in filter".to_string()),
+                }
+            }
+            Some(Token::Field(field)) => match (self.next(), self.next()) {
+                (Some(Token::Op(op)), Some(Token::Number(value))) => {
+                    Ok(Expr::Compare(field, op, value))
+                }
+                _ => Err(format!(
+                    "expected a comparison like {:?} > 0 in filter",
+                    field
+                )),
+            },
+            Some(token) => Err(format!("unexpected token {:?} in filter", token)),
+            None => Err("unexpected end of filter".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EntryFields, EntryFilter};
+
+    fn fields(score: i64, result: i64, ply: i64) -> EntryFields {
+        EntryFields {
+            score,
+            result,
+            ply,
+            pieces: 32,
+            cont: 1,
+        }
+    }
+
+    #[test]
+    fn evaluates_conjunctions() {
+        let filter: EntryFilter = "score>100 && result==1 && ply<40".parse().unwrap();
+
+        assert!(filter.matches(&fields(150, 1, 20)));
+        assert!(!filter.matches(&fields(100, 1, 20)));
+        assert!(!filter.matches(&fields(150, 0, 20)));
+        assert!(!filter.matches(&fields(150, 1, 40)));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let filter: EntryFilter = "score < -50 || score > 50 && (ply >= 10 || cont == 0)"
+            .parse()
+            .unwrap();
+
+        assert!(filter.matches(&fields(-60, 0, 0)));
+        assert!(filter.matches(&fields(60, 0, 12)));
+        assert!(!filter.matches(&fields(60, 0, 2)));
+        assert!(!filter.matches(&fields(0, 0, 12)));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!("depth > 3".parse::<EntryFilter>().is_err());
+        assert!("score >".parse::<EntryFilter>().is_err());
+    }
+}
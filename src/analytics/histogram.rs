@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+
+use crate::analytics::VALUE_NONE_SCORE;
+use crate::wdl::wdl::MATE_SCORE_THRESHOLD;
+
+/// Width of a histogram bucket in centipawns.
+pub const BUCKET_WIDTH: i32 = 50;
+
+/// Distribution of stored scores, with mate scores and VALUE_NONE counted separately.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EvalHistogram {
+    /// Lower bound of each bucket -> count
+    buckets: BTreeMap<i32, u64>,
+    mate: u64,
+    none: u64,
+}
+
+impl EvalHistogram {
+    pub fn add(&mut self, score: i32) {
+        if score.abs() == VALUE_NONE_SCORE {
+            self.none += 1;
+        } else if score.abs() >= MATE_SCORE_THRESHOLD {
+            self.mate += 1;
+        } else {
+            let bucket = score.div_euclid(BUCKET_WIDTH) * BUCKET_WIDTH;
+            *self.buckets.entry(bucket).or_default() += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &EvalHistogram) {
+        for (bucket, count) in &other.buckets {
+            *self.buckets.entry(*bucket).or_default() += count;
+        }
+        self.mate += other.mate;
+        self.none += other.none;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.buckets.values().sum::<u64>() + self.mate + self.none
+    }
+
+    pub fn print(&self) {
+        println!("Eval histogram ({}cp buckets):", BUCKET_WIDTH);
+        for (bucket, count) in &self.buckets {
+            println!(
+                "  [{:>6}, {:>6})  {:>12}",
+                bucket,
+                bucket + BUCKET_WIDTH,
+                count
+            );
+        }
+        println!("  {:<16}  {:>12}", "mate", self.mate);
+        println!("  {:<16}  {:>12}", "VALUE_NONE", self.none);
+        println!("  {:<16}  {:>12}", "total", self.total());
+    }
+}
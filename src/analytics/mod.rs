@@ -1,2 +1,23 @@
+pub mod continuity;
+pub mod errors;
+pub mod filter;
+pub mod histogram;
+pub mod moves;
+pub mod occupancy;
+pub mod overlap;
+pub mod pgn_check;
+pub mod profile;
+pub mod sign_check;
+pub mod summary;
 pub mod unique;
+pub mod variants;
 pub mod view;
+
+/// Score Stockfish writes for positions without an evaluation.
+pub const VALUE_NONE_SCORE: i32 = 32002;
+
+/// Whether a stored score is an actual evaluation rather than a placeholder
+/// (0 from unscored builds or VALUE_NONE).
+pub fn carries_eval(score: i32) -> bool {
+    score != 0 && score.abs() != VALUE_NONE_SCORE
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::io::{Read, Seek};
+
+use anyhow::Result;
+use sfbinpack::CompressedTrainingDataEntryReader;
+
+/// How often each move (as UCI) is stored over all entries of a binpack.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MoveCounts {
+    counts: HashMap<String, u64>,
+    entries: u64,
+}
+
+impl MoveCounts {
+    pub fn add(&mut self, uci: &str) {
+        match self.counts.get_mut(uci) {
+            Some(count) => *count += 1,
+            None => {
+                self.counts.insert(uci.to_owned(), 1);
+            }
+        }
+        self.entries += 1;
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Number of different moves seen.
+    pub fn distinct(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// The `n` most frequent moves, ties in UCI order.
+    pub fn top(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut moves: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(uci, &count)| (uci.as_str(), count))
+            .collect();
+        moves.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        moves.truncate(n);
+        moves
+    }
+
+    pub fn print(&self, n: usize) {
+        println!("{:<8} {:>12} {:>8}", "move", "count", "share");
+        for (uci, count) in self.top(n) {
+            let share = count as f64 * 100.0 / self.entries.max(1) as f64;
+            println!("{:<8} {:>12} {:>7.2}%", uci, count, share);
+        }
+    }
+}
+
+/// Tallies the move of every entry.
+pub fn move_counts_from_file<T: Read + Seek>(file: T) -> Result<MoveCounts> {
+    let mut reader = CompressedTrainingDataEntryReader::new(file)?;
+    let mut counts = MoveCounts::default();
+
+    while reader.has_next() {
+        counts.add(&reader.next().mv.as_uci());
+    }
+
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::move_counts_from_file;
+    use crate::binpack::test_util::{game_entries, write_entries};
+
+    #[test]
+    fn most_common_move_comes_first() {
+        // e2e4 opens five games, d2d4 two, and each is answered once by e7e5 or d7d5
+        let mut entries = Vec::new();
+        for _ in 0..5 {
+            entries.extend(game_entries(&[(12, 28, 0), (52, 36, 0)], 0));
+        }
+        for _ in 0..2 {
+            entries.extend(game_entries(&[(11, 27, 0), (51, 35, 0)], 0));
+        }
+        let counts = move_counts_from_file(Cursor::new(write_entries(&entries))).unwrap();
+
+        assert_eq!(counts.entries(), 14);
+        assert_eq!(counts.distinct(), 4);
+        assert_eq!(counts.top(3), [("e2e4", 5), ("e7e5", 5), ("d2d4", 2)]);
+    }
+}
@@ -0,0 +1,123 @@
+use std::io::{Read, Seek};
+
+use anyhow::{Context, Result};
+use sfbinpack::CompressedTrainingDataEntryReader;
+use shakmaty::{fen::Fen, Color, Piece, Square};
+
+/// Column labels of the occupancy table, white pieces first.
+pub const PIECE_LABELS: [char; 12] = ['P', 'N', 'B', 'R', 'Q', 'K', 'p', 'n', 'b', 'r', 'q', 'k'];
+
+/// How often each piece stands on each square, over all entries of a binpack.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Occupancy {
+    /// Square (a1 = 0 .. h8 = 63) -> piece (see [`PIECE_LABELS`]) -> count
+    counts: [[u64; 12]; 64],
+    positions: u64,
+}
+
+impl Default for Occupancy {
+    fn default() -> Self {
+        Self {
+            counts: [[0; 12]; 64],
+            positions: 0,
+        }
+    }
+}
+
+impl Occupancy {
+    pub fn positions(&self) -> u64 {
+        self.positions
+    }
+
+    /// Share of positions in which `piece` stands on `square`.
+    pub fn frequency(&self, square: Square, piece: Piece) -> f64 {
+        if self.positions == 0 {
+            return 0.0;
+        }
+        self.counts[square as usize][piece_index(piece)] as f64 / self.positions as f64
+    }
+
+    pub fn print(&self) {
+        print!("{:<6}", "square");
+        for label in PIECE_LABELS {
+            print!(" {:>7}", label);
+        }
+        println!();
+
+        for square in Square::ALL {
+            print!("{:<6}", square.to_string());
+            for label in PIECE_LABELS {
+                let piece = Piece::from_char(label).expect("valid piece label");
+                print!(" {:>7.4}", self.frequency(square, piece));
+            }
+            println!();
+        }
+    }
+}
+
+fn piece_index(piece: Piece) -> usize {
+    let color = match piece.color {
+        Color::White => 0,
+        Color::Black => 6,
+    };
+    color + usize::from(piece.role) - 1
+}
+
+/// Tallies the piece placement of every entry.
+pub fn occupancy_from_file<T: Read + Seek>(file: T) -> Result<Occupancy> {
+    let mut reader = CompressedTrainingDataEntryReader::new(file)?;
+    let mut occupancy = Occupancy::default();
+
+    while reader.has_next() {
+        let entry = reader.next();
+        let fen = entry
+            .pos
+            .fen()
+            .map_err(|err| anyhow::anyhow!("failed to render FEN for entry: {err:?}"))?;
+        let setup = Fen::from_ascii(fen.as_bytes())
+            .with_context(|| format!("parsing entry FEN: {}", fen))?
+            .into_setup();
+
+        for (square, piece) in setup.board.iter() {
+            occupancy.counts[square as usize][piece_index(piece)] += 1;
+        }
+        occupancy.positions += 1;
+    }
+
+    Ok(occupancy)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use shakmaty::{Color, Piece, Role, Square};
+
+    use super::occupancy_from_file;
+    use crate::binpack::test_util::{game_entries, write_entries};
+
+    #[test]
+    fn start_positions_fill_the_back_ranks() {
+        // three one-move games, so every entry is the start position
+        let mut entries = game_entries(&[(12, 28, 0)], 0);
+        entries.extend(game_entries(&[(11, 27, 0)], 0));
+        entries.extend(game_entries(&[(6, 21, 0)], 0));
+        let occupancy = occupancy_from_file(Cursor::new(write_entries(&entries))).unwrap();
+
+        let white = |role| Piece {
+            color: Color::White,
+            role,
+        };
+        let black = |role| Piece {
+            color: Color::Black,
+            role,
+        };
+
+        assert_eq!(occupancy.positions(), 3);
+        assert_eq!(occupancy.frequency(Square::E1, white(Role::King)), 1.0);
+        assert_eq!(occupancy.frequency(Square::A8, black(Role::Rook)), 1.0);
+        assert_eq!(occupancy.frequency(Square::E2, white(Role::Pawn)), 1.0);
+        assert_eq!(occupancy.frequency(Square::E4, white(Role::Pawn)), 0.0);
+        assert_eq!(occupancy.frequency(Square::E1, black(Role::King)), 0.0);
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+use std::io::{Read, Seek};
+
+use anyhow::Result;
+
+use crate::analytics::unique::{ep_mode, for_each_position_hash};
+use crate::cli::Backend;
+
+/// How many positions of one binpack already occur in another.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Overlap {
+    /// Entries in the second file
+    pub positions: u64,
+    /// Entries in the second file whose position occurs in the first file
+    pub duplicated: u64,
+    /// Distinct positions in the second file
+    pub unique: u64,
+    /// Distinct positions in the second file that occur in the first file
+    pub unique_duplicated: u64,
+}
+
+/// Builds a Zobrist set from `reference` and scans `candidate` against it.
+pub fn overlap_between<A: Read + Seek, B: Read + Seek>(
+    reference: A,
+    candidate: B,
+    backend: Backend,
+    ignore_ep: bool,
+) -> Result<Overlap> {
+    let ep = ep_mode(ignore_ep);
+    let mut known: HashSet<u64> = HashSet::new();
+    for_each_position_hash(reference, None, backend, ep, |hash, _| {
+        known.insert(hash);
+    })?;
+
+    let mut overlap = Overlap::default();
+    let mut seen: HashSet<u64> = HashSet::new();
+    for_each_position_hash(candidate, None, backend, ep, |hash, _| {
+        let duplicated = known.contains(&hash);
+        overlap.positions += 1;
+        overlap.duplicated += u64::from(duplicated);
+
+        if seen.insert(hash) {
+            overlap.unique += 1;
+            overlap.unique_duplicated += u64::from(duplicated);
+        }
+    })?;
+
+    Ok(overlap)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{overlap_between, Overlap};
+    use crate::binpack::test_util::{game_entries, write_entries};
+    use crate::cli::Backend;
+
+    #[test]
+    fn counts_positions_shared_with_reference() {
+        // A: 1. e4 e5 2. Nf3
+        let a = write_entries(&game_entries(&[(12, 28, 0), (52, 36, 0), (6, 21, 0)], 0));
+        // B: 1. e4 d5 2. d4, then 1. d4 in a second game
+        let mut b_entries = game_entries(&[(12, 28, 0), (51, 35, 0), (11, 27, 0)], 0);
+        b_entries.extend(game_entries(&[(11, 27, 0)], 0));
+        let b = write_entries(&b_entries);
+
+        let overlap =
+            overlap_between(Cursor::new(a), Cursor::new(b), Backend::Sfbinpack, false).unwrap();
+
+        // start position (twice) and the position after 1. e4 are in A
+        assert_eq!(
+            overlap,
+            Overlap {
+                positions: 4,
+                duplicated: 3,
+                unique: 3,
+                unique_duplicated: 2,
+            }
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::io::{Cursor, Read, Seek};
+
+use anyhow::Result;
+use sfbinpack::{CompressedTrainingDataEntryReader, TrainingDataEntry};
+
+use crate::binpack::{BinpackBuilder, BuildOptions};
+use crate::cli::Backend;
+
+/// First entry in which a binpack differs from a fresh conversion of its PGN.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub index: u64,
+    /// Field that differs, or `entries` if one side ran out first
+    pub field: &'static str,
+    /// Value from the PGN
+    pub expected: String,
+    /// Value stored in the binpack
+    pub found: String,
+}
+
+/// Outcome of comparing a binpack with its source PGN.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PgnCheck {
+    /// Entries that matched before the first divergence
+    pub matched: u64,
+    pub divergence: Option<Divergence>,
+}
+
+/// Converts `pgn` again with `options` and compares the result entry by entry
+/// with `binpack`; both list the games in PGN order, so entry `i` of one
+/// corresponds to entry `i` of the other.
+pub fn verify_against_pgn<B, P>(binpack: B, pgn: P, options: &BuildOptions) -> Result<PgnCheck>
+where
+    B: Read + Seek,
+    P: Read,
+{
+    let mut builder = BinpackBuilder::new(
+        "<source pgn>",
+        Cursor::new(Vec::new()),
+        Backend::Sfbinpack,
+        options.clone(),
+    );
+    builder.create_binpack_from(pgn)?;
+    let expected = builder.into_inner()?.into_inner();
+
+    let mut expected = CompressedTrainingDataEntryReader::new(Cursor::new(expected))?;
+    let mut found = CompressedTrainingDataEntryReader::new(binpack)?;
+    let mut check = PgnCheck::default();
+
+    loop {
+        let divergence = match (expected.has_next(), found.has_next()) {
+            (false, false) => break,
+            (true, true) => compare(check.matched, &expected.next(), &found.next())?,
+            (more_expected, _) => Some(Divergence {
+                index: check.matched,
+                field: "entries",
+                expected: if more_expected { "more" } else { "end" }.to_string(),
+                found: if more_expected { "end" } else { "more" }.to_string(),
+            }),
+        };
+
+        if divergence.is_some() {
+            check.divergence = divergence;
+            break;
+        }
+        check.matched += 1;
+    }
+
+    Ok(check)
+}
+
+fn compare(
+    index: u64,
+    expected: &TrainingDataEntry,
+    found: &TrainingDataEntry,
+) -> Result<Option<Divergence>> {
+    let fen = |entry: &TrainingDataEntry| {
+        entry
+            .pos
+            .fen()
+            .map_err(|err| anyhow::anyhow!("failed to render FEN for entry: {err:?}"))
+    };
+
+    let fields = [
+        ("position", fen(expected)?, fen(found)?),
+        (
+            "move",
+            expected.mv.as_uci().to_string(),
+            found.mv.as_uci().to_string(),
+        ),
+        ("score", expected.score.to_string(), found.score.to_string()),
+        ("ply", expected.ply.to_string(), found.ply.to_string()),
+        (
+            "result",
+            expected.result.to_string(),
+            found.result.to_string(),
+        ),
+    ];
+
+    Ok(fields
+        .into_iter()
+        .find(|(_, expected, found)| expected != found)
+        .map(|(field, expected, found)| Divergence {
+            index,
+            field,
+            expected,
+            found,
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::verify_against_pgn;
+    use crate::binpack::test_util::{read_entries, write_entries};
+    use crate::binpack::{BinpackBuilder, BuildOptions};
+    use crate::cli::Backend;
+
+    const PGN: &str =
+        "[Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {-0.20/12} 2. Nf3 {+0.25/12} 1-0\n\n\
+                       [Result \"0-1\"]\n\n1. d4 {+0.20/12} d5 {-0.10/12} 0-1\n";
+
+    #[test]
+    fn tampered_binpack_diverges_from_its_pgn() {
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            BuildOptions::default(),
+        );
+        builder.create_binpack_from(PGN.as_bytes()).unwrap();
+        let converted = builder.into_inner().unwrap().into_inner();
+
+        let verify = |binpack: Vec<u8>| {
+            verify_against_pgn(
+                Cursor::new(binpack),
+                PGN.as_bytes(),
+                &BuildOptions::default(),
+            )
+            .unwrap()
+        };
+
+        let check = verify(converted.clone());
+        assert_eq!(check.matched, 5);
+        assert_eq!(check.divergence, None);
+
+        let mut entries = read_entries(converted);
+        entries[3].score += 1;
+        let divergence = verify(write_entries(&entries)).divergence.unwrap();
+        assert_eq!((divergence.index, divergence.field), (3, "score"));
+
+        let divergence = verify(write_entries(&entries[..2])).divergence.unwrap();
+        assert_eq!((divergence.index, divergence.field), (2, "entries"));
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+/// Time spent in each phase of a build. Parsing and compression are summed over
+/// the worker threads, so together they can exceed the wall-clock time.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Reading PGN text and replaying the games
+    pub parsing: Duration,
+    /// Encoding entries into binpack blocks
+    pub compression: Duration,
+    /// Writing the output files
+    pub writing: Duration,
+}
+
+impl Profile {
+    pub fn merge(&mut self, other: &Profile) {
+        self.parsing += other.parsing;
+        self.compression += other.compression;
+        self.writing += other.writing;
+    }
+
+    pub fn print(&self) {
+        let _ = self.write_report(&mut io::stdout().lock());
+    }
+
+    pub fn write_report<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let total = self.parsing + self.compression + self.writing;
+        let share = |time: Duration| {
+            if total.is_zero() {
+                0.0
+            } else {
+                100.0 * time.as_secs_f64() / total.as_secs_f64()
+            }
+        };
+
+        writeln!(out, "Profile (summed over threads):")?;
+        for (phase, time) in [
+            ("parsing", self.parsing),
+            ("compression", self.compression),
+            ("writing", self.writing),
+        ] {
+            writeln!(out, "  {:<12} {:>12.2?} {:>6.1}%", phase, time, share(time))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Profile;
+
+    #[test]
+    fn report_lists_every_phase() {
+        let mut profile = Profile {
+            parsing: Duration::from_millis(300),
+            compression: Duration::from_millis(100),
+            writing: Duration::ZERO,
+        };
+        profile.merge(&Profile {
+            writing: Duration::from_millis(100),
+            ..Profile::default()
+        });
+
+        let mut out = Vec::new();
+        profile.write_report(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let phases: Vec<&str> = out
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().next())
+            .collect();
+
+        assert_eq!(phases, ["parsing", "compression", "writing"]);
+        assert!(out.contains("60.0%"), "{out}");
+        assert!(out.contains("20.0%"), "{out}");
+    }
+}
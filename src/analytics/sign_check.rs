@@ -0,0 +1,85 @@
+use std::io::{Read, Seek};
+
+use anyhow::Result;
+use sfbinpack::CompressedTrainingDataEntryReader;
+
+use crate::analytics::carries_eval;
+
+/// Scores of at least this magnitude are expected to agree with the game result.
+pub const DECISIVE_SCORE: i32 = 500;
+
+/// How often decisive scores point the same way as the game result.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SignCheck {
+    /// Entries of decided games with a score of at least [`DECISIVE_SCORE`]
+    pub decisive: u64,
+    /// Decisive entries whose score favours the eventual winner
+    pub agree: u64,
+    /// Decisive entries whose score favours the eventual loser
+    pub disagree: u64,
+}
+
+impl SignCheck {
+    pub fn disagreement_rate(&self) -> f64 {
+        if self.decisive == 0 {
+            0.0
+        } else {
+            self.disagree as f64 / self.decisive as f64
+        }
+    }
+
+    /// Engines misjudge single positions, but when most decisive scores favour
+    /// the loser the scores were most likely stored from the wrong side.
+    pub fn looks_flipped(&self) -> bool {
+        self.disagree > self.agree
+    }
+}
+
+/// Compares the sign of decisive scores with the result stored in each entry.
+pub fn check_score_signs<T: Read + Seek>(file: T) -> Result<SignCheck> {
+    let mut reader = CompressedTrainingDataEntryReader::new(file)?;
+    let mut check = SignCheck::default();
+
+    while reader.has_next() {
+        let entry = reader.next();
+        let score = i32::from(entry.score);
+
+        if entry.result == 0 || !carries_eval(score) || score.abs() < DECISIVE_SCORE {
+            continue;
+        }
+
+        check.decisive += 1;
+        if score.signum() == i32::from(entry.result) {
+            check.agree += 1;
+        } else {
+            check.disagree += 1;
+        }
+    }
+
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::check_score_signs;
+    use crate::binpack::test_util::{game_entries, write_entries};
+
+    #[test]
+    fn flags_sign_flipped_scores() {
+        // white wins; scores are from the side to move's point of view
+        let moves = [(12, 28, 600), (52, 36, -650), (6, 21, 700), (57, 42, 20)];
+        let flipped: Vec<_> = moves.iter().map(|&(f, t, s)| (f, t, -s)).collect();
+
+        let good = check_score_signs(Cursor::new(write_entries(&game_entries(&moves, 1)))).unwrap();
+        assert_eq!((good.decisive, good.agree, good.disagree), (3, 3, 0));
+        assert!(!good.looks_flipped());
+
+        let bad =
+            check_score_signs(Cursor::new(write_entries(&game_entries(&flipped, 1)))).unwrap();
+        assert_eq!(bad.disagree, 3);
+        assert!(bad.looks_flipped());
+        assert_eq!(bad.disagreement_rate(), 1.0);
+    }
+}
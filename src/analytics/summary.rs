@@ -0,0 +1,89 @@
+use std::fmt::{Display, Write as _};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Flat, machine readable summary of an analytics run, rendered as a single JSON object.
+pub struct Summary {
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Summary {
+    pub fn new(command: &str) -> Self {
+        Self {
+            fields: vec![("command", quote(command))],
+        }
+    }
+
+    pub fn number<N: Display>(mut self, key: &'static str, value: N) -> Self {
+        self.fields.push((key, value.to_string()));
+        self
+    }
+
+    pub fn string(mut self, key: &'static str, value: &str) -> Self {
+        self.fields.push((key, quote(value)));
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{");
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            let _ = write!(out, "{}: {}", quote(key), value);
+        }
+        out.push('}');
+        out
+    }
+
+    /// Writes the summary to `path`, or prints it to stdout when no path is given.
+    pub fn emit(&self, path: Option<&Path>) -> Result<()> {
+        match path {
+            Some(path) => std::fs::write(path, self.to_json() + "\n")
+                .with_context(|| format!("writing summary to {}", path.display())),
+            None => {
+                println!("{}", self.to_json());
+                Ok(())
+            }
+        }
+    }
+}
+
+pub(crate) fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Summary;
+
+    #[test]
+    fn renders_unique_summary() {
+        let summary = Summary::new("unique")
+            .string("file", "data\\train \"a\".binpack")
+            .number("unique", 12345u64)
+            .number("elapsed_ms", 678u128);
+
+        assert_eq!(
+            summary.to_json(),
+            r#"{"command": "unique", "file": "data\\train \"a\".binpack", "unique": 12345, "elapsed_ms": 678}"#
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashSet;
-use std::io::{BufReader, ErrorKind, Read, Seek};
+use std::io::{BufReader, ErrorKind, Read, Seek, Write};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use sfbinpack::CompressedTrainingDataEntryReader;
 use shakmaty::{
     fen::Fen, uci::UciMove, zobrist::Zobrist64, zobrist::ZobristHash, CastlingMode, Chess,
@@ -9,23 +9,117 @@ use shakmaty::{
 };
 use viriformat::dataformat::Game as ViriGame;
 
+use crate::analytics::carries_eval;
 use crate::cli::Backend;
 
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UniqueOptions {
+    /// Only hash entries that carry an eval
+    pub eval_only: bool,
+    /// Treat positions that only differ by an uncapturable en passant square as equal
+    pub ignore_ep: bool,
+    /// Only count entries from this index on; the limit counts entries after them
+    pub skip: usize,
+}
+
+/// Which en passant squares take part in the hash.
+pub fn ep_mode(ignore_ep: bool) -> EnPassantMode {
+    if ignore_ep {
+        EnPassantMode::Legal
+    } else {
+        EnPassantMode::Always
+    }
+}
+
+/// Counts distinct Zobrist hashes.
 pub fn unique_positions_from_file<T: Read + Seek>(
     file: T,
     limit: Option<usize>,
     backend: Backend,
+    options: &UniqueOptions,
+) -> Result<u64> {
+    let mut unique: HashSet<u64> = HashSet::new();
+    let mut index = 0usize;
+
+    // skipped entries are still replayed, later positions depend on them
+    for_each_position_hash(
+        file,
+        limit.map(|limit| limit + options.skip),
+        backend,
+        ep_mode(options.ignore_ep),
+        |hash, score| {
+            if index >= options.skip && (!options.eval_only || carries_eval(score)) {
+                unique.insert(hash);
+            }
+            index += 1;
+        },
+    )?;
+
+    Ok(unique.len() as u64)
+}
+
+/// Writes the Zobrist hash of every position as 16 hex digits per line, in entry
+/// order, so that other tools can build their own dedup index. Like
+/// [`unique_positions_from_file`], the first `skip` entries are left out and the
+/// limit counts entries after them. Returns the number of hashes written.
+pub fn dump_position_hashes<T: Read + Seek, W: Write>(
+    file: T,
+    limit: Option<usize>,
+    skip: usize,
+    backend: Backend,
+    ep: EnPassantMode,
+    mut out: W,
 ) -> Result<u64> {
+    let mut count = 0u64;
+    let mut index = 0usize;
+    let mut result = Ok(());
+
+    for_each_position_hash(
+        file,
+        limit.map(|limit| limit + skip),
+        backend,
+        ep,
+        |hash, _| {
+            if index >= skip && result.is_ok() {
+                result = writeln!(out, "{:016x}", hash);
+                count += 1;
+            }
+            index += 1;
+        },
+    )?;
+    result?;
+    out.flush()?;
+
+    Ok(count)
+}
+
+/// Replays the games of a binpack and calls `f` with the Zobrist hash and the
+/// stored score of every position, stopping after `limit` entries.
+pub fn for_each_position_hash<T, F>(
+    file: T,
+    limit: Option<usize>,
+    backend: Backend,
+    ep: EnPassantMode,
+    f: F,
+) -> Result<()>
+where
+    T: Read + Seek,
+    F: FnMut(u64, i32),
+{
     match backend {
-        Backend::Sfbinpack => unique_sf(file, limit),
-        Backend::Viriformat => unique_viriformat(file, limit),
+        Backend::Sfbinpack => hashes_sf(file, limit, ep, f),
+        Backend::Viriformat => hashes_viriformat(file, limit, ep, f),
     }
 }
 
-fn unique_sf<T: Read + Seek>(file: T, limit: Option<usize>) -> Result<u64> {
+fn hashes_sf<T: Read + Seek, F: FnMut(u64, i32)>(
+    file: T,
+    limit: Option<usize>,
+    ep: EnPassantMode,
+    mut f: F,
+) -> Result<()> {
     let mut reader = CompressedTrainingDataEntryReader::new(file)?;
     let mut position = Chess::default();
-    let mut unique: HashSet<u64> = HashSet::new();
     let mut new_game = true;
     let mut count: usize = 0;
 
@@ -33,20 +127,28 @@ fn unique_sf<T: Read + Seek>(file: T, limit: Option<usize>) -> Result<u64> {
         let entry = reader.next();
 
         if new_game {
-            let fen = entry.pos.fen().expect("entry has FEN");
-            let fen = Fen::from_ascii(fen.as_bytes()).expect("invalid FEN");
-            position = fen
-                .into_position(CastlingMode::Standard)
-                .expect("invalid position");
+            let fen = entry
+                .pos
+                .fen()
+                .map_err(|err| anyhow::anyhow!("failed to render FEN for entry: {err:?}"))?;
+            // Chess960 games castle as king onto rook, which covers standard chess too
+            position = Fen::from_ascii(fen.as_bytes())
+                .with_context(|| format!("parsing entry FEN: {}", fen))?
+                .into_position(CastlingMode::Chess960)
+                .with_context(|| format!("creating position from FEN: {}", fen))?;
             new_game = false;
         }
 
-        let hash = position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal);
-        unique.insert(hash.0);
+        let hash = position.zobrist_hash::<Zobrist64>(ep);
+        f(hash.0, i32::from(entry.score));
 
         if reader.has_next() && reader.is_next_entry_continuation() {
-            let uci: UciMove = entry.mv.as_uci().parse().expect("invalid UCI move");
-            let m = uci.to_move(&position).expect("illegal move in stream");
+            let uci = entry.mv.as_uci();
+            let m = uci
+                .parse::<UciMove>()
+                .ok()
+                .and_then(|m| m.to_move(&position).ok())
+                .with_context(|| format!("illegal move {} in stream", uci))?;
             position.play_unchecked(m);
         } else {
             new_game = true;
@@ -60,12 +162,16 @@ fn unique_sf<T: Read + Seek>(file: T, limit: Option<usize>) -> Result<u64> {
         }
     }
 
-    Ok(unique.len() as u64)
+    Ok(())
 }
 
-fn unique_viriformat<T: Read + Seek>(file: T, limit: Option<usize>) -> Result<u64> {
+fn hashes_viriformat<T: Read + Seek, F: FnMut(u64, i32)>(
+    file: T,
+    limit: Option<usize>,
+    ep: EnPassantMode,
+    mut f: F,
+) -> Result<()> {
     let mut reader = BufReader::new(file);
-    let mut unique: HashSet<u64> = HashSet::new();
     let mut processed = 0usize;
 
     loop {
@@ -81,14 +187,14 @@ fn unique_viriformat<T: Read + Seek>(file: T, limit: Option<usize>) -> Result<u6
                         anyhow::anyhow!("unable to convert FEN to position: {}", fen_str)
                     })?;
 
-                for (mv, _) in &game.moves {
-                    let hash = position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal);
-                    unique.insert(hash.0);
+                for (mv, eval) in &game.moves {
+                    let hash = position.zobrist_hash::<Zobrist64>(ep);
+                    f(hash.0, i32::from(eval.get()));
 
                     processed += 1;
                     if let Some(limit) = limit {
                         if processed >= limit {
-                            return Ok(unique.len() as u64);
+                            return Ok(());
                         }
                     }
 
@@ -103,5 +209,222 @@ fn unique_viriformat<T: Read + Seek>(file: T, limit: Option<usize>) -> Result<u6
         }
     }
 
-    Ok(unique.len() as u64)
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use shakmaty::{
+        fen::Fen, uci::UciMove, zobrist::Zobrist64, zobrist::ZobristHash, CastlingMode, Chess,
+        EnPassantMode, Position,
+    };
+
+    use super::{dump_position_hashes, unique_positions_from_file, UniqueOptions};
+    use crate::analytics::VALUE_NONE_SCORE;
+    use crate::binpack::builder::{BinpackBuilder, BuildOptions};
+    use crate::binpack::test_util::{game_entries, write_entries};
+    use crate::cli::Backend;
+
+    #[test]
+    fn dumped_hashes_match_replayed_positions() {
+        // 1. e4 e5 2. Nf3, then 1. d4 in a second game
+        let mut entries = game_entries(&[(12, 28, 0), (52, 36, 0), (6, 21, 0)], 0);
+        entries.extend(game_entries(&[(11, 27, 0)], 0));
+
+        let mut dump = Vec::new();
+        let count = dump_position_hashes(
+            Cursor::new(write_entries(&entries)),
+            None,
+            0,
+            Backend::Sfbinpack,
+            EnPassantMode::Always,
+            &mut dump,
+        )
+        .unwrap();
+
+        let mut expected = Vec::new();
+        for game in [&["e2e4", "e7e5", "g1f3"][..], &["d2d4"]] {
+            let mut position = Chess::default();
+            for uci in game {
+                let hash = position.zobrist_hash::<Zobrist64>(EnPassantMode::Always);
+                expected.push(format!("{:016x}", hash.0));
+                let mv = uci.parse::<UciMove>().unwrap().to_move(&position).unwrap();
+                position.play_unchecked(mv);
+            }
+        }
+
+        assert_eq!(count, 4);
+        assert_eq!(
+            String::from_utf8(dump).unwrap().lines().collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn chess960_games_are_replayed_with_their_castles() {
+        let fen = "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w HAha - 0 1";
+        let pgn = format!(
+            "[Variant \"Chess960\"]\n[FEN \"{fen}\"]\n[Result \"*\"]\n\n\
+             1. O-O {{+0.10/10}} O-O-O {{-0.10/10}} 2. d4 {{+0.20/10}} *\n"
+        );
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            BuildOptions::default(),
+        );
+        builder.create_binpack_from(pgn.as_bytes()).unwrap();
+        let buffer = builder.into_inner().unwrap().into_inner();
+
+        let mut dump = Vec::new();
+        let count = dump_position_hashes(
+            Cursor::new(buffer),
+            None,
+            0,
+            Backend::Sfbinpack,
+            EnPassantMode::Always,
+            &mut dump,
+        )
+        .unwrap();
+
+        let mut position: Chess = Fen::from_ascii(fen.as_bytes())
+            .unwrap()
+            .into_position(CastlingMode::Chess960)
+            .unwrap();
+        let mut expected = Vec::new();
+        for uci in ["e1h1", "e8a8", "d2d4"] {
+            let hash = position.zobrist_hash::<Zobrist64>(EnPassantMode::Always);
+            expected.push(format!("{:016x}", hash.0));
+            let mv = uci.parse::<UciMove>().unwrap().to_move(&position).unwrap();
+            position.play_unchecked(mv);
+        }
+
+        assert_eq!(count, 3);
+        assert_eq!(
+            String::from_utf8(dump).unwrap().lines().collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn eval_only_skips_unscored_entries() {
+        // 1. e4 e5 2. Nf3 scored as: unscored, +35, VALUE_NONE
+        let entries = game_entries(
+            &[(12, 28, 0), (52, 36, 35), (6, 21, VALUE_NONE_SCORE as i16)],
+            0,
+        );
+        let buffer = write_entries(&entries);
+
+        let count = |options| {
+            unique_positions_from_file(
+                Cursor::new(buffer.clone()),
+                None,
+                Backend::Sfbinpack,
+                &options,
+            )
+            .unwrap()
+        };
+        let all = count(UniqueOptions::default());
+        let scored = count(UniqueOptions {
+            eval_only: true,
+            ..UniqueOptions::default()
+        });
+
+        assert_eq!(all, 3);
+        assert_eq!(scored, 1);
+    }
+
+    #[test]
+    fn resume_counts_from_the_requested_index() {
+        // 1. e4 e5 2. Nf3 Nc6, then 1. e4 again in a second game
+        let mut entries = game_entries(&[(12, 28, 0), (52, 36, 0), (6, 21, 0), (57, 42, 0)], 0);
+        entries.extend(game_entries(&[(12, 28, 0)], 0));
+        let buffer = write_entries(&entries);
+
+        let count = |skip, limit| {
+            let options = UniqueOptions {
+                skip,
+                ..UniqueOptions::default()
+            };
+            unique_positions_from_file(
+                Cursor::new(buffer.clone()),
+                limit,
+                Backend::Sfbinpack,
+                &options,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(count(0, None), 4);
+        // entries 2.. are after 1. e4 e5, after 2. Nf3 and the start position again
+        assert_eq!(count(2, None), 3);
+        assert_eq!(count(2, Some(2)), 2);
+    }
+
+    #[test]
+    fn dump_resumes_from_the_requested_index() {
+        // 1. e4 e5 2. Nf3
+        let buffer = write_entries(&game_entries(&[(12, 28, 0), (52, 36, 0), (6, 21, 0)], 0));
+        let dump = |limit, skip| {
+            let mut out = Vec::new();
+            let count = dump_position_hashes(
+                Cursor::new(buffer.clone()),
+                limit,
+                skip,
+                Backend::Sfbinpack,
+                EnPassantMode::Always,
+                &mut out,
+            )
+            .unwrap();
+            let lines: Vec<String> = String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect();
+            assert_eq!(count, lines.len() as u64);
+            lines
+        };
+
+        let all = dump(None, 0);
+        assert_eq!(all.len(), 3);
+        assert_eq!(dump(None, 1), all[1..]);
+        assert_eq!(dump(Some(1), 1), all[1..2]);
+    }
+
+    #[test]
+    fn ignore_ep_merges_phantom_en_passant_positions() {
+        // 1. e4 Nf6 2. Nf3 Ng8 3. Ng1 Nc6: the position after 1. e4 (en passant square e3,
+        // no capture possible) repeats after 3. Ng1 without an en passant square
+        let entries = game_entries(
+            &[
+                (12, 28, 0),
+                (62, 45, 0),
+                (6, 21, 0),
+                (45, 62, 0),
+                (21, 6, 0),
+                (57, 42, 0),
+            ],
+            0,
+        );
+        let buffer = write_entries(&entries);
+
+        let count = |ignore_ep| {
+            let options = UniqueOptions {
+                ignore_ep,
+                ..UniqueOptions::default()
+            };
+            unique_positions_from_file(
+                Cursor::new(buffer.clone()),
+                None,
+                Backend::Sfbinpack,
+                &options,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(count(false), 6);
+        assert_eq!(count(true), 5);
+    }
 }
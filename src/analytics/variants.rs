@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use pgn_reader::{RawTag, Reader, Visitor};
+use rayon::prelude::*;
+
+use crate::io::{collect_pgn_files, open_pgn};
+
+/// Label used for games without a `Variant` tag.
+pub const NO_VARIANT: &str = "(none)";
+
+/// Tallies the `Variant` tag values of every game below `root`.
+pub fn scan_variants(root: &Path) -> Result<BTreeMap<String, u64>> {
+    let files = collect_pgn_files(root, None)?;
+
+    if files.is_empty() {
+        anyhow::bail!("No PGN files found in {}", root.display());
+    }
+
+    files
+        .par_iter()
+        .map(|file| {
+            let input = open_pgn(file)?;
+            count_variants(input).with_context(|| format!("scanning {}", file.display()))
+        })
+        .try_reduce(BTreeMap::new, |mut acc, counts| {
+            for (variant, count) in counts {
+                *acc.entry(variant).or_insert(0) += count;
+            }
+            Ok(acc)
+        })
+}
+
+pub fn count_variants<R: Read>(input: R) -> Result<BTreeMap<String, u64>> {
+    let mut reader = Reader::new(BufReader::new(input));
+    let mut visitor = VariantVisitor;
+    let mut counts = BTreeMap::new();
+
+    for variant in reader.read_games(&mut visitor) {
+        let variant = variant?.unwrap_or_else(|| NO_VARIANT.to_string());
+        *counts.entry(variant).or_insert(0) += 1;
+    }
+
+    Ok(counts)
+}
+
+/// Counts the games of a PGN without parsing their moves.
+pub fn count_games<R: Read>(input: R) -> Result<u64> {
+    Ok(count_variants(input)?.values().sum())
+}
+
+// Only looks at the tags, the movetext is skipped without being parsed.
+struct VariantVisitor;
+
+impl Visitor for VariantVisitor {
+    type Tags = Option<String>;
+    type Movetext = ();
+    type Output = Option<String>;
+
+    fn begin_tags(&mut self) -> ControlFlow<Self::Output, Self::Tags> {
+        ControlFlow::Continue(None)
+    }
+
+    fn tag(
+        &mut self,
+        tags: &mut Self::Tags,
+        name: &[u8],
+        value: RawTag<'_>,
+    ) -> ControlFlow<Self::Output> {
+        if name == b"Variant" {
+            *tags = Some(String::from_utf8_lossy(value.0).into_owned());
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn begin_movetext(&mut self, tags: Self::Tags) -> ControlFlow<Self::Output, Self::Movetext> {
+        ControlFlow::Break(tags)
+    }
+
+    fn end_game(&mut self, _movetext: Self::Movetext) -> Self::Output {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count_variants, NO_VARIANT};
+
+    #[test]
+    fn counts_variant_tags() {
+        let pgn = r#"[Event "a"]
+[Variant "Chess960"]
+
+1. e4 e5 1-0
+
+[Event "b"]
+
+1. d4 d5 0-1
+
+[Event "c"]
+[Variant "Chess960"]
+
+1. c4 1/2-1/2
+
+[Event "d"]
+[Variant "Atomic"]
+
+1. Nf3 *
+"#;
+
+        let counts = count_variants(pgn.as_bytes()).expect("valid pgn");
+
+        assert_eq!(counts.get("Chess960"), Some(&2));
+        assert_eq!(counts.get("Atomic"), Some(&1));
+        assert_eq!(counts.get(NO_VARIANT), Some(&1));
+        assert_eq!(counts.len(), 3);
+    }
+}
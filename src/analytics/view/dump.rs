@@ -1,28 +1,39 @@
-use std::io::{Read, Seek};
+use std::io::{self, Read, Seek, Write};
 
 use anyhow::Result;
 
-use super::{render_board, ViewSession};
+use super::ViewSession;
 
 pub(super) fn dump_frames<T: Read + Seek>(session: &mut ViewSession<T>) -> Result<()> {
+    write_frames(session, &mut io::stdout().lock())
+}
+
+pub(super) fn write_frames<T: Read + Seek, W: Write>(
+    session: &mut ViewSession<T>,
+    out: &mut W,
+) -> Result<()> {
     let mut index = 0usize;
 
     while session.ensure_loaded(index)? {
         let frame = &session.frames[index];
-        println!("position {}", index + 1);
-        println!("game {} move {}", frame.game_index, frame.position_in_game);
-        println!("fen {}", frame.fen);
-        println!("uci move {}", frame.uci_move);
-        println!("score {}", frame.score);
-        println!("ply {}", frame.ply);
-        println!("result {}", frame.result);
-        println!();
+        writeln!(out, "[{}] position {}", frame.entry_index, index + 1)?;
+        writeln!(
+            out,
+            "game {} move {}",
+            frame.game_index, frame.position_in_game
+        )?;
+        writeln!(out, "fen {}", frame.fen)?;
+        writeln!(out, "uci move {}", frame.uci_move)?;
+        writeln!(out, "score {}", frame.score)?;
+        writeln!(out, "ply {}", frame.ply)?;
+        writeln!(out, "result {}", frame.result)?;
+        writeln!(out)?;
 
         index += 1;
     }
 
     if index == 0 {
-        println!("No positions found.");
+        writeln!(out, "No positions found.")?;
     }
 
     Ok(())
@@ -6,8 +6,10 @@ use std::io::{self, BufReader, ErrorKind, IsTerminal, Read, Seek};
 
 use anyhow::{anyhow, Context, Result};
 use sfbinpack::CompressedTrainingDataEntryReader;
-use viriformat::dataformat::Game as ViriGame;
+use viriformat::dataformat::{Game as ViriGame, WDL};
 
+use crate::analytics::filter::{EntryFields, EntryFilter};
+use crate::analytics::{carries_eval, VALUE_NONE_SCORE};
 use crate::cli::Backend;
 
 pub(super) const LARGE_SQUARE_WIDTH: usize = 7;
@@ -17,37 +19,99 @@ const LIGHT_SQUARE_BG: &str = "\x1b[48;5;250m";
 const DARK_SQUARE_BG: &str = "\x1b[48;5;60m";
 const LIGHT_PIECE_FG: &str = "\x1b[1;38;5;255m";
 const DARK_PIECE_FG: &str = "\x1b[1;38;5;16m";
-const VALUE_NONE_SCORE: i32 = 32002;
 
 #[derive(Clone, Debug)]
 pub(super) struct ViewFrame {
+    // index of the entry in the file, counted from 0 before any filtering
+    pub(super) entry_index: usize,
     pub(super) game_index: usize,
     pub(super) position_in_game: usize,
     pub(super) fen: String,
     pub(super) uci_move: String,
     pub(super) score: String,
+    pub(super) raw_score: i32,
     pub(super) ply: u32,
     pub(super) result: String,
+    // result from the side to move's point of view
+    pub(super) raw_result: i32,
 }
 
-pub fn view_entries<T: Read + Seek>(file: T, limit: Option<usize>, backend: Backend) -> Result<()> {
-    let mut session = ViewSession::new(file, limit, backend)?;
+impl ViewFrame {
+    fn filter_fields(&self) -> EntryFields {
+        let pieces = self
+            .fen
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .chars()
+            .filter(|c| c.is_ascii_alphabetic())
+            .count();
+
+        EntryFields {
+            score: i64::from(self.raw_score),
+            result: i64::from(self.raw_result),
+            ply: i64::from(self.ply),
+            pieces: pieces as i64,
+            cont: i64::from(self.position_in_game > 1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ViewOptions {
+    /// Only show entries that carry an eval (score neither 0 nor VALUE_NONE)
+    pub eval_only: bool,
+    /// Only show entries matching this expression
+    pub filter: Option<EntryFilter>,
+    /// Start at this entry index
+    pub skip: usize,
+}
+
+impl ViewOptions {
+    fn accepts(&self, frame: &ViewFrame) -> bool {
+        if frame.entry_index < self.skip {
+            return false;
+        }
+
+        if self.eval_only && !carries_eval(frame.raw_score) {
+            return false;
+        }
+
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(&frame.filter_fields()))
+    }
+}
+
+/// Shows the entries of a binpack and returns how many positions were loaded.
+pub fn view_entries<T: Read + Seek>(
+    file: T,
+    limit: Option<usize>,
+    backend: Backend,
+    options: &ViewOptions,
+) -> Result<usize> {
+    let mut session = ViewSession::new(file, limit, backend, options.clone())?;
 
     if io::stdin().is_terminal() && io::stdout().is_terminal() {
-        tui::browse_frames(&mut session)
+        tui::browse_frames(&mut session)?;
     } else {
-        dump::dump_frames(&mut session)
+        dump::dump_frames(&mut session)?;
     }
+
+    Ok(session.frames.len())
 }
 
 pub(super) struct ViewSession<T: Read + Seek> {
     source: ViewSource<T>,
+    options: ViewOptions,
     pub(super) frames: Vec<ViewFrame>,
     pub(super) eof: bool,
 }
 
 impl<T: Read + Seek> ViewSession<T> {
-    fn new(file: T, limit: Option<usize>, backend: Backend) -> Result<Self> {
+    fn new(file: T, limit: Option<usize>, backend: Backend, options: ViewOptions) -> Result<Self> {
+        // the limit counts entries after the skipped ones
+        let limit = limit.map(|limit| limit + options.skip);
         let source = match backend {
             Backend::Sfbinpack => ViewSource::Sf(SfSource::new(file, limit)?),
             Backend::Viriformat => ViewSource::Viriformat(ViriformatSource::new(file, limit)),
@@ -55,6 +119,7 @@ impl<T: Read + Seek> ViewSession<T> {
 
         Ok(Self {
             source,
+            options,
             frames: Vec::new(),
             eof: false,
         })
@@ -63,7 +128,8 @@ impl<T: Read + Seek> ViewSession<T> {
     pub(super) fn ensure_loaded(&mut self, index: usize) -> Result<bool> {
         while self.frames.len() <= index && !self.eof {
             match self.source.next_frame()? {
-                Some(frame) => self.frames.push(frame),
+                Some(frame) if self.options.accepts(&frame) => self.frames.push(frame),
+                Some(_) => {}
                 None => self.eof = true,
             }
         }
@@ -165,6 +231,7 @@ impl<T: Read + Seek> SfSource<T> {
         }
 
         let frame = ViewFrame {
+            entry_index: self.emitted,
             game_index: self.game_index,
             position_in_game: self.position_in_game,
             fen: entry
@@ -173,8 +240,10 @@ impl<T: Read + Seek> SfSource<T> {
                 .map_err(|err| anyhow!("failed to render FEN for entry: {err:?}"))?,
             uci_move: entry.mv.as_uci().to_string(),
             score: format_score(i32::from(entry.score)),
+            raw_score: i32::from(entry.score),
             ply: entry.ply.into(),
             result: format!("{:?}", entry.result),
+            raw_result: i32::from(entry.result),
         };
 
         self.emitted += 1;
@@ -214,7 +283,8 @@ impl<T: Read + Seek> ViriformatSource<T> {
         }
 
         loop {
-            if let Some(frame) = self.pending_frames.pop_front() {
+            if let Some(mut frame) = self.pending_frames.pop_front() {
+                frame.entry_index = self.emitted;
                 self.emitted += 1;
                 return Ok(Some(frame));
             }
@@ -236,12 +306,27 @@ fn build_viriformat_frames(game: ViriGame, game_index: usize) -> Result<VecDeque
     let mut frames = VecDeque::with_capacity(game.moves.len());
 
     for (position_in_game, (mv, eval)) in game.moves.iter().enumerate() {
+        let fen = board.to_string();
+        let white_result = match game.outcome() {
+            WDL::Win => 1,
+            WDL::Loss => -1,
+            WDL::Draw => 0,
+        };
+        let raw_result = if side_to_move(&fen) == "Black" {
+            -white_result
+        } else {
+            white_result
+        };
+
         frames.push_back(ViewFrame {
+            entry_index: 0, // assigned when the frame is emitted
             game_index,
             position_in_game: position_in_game + 1,
-            fen: board.to_string(),
+            raw_result,
+            fen,
             uci_move: mv.display(false).to_string(),
             score: format_score(i32::from(eval.get())),
+            raw_score: i32::from(eval.get()),
             ply: board.ply() as u32,
             result: format!("{:?}", game.outcome()),
         });
@@ -474,7 +559,12 @@ fn unicode_piece(piece: char) -> char {
 
 #[cfg(test)]
 mod tests {
-    use super::render_board;
+    use std::io::Cursor;
+
+    use super::{dump, render_board, ViewOptions, ViewSession};
+    use crate::binpack::test_util::{game_entries, write_entries};
+    use crate::cli::Backend;
+    use crate::io::spool_to_temp;
 
     #[test]
     fn renders_start_position_board() {
@@ -490,4 +580,98 @@ mod tests {
         assert_eq!(lines[7], "1  ♖  ♘  ♗  ♕  ♔  ♗  ♘  ♖ ");
         assert!(board.contains("  a  b  c  d  e  f  g  h"));
     }
+
+    #[test]
+    fn dump_indices_start_at_skip_offset() {
+        let mut entries = game_entries(&[(12, 28, 10), (52, 36, 20), (6, 21, 30)], 0);
+        entries.extend(game_entries(&[(11, 27, 40), (51, 35, 50)], 0));
+        let options = ViewOptions {
+            skip: 2,
+            ..ViewOptions::default()
+        };
+        let mut session = ViewSession::new(
+            Cursor::new(write_entries(&entries)),
+            Some(2),
+            Backend::Sfbinpack,
+            options,
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        dump::write_frames(&mut session, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let indices: Vec<&str> = out
+            .lines()
+            .filter_map(|line| line.strip_prefix('['))
+            .filter_map(|line| line.split(']').next())
+            .collect();
+
+        assert_eq!(indices, ["2", "3"]);
+    }
+
+    #[test]
+    fn filter_selects_entries_of_a_binpack() {
+        // 1. e4 e5 2. Nf3 Nc6 3. Nxe5 d6, then 1. d4 d5
+        let mut entries = game_entries(
+            &[
+                (12, 28, 10),
+                (52, 36, 20),
+                (6, 21, 30),
+                (57, 42, -25),
+                (21, 36, 90),
+                (51, 43, -80),
+            ],
+            1,
+        );
+        entries.extend(game_entries(&[(11, 27, 40), (51, 35, -40)], 0));
+        let bytes = write_entries(&entries);
+
+        let indices = |filter: &str| {
+            let options = ViewOptions {
+                filter: Some(filter.parse().unwrap()),
+                ..ViewOptions::default()
+            };
+            let mut session = ViewSession::new(
+                Cursor::new(bytes.clone()),
+                None,
+                Backend::Sfbinpack,
+                options,
+            )
+            .unwrap();
+            let mut out = Vec::new();
+            dump::write_frames(&mut session, &mut out).unwrap();
+            String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .filter_map(|line| line.strip_prefix('['))
+                .filter_map(|line| line.split(']').next())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        // only the position after 3. Nxe5 has lost a piece; game starts are not continuations
+        assert_eq!(indices("pieces<32 || cont==0"), ["0", "5", "6"]);
+        assert_eq!(indices("cont==1 && score>=30 && ply<6"), ["2", "4"]);
+        assert_eq!(indices("result==-1 && (score<0 || ply==5)"), ["3", "5"]);
+    }
+
+    #[test]
+    fn piped_binpack_is_spooled_for_viewing() {
+        let entries = game_entries(&[(12, 28, 10), (52, 36, 20)], 1);
+        let bytes = write_entries(&entries);
+
+        // a byte slice stands in for stdin, which cannot seek
+        let file = spool_to_temp(&bytes[..]).unwrap();
+        let mut session =
+            ViewSession::new(file, None, Backend::Sfbinpack, ViewOptions::default()).unwrap();
+
+        let mut out = Vec::new();
+        dump::write_frames(&mut session, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"));
+        assert!(out.contains("uci move e7e5"));
+        assert!(out.contains("score 20"));
+        assert!(!out.contains("No positions found."));
+    }
 }
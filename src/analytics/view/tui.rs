@@ -176,6 +176,7 @@ fn render_frame(
     let left_panel: Vec<String> = Vec::new();
     let mut right_panel = vec![
         format!("Position {}", index + 1),
+        format!("Entry:    {}", frame.entry_index),
         format!("Total:    {}", total_display),
         format!("Game:     {}", frame.game_index),
         format!("Move #:    {}", frame.position_in_game),
@@ -207,7 +208,7 @@ fn render_frame(
     }
 
     if right_panel_width > 0 {
-        let fen_insert_at = 11;
+        let fen_insert_at = 12;
         let fen_lines = wrap_text(&frame.fen, right_panel_width);
         right_panel.splice(fen_insert_at..fen_insert_at, fen_lines);
     }
@@ -1,19 +1,33 @@
 use std::{
-    fs::File,
-    io::{BufReader, Seek, Write},
-    ops::ControlFlow,
-    path::PathBuf,
+    collections::HashMap,
+    io::{BufReader, Read, Seek, Write},
+    ops::{ControlFlow, Range},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, Context, Result};
-use flate2::read::MultiGzDecoder;
 
 use sfbinpack::{
-    chess::{color::Color as SfColor, position::Position as SfPosition},
+    chess::{
+        castling_rights::CastlingRights as SfCastlingRights, color::Color as SfColor,
+        position::Position as SfPosition, r#move::MoveType as SfMoveType,
+    },
     CompressedTrainingDataEntryWriter, TrainingDataEntry,
 };
 
-use shakmaty::{fen::Fen, Chess, EnPassantMode, Move, Position};
+use shakmaty::{
+    fen::Fen,
+    san::San,
+    uci::UciMove,
+    zobrist::{Zobrist64, ZobristHash},
+    CastlingMode, Chess, Color, EnPassantMode, Move, Position, PositionError, Square,
+};
 
 use pgn_reader::{RawComment, RawTag, Reader, SanPlus, Skip, Visitor};
 
@@ -25,71 +39,324 @@ use viriformat::{
     dataformat::Game as ViriGame,
 };
 
-use crate::cli::Backend;
+use crate::analytics::histogram::EvalHistogram;
+use crate::analytics::profile::Profile;
+use crate::binpack::transform::scale_score;
+use crate::cli::{Backend, FenNormalization, NullMovePolicy, PgnEncoding, ScoreFrom};
+use crate::util::rng::{reservoir_sample, SplitMix64};
 use crate::util::util;
 use crate::wdl::wdl;
 
+/// Knobs that change how games are turned into entries.
+#[derive(Clone, Debug, Default)]
+pub struct BuildOptions {
+    /// Replay every entry's move on a shakmaty board before writing it
+    pub verify_on_write: bool,
+    /// Check that every entry's position survives a FEN roundtrip through sfbinpack
+    pub verify_fen_roundtrip: bool,
+    /// Encoding used to decode tags and comments
+    pub encoding: PgnEncoding,
+    /// Collect a histogram of the written scores (sfbinpack only)
+    pub eval_histogram: bool,
+    /// Time parsing and compression (sfbinpack only)
+    pub profile: bool,
+    /// Per-entry lines to collect alongside the binpack (sfbinpack only)
+    pub sidecars: Vec<SidecarKind>,
+    /// Drop games in which fewer than this percentage of moves carry an eval (sfbinpack only)
+    pub min_eval_coverage: Option<f64>,
+    /// Where scores come from; with `ScoreFrom::None` every move is written with score 0
+    pub score_from: ScoreFrom,
+    /// What to do when a game contains a null move (sfbinpack only)
+    pub on_null_move: NullMovePolicy,
+    /// Comment pattern tried before the built-in eval parsers (sfbinpack only)
+    pub eval_regex: Option<util::EvalRegex>,
+    /// Skip games whose `Termination` tag equals one of these, ignoring case (sfbinpack only)
+    pub drop_terminations: Vec<String>,
+    /// Only write positions whose material (P=1 N=3 B=3 R=5 Q=9, both sides) is at
+    /// least this value (sfbinpack only)
+    pub min_material: Option<i32>,
+    /// Only write positions whose material is at most this value (sfbinpack only)
+    pub max_material: Option<i32>,
+    /// Only convert the games with these indices within the file
+    pub games: Option<Range<u64>>,
+    /// Entries all builders of a run may still write together (sfbinpack only)
+    pub entry_budget: Option<Arc<EntryBudget>>,
+    /// Stop writing a game's positions after the first one with a mate score (sfbinpack only)
+    pub truncate_after_mate: bool,
+    /// Multiply the scores of games with result 0 (drawn or `*`) by this factor; mate
+    /// scores are kept (sfbinpack only)
+    pub draw_dampen: Option<f64>,
+    /// Library whose reading of the FEN tag both boards start from (sfbinpack only)
+    pub fen_normalization: FenNormalization,
+    /// Multiply scores by a factor chosen by how the game ended for the side to move;
+    /// mate scores are kept (sfbinpack only)
+    pub result_weight: Option<ResultWeight>,
+    /// Within a game, keep only the first of consecutive entries with the same score
+    /// (sfbinpack only)
+    pub collapse_equal_scores: bool,
+    /// Write moves without an eval too, with this score in centipawns (sfbinpack only)
+    pub keep_unscored: Option<i16>,
+    /// Drop the first position with an eval of every game (sfbinpack only)
+    pub drop_first_eval: bool,
+    /// Warn about games whose result contradicts a checkmate or a draw by rule in the
+    /// final position (sfbinpack only)
+    pub check_results: bool,
+    /// Keep at most this many uniformly sampled positions per game (sfbinpack only)
+    pub reservoir_per_game: Option<usize>,
+    /// Seed of the per-game sampling; every file starts from it
+    pub seed: u64,
+    /// Treat the mainline after the FEN as a puzzle solution: every move is written,
+    /// comments are ignored and games without a FEN are skipped (sfbinpack only)
+    pub puzzle_mode: bool,
+    /// Log and skip games that fail to convert instead of failing the whole file
+    pub skip_errors: bool,
+}
+
+/// Kinds of per-entry sidecar lines, written in the same order as the entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SidecarKind {
+    /// "win draw loss" probabilities of the stored score
+    Wdl,
+    /// Number of legal moves in the entry's position
+    LegalMoves,
+    /// "side castling ep" fields of the entry's position, e.g. `w KQkq -`
+    BoardFlags,
+    /// `Termination` tag of the entry's game, `unknown` if missing
+    Termination,
+    /// Seconds on the mover's clock after the entry's move, from a `[%clk ...]`
+    /// comment; empty if missing
+    Clock,
+}
+
+/// Score factors per game outcome from the side to move's view, written as
+/// `WIN:DRAW:LOSS`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResultWeight {
+    pub win: f64,
+    pub draw: f64,
+    pub loss: f64,
+}
+
+impl ResultWeight {
+    /// Factor for an entry with sfbinpack result `result` (1 win, 0 draw, -1 loss).
+    pub fn factor(&self, result: i16) -> f64 {
+        match result.signum() {
+            1 => self.win,
+            -1 => self.loss,
+            _ => self.draw,
+        }
+    }
+}
+
+impl FromStr for ResultWeight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let [win, draw, loss] = parts[..] else {
+            return Err(format!("expected WIN:DRAW:LOSS, got {:?}", s));
+        };
+        let parse = |v: &str| match v.trim().parse::<f64>() {
+            Ok(factor) if factor.is_finite() && factor >= 0.0 => Ok(factor),
+            _ => Err(format!("invalid weight {:?}, expected a number >= 0", v)),
+        };
+        Ok(Self {
+            win: parse(win)?,
+            draw: parse(draw)?,
+            loss: parse(loss)?,
+        })
+    }
+}
+
+/// Global cap on the number of written entries, shared between workers.
+#[derive(Debug)]
+pub struct EntryBudget {
+    remaining: AtomicU64,
+}
+
+impl EntryBudget {
+    pub fn new(entries: u64) -> Self {
+        Self {
+            remaining: AtomicU64::new(entries),
+        }
+    }
+
+    /// Reserves up to `wanted` entries and returns how many were granted.
+    pub fn take(&self, wanted: u64) -> u64 {
+        let mut granted = 0;
+        let _ = self
+            .remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                granted = left.min(wanted);
+                Some(left - granted)
+            });
+        granted
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.remaining.load(Ordering::SeqCst) == 0
+    }
+}
+
+/// Score of the final solution position in puzzle mode, from the solver's side.
+pub const PUZZLE_SOLVED_SCORE: i16 = 1000;
+
 pub struct BinpackBuilder<T: Write + Seek> {
     input: PathBuf,
     output: T,
     total_pos: u64,
+    total_games: u64,
     backend: Backend,
+    options: BuildOptions,
+    histogram: Option<EvalHistogram>,
+    profile: Option<Profile>,
+    sidecars: Vec<Vec<u8>>,
+    eval_unit_warning: bool,
+    result_mismatches: Vec<ResultMismatch>,
+    entries_written: u64,
+    game_errors: Vec<GameError>,
+}
+
+/// A game that failed to convert and was skipped under `skip_errors`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameError {
+    /// Index of the game within its file
+    pub game: u64,
+    /// The error with its context chain
+    pub message: String,
+}
+
+/// A game whose PGN result contradicts how its final position ends.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResultMismatch {
+    /// Index of the game within its file
+    pub game: u64,
+    pub result: String,
+    /// How the final position ends, e.g. "by the fifty-move rule"
+    pub reason: &'static str,
 }
 
 impl<T: Write + Seek> BinpackBuilder<T> {
-    pub fn new<P: Into<PathBuf>>(input_pgn: P, output_file: T, backend: Backend) -> Self {
+    pub fn new<P: Into<PathBuf>>(
+        input_pgn: P,
+        output_file: T,
+        backend: Backend,
+        options: BuildOptions,
+    ) -> Self {
         Self {
             input: input_pgn.into(),
             output: output_file,
             total_pos: 0,
+            total_games: 0,
             backend,
+            options,
+            histogram: None,
+            profile: None,
+            sidecars: Vec::new(),
+            eval_unit_warning: false,
+            result_mismatches: Vec::new(),
+            entries_written: 0,
+            game_errors: Vec::new(),
         }
     }
 
     pub fn create_binpack(&mut self) -> Result<()> {
-        let reader_input = self.get_reader()?;
-        let buf_reader = BufReader::new(reader_input);
+        let reader_input = crate::io::open_pgn(&self.input)?;
+        self.create_binpack_from(reader_input)
+    }
+
+    /// Converts PGN text from `input`; `self.input` is only used for error messages.
+    pub fn create_binpack_from<R: Read>(&mut self, input: R) -> Result<()> {
+        if self.budget_exhausted() {
+            return Ok(());
+        }
+
+        let buf_reader = BufReader::new(input);
         let mut reader = Reader::new(buf_reader);
 
         match self.backend {
             Backend::Sfbinpack => {
                 let mut writer = CompressedTrainingDataEntryWriter::new(&mut self.output)
                     .context("creating binpack writer")?;
-                let mut visitor = SfVisitor::new(&mut writer);
-
-                for res in reader.read_games(&mut visitor) {
-                    let game_result =
-                        res.with_context(|| format!("reading PGN game: {:?}", self.input))?;
-                    let moves = game_result.context("processing game moves")?;
-                    self.total_pos += moves as u64;
-                }
+                let mut visitor = SfVisitor::new(&mut writer, &self.options);
+
+                let t0 = Instant::now();
+                let games = read_all_games(
+                    &mut reader,
+                    &mut visitor,
+                    &self.input,
+                    &mut self.total_pos,
+                    &mut self.total_games,
+                    self.options.skip_errors.then_some(&mut self.game_errors),
+                );
+                let elapsed = t0.elapsed();
+                // keep the scores of the games written before a failure
+                self.histogram = visitor.histogram.take();
+                self.sidecars = std::mem::take(&mut visitor.sidecars);
+                let eval_units = std::mem::take(&mut visitor.eval_units);
+                let mismatches = std::mem::take(&mut visitor.result_mismatches);
+                self.entries_written = visitor.written;
+                let compression = visitor.compression;
+
+                // the writer compresses its last block when dropped
+                let t1 = Instant::now();
+                drop(writer);
+                self.set_profile(elapsed, compression, t1.elapsed());
+                self.check_eval_units(&eval_units);
+                self.report_result_mismatches(mismatches);
+                games?;
             }
             Backend::Viriformat => {
-                let mut visitor = ViriformatVisitor::new(&mut self.output);
-                for res in reader.read_games(&mut visitor) {
-                    let game_result =
-                        res.with_context(|| format!("reading PGN game: {:?}", self.input))?;
-                    let moves = game_result.context("processing game moves")?;
-                    self.total_pos += moves as u64;
-                }
+                let mut visitor = ViriformatVisitor::new(
+                    &mut self.output,
+                    self.options.encoding,
+                    self.options.games.clone(),
+                );
+                read_all_games(
+                    &mut reader,
+                    &mut visitor,
+                    &self.input,
+                    &mut self.total_pos,
+                    &mut self.total_games,
+                    self.options.skip_errors.then_some(&mut self.game_errors),
+                )?;
             }
         }
         Ok(())
     }
 
-    fn get_reader(&self) -> Result<Box<dyn std::io::Read>> {
-        let reader_input: Box<dyn std::io::Read> =
-            if self.input.extension().and_then(|s| s.to_str()) == Some("gz") {
-                let file = File::open(&self.input)
-                    .with_context(|| format!("opening gz file {:?}", self.input))?;
-                Box::new(MultiGzDecoder::new(file))
-            } else {
-                let file = File::open(&self.input)
-                    .with_context(|| format!("opening file {:?}", self.input))?;
-                Box::new(file)
-            };
+    /// Converts the input PGN into a binpack writer shared with other builders
+    /// instead of this builder's own output (sfbinpack only).
+    pub fn create_binpack_shared<W: Write + Seek>(
+        &mut self,
+        shared: &Mutex<CompressedTrainingDataEntryWriter<W>>,
+    ) -> Result<()> {
+        if self.backend != Backend::Sfbinpack {
+            bail!("a shared writer is only supported with the sfbinpack backend");
+        }
 
-        Ok(reader_input)
+        let mut reader = Reader::new(BufReader::new(crate::io::open_pgn(&self.input)?));
+        let mut sink = shared;
+        let mut visitor = SfVisitor::new(&mut sink, &self.options);
+
+        let t0 = Instant::now();
+        let games = read_all_games(
+            &mut reader,
+            &mut visitor,
+            &self.input,
+            &mut self.total_pos,
+            &mut self.total_games,
+            self.options.skip_errors.then_some(&mut self.game_errors),
+        );
+        self.histogram = visitor.histogram.take();
+        self.sidecars = std::mem::take(&mut visitor.sidecars);
+        let eval_units = std::mem::take(&mut visitor.eval_units);
+        let mismatches = std::mem::take(&mut visitor.result_mismatches);
+        self.entries_written = visitor.written;
+        self.set_profile(t0.elapsed(), visitor.compression, Duration::ZERO);
+        self.check_eval_units(&eval_units);
+        self.report_result_mismatches(mismatches);
+        games
     }
 
     pub fn into_inner(self) -> std::io::Result<T> {
@@ -99,75 +366,442 @@ impl<T: Write + Seek> BinpackBuilder<T> {
     pub fn total_positions(&self) -> u64 {
         self.total_pos
     }
+
+    /// Number of games read without errors.
+    pub fn total_games(&self) -> u64 {
+        self.total_games
+    }
+
+    /// Number of games dropped for an error, if `skip_errors` was set.
+    pub fn skipped_games(&self) -> u64 {
+        self.game_errors.len() as u64
+    }
+
+    /// The games dropped for an error, if `skip_errors` was set.
+    pub fn game_errors(&self) -> &[GameError] {
+        &self.game_errors
+    }
+
+    /// Scores written so far, if `eval_histogram` was requested.
+    pub fn histogram(&self) -> Option<&EvalHistogram> {
+        self.histogram.as_ref()
+    }
+
+    /// Whether the evals of the input looked like centipawns rather than pawns.
+    pub fn eval_unit_warning(&self) -> bool {
+        self.eval_unit_warning
+    }
+
+    fn check_eval_units(&mut self, check: &util::EvalUnitCheck) {
+        if check.looks_like_centipawns() {
+            eprintln!(
+                "\nWarning: evals in {} look like centipawns (median |eval| {:.2} pawns) but are \
+                 read as pawns; scores will be 100x too large",
+                self.input.display(),
+                f64::from(check.median().unwrap_or(0)) / 100.0
+            );
+            self.eval_unit_warning = true;
+        }
+    }
+
+    /// Entries handed to the binpack writer (sfbinpack only); unlike `total_positions`
+    /// this leaves out moves that were not written.
+    pub fn entries_written(&self) -> u64 {
+        self.entries_written
+    }
+
+    /// Games whose result contradicted their final position, if `check_results` was set.
+    pub fn result_mismatches(&self) -> &[ResultMismatch] {
+        &self.result_mismatches
+    }
+
+    fn report_result_mismatches(&mut self, mismatches: Vec<ResultMismatch>) {
+        for mismatch in &mismatches {
+            eprintln!(
+                "\nWarning: game {} of {} is marked {} but ends {}",
+                mismatch.game,
+                self.input.display(),
+                mismatch.result,
+                mismatch.reason
+            );
+        }
+        self.result_mismatches = mismatches;
+    }
+
+    fn budget_exhausted(&self) -> bool {
+        self.options
+            .entry_budget
+            .as_ref()
+            .is_some_and(|budget| budget.exhausted())
+    }
+
+    /// Time spent per phase, if `profile` was requested.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Records a profile from the time spent reading games, the part of it spent
+    /// writing entries, and the time taken to finish the writer.
+    fn set_profile(&mut self, reading: Duration, writing: Duration, finish: Duration) {
+        if self.options.profile {
+            self.profile = Some(Profile {
+                parsing: reading.saturating_sub(writing),
+                compression: writing + finish,
+                writing: Duration::ZERO,
+            });
+        }
+    }
+
+    /// Sidecar lines of the written entries, one buffer per requested kind.
+    pub fn take_sidecars(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.sidecars)
+    }
+}
+
+/// Destination for the entries of complete games.
+pub trait EntrySink {
+    fn write_game(&mut self, entries: &[TrainingDataEntry]) -> Result<()>;
+}
+
+impl<T: Write + Seek> EntrySink for CompressedTrainingDataEntryWriter<T> {
+    fn write_game(&mut self, entries: &[TrainingDataEntry]) -> Result<()> {
+        for entry in entries {
+            self.write_entry(entry)
+                .context("writing entry to binpack")?;
+        }
+        Ok(())
+    }
+}
+
+/// A writer shared between threads; each game is written under one lock so
+/// that its entries stay contiguous.
+impl<T: Write + Seek> EntrySink for &Mutex<CompressedTrainingDataEntryWriter<T>> {
+    fn write_game(&mut self, entries: &[TrainingDataEntry]) -> Result<()> {
+        let mut writer = self
+            .lock()
+            .map_err(|_| anyhow::anyhow!("shared binpack writer poisoned"))?;
+        writer.write_game(entries)
+    }
+}
+
+/// Reads every game of `reader`. With `skipped` set, a game that fails to convert is
+/// logged and recorded there instead of ending the file; read errors still do.
+fn read_all_games<R: Read, V: Visitor<Output = Result<u32>>>(
+    reader: &mut Reader<R>,
+    visitor: &mut V,
+    input: &Path,
+    total_pos: &mut u64,
+    total_games: &mut u64,
+    mut skipped: Option<&mut Vec<GameError>>,
+) -> Result<()> {
+    for res in reader.read_games(visitor) {
+        let game_result = res.with_context(|| format!("reading PGN game: {:?}", input))?;
+        let moves = match (game_result, skipped.as_deref_mut()) {
+            (Ok(moves), _) => moves,
+            (Err(e), Some(skipped)) => {
+                // the visitor resets itself when the next game's tags begin
+                let error = GameError {
+                    game: *total_games + skipped.len() as u64,
+                    message: format!("{:#}", e),
+                };
+                eprintln!(
+                    "\nWarning: skipping game {} of {}: {}",
+                    error.game,
+                    input.display(),
+                    error.message
+                );
+                skipped.push(error);
+                continue;
+            }
+            (Err(e), None) => return Err(e.context("processing game moves")),
+        };
+        *total_pos += moves as u64;
+        *total_games += 1;
+    }
+    Ok(())
 }
 
 // ---------------- Visitor & parsing logic ----------------
 
-struct SfVisitor<'a, T: Write + Seek> {
-    writer: &'a mut CompressedTrainingDataEntryWriter<T>,
+struct SfVisitor<'a, S: EntrySink> {
+    writer: &'a mut S,
+    options: &'a BuildOptions,
     // todo: could apply directly
     start_fen: Option<String>,
     // game result from the PGN tags: 1 = white win, -1 = black win, 0 = draw/unknown
     result: i16,
+    // the Result tag was 1/2-1/2 rather than *
+    drawn: bool,
     // shakmaty crate representation of the board
     chess: Chess,
     // binpack crate representation of the board
     binpack_board: SfPosition,
+    // start positions of the previous game, reused when the next game starts from the same FEN
+    start_cache: StartCache,
+    selector: GameSelector,
     pending_entry: Option<TrainingDataEntry>,
     pending_score_set: bool,
+    // whether the position of the pending entry lies within the material band and
+    // binpack can store its move
+    pending_writable: bool,
+    // legal moves in the position of the pending entry, if that sidecar was requested
+    pending_legal_moves: usize,
+    // [%clk] of the pending entry's move, in seconds
+    pending_clock: Option<f64>,
+    // score of the previous in-band entry of the game, for collapse_equal_scores
+    last_score: Option<i16>,
+    // eval of a comment that came before the move it annotates
+    early_eval: Option<i16>,
     game_end_time: Option<String>,
+    termination: Option<String>,
+    // the Variant tag names Chess960
+    chess960: bool,
     // number of moves processed per game
     moves: u32,
+    // number of moves of the current game that carried a score
+    scored: u32,
+    // a mate score was written in the current game
+    mate_seen: bool,
+    // entries of the current game, written once the game is complete
+    game: Vec<TrainingDataEntry>,
+    // sidecar lines of the entries in `game`, one buffer per `options.sidecars`
+    game_sidecars: Vec<Vec<u8>>,
+    histogram: Option<EvalHistogram>,
+    // one line per written entry and requested kind
+    sidecars: Vec<Vec<u8>>,
+    rng: SplitMix64,
+    // time spent handing complete games to the writer
+    compression: Duration,
+    // evals of the file as parsed, to spot centipawn comments
+    eval_units: util::EvalUnitCheck,
+    // occurrences of each position of the current game, if check_results was requested
+    repetitions: HashMap<u64, u32>,
+    result_mismatches: Vec<ResultMismatch>,
+    // entries handed to the writer
+    written: u64,
+}
+
+/// Picks the games of a file that fall into the requested index range.
+struct GameSelector {
+    range: Option<Range<u64>>,
+    next: u64,
+}
+
+impl GameSelector {
+    fn new(range: Option<Range<u64>>) -> Self {
+        Self { range, next: 0 }
+    }
+
+    /// Whether the game that starts now should be converted.
+    fn select_next(&mut self) -> bool {
+        let index = self.next;
+        self.next += 1;
+        self.range
+            .as_ref()
+            .is_none_or(|range| range.contains(&index))
+    }
 }
 
-impl<'a, T: Write + Seek> SfVisitor<'a, T> {
-    fn new(writer: &'a mut CompressedTrainingDataEntryWriter<T>) -> Self {
+/// Parsed start positions, so that games sharing a start position (usually the
+/// standard one) don't pay for constructing the boards again.
+struct StartCache {
+    default_chess: Chess,
+    default_board: SfPosition,
+    last_fen: Option<(String, Chess, SfPosition)>,
+}
+
+impl StartCache {
+    fn new() -> Self {
+        Self {
+            default_chess: Chess::default(),
+            default_board: SfPosition::default(),
+            last_fen: None,
+        }
+    }
+
+    fn lookup(&self, fen: &str) -> Option<(Chess, SfPosition)> {
+        self.last_fen
+            .as_ref()
+            .filter(|(cached, _, _)| cached == fen)
+            .map(|(_, chess, board)| (chess.clone(), *board))
+    }
+}
+
+impl<'a, S: EntrySink> SfVisitor<'a, S> {
+    fn new(writer: &'a mut S, options: &'a BuildOptions) -> Self {
         Self {
             writer,
+            options,
             start_fen: None,
             result: 0,
+            drawn: false,
             chess: Chess::default(),
             binpack_board: SfPosition::default(),
+            start_cache: StartCache::new(),
+            selector: GameSelector::new(options.games.clone()),
             pending_entry: None,
             pending_score_set: false,
+            pending_writable: true,
+            pending_legal_moves: 0,
+            pending_clock: None,
+            last_score: None,
+            early_eval: None,
             game_end_time: None,
+            termination: None,
+            chess960: false,
             moves: 0,
+            scored: 0,
+            mate_seen: false,
+            game: Vec::new(),
+            game_sidecars: vec![Vec::new(); options.sidecars.len()],
+            histogram: options.eval_histogram.then(EvalHistogram::default),
+            sidecars: vec![Vec::new(); options.sidecars.len()],
+            rng: SplitMix64::new(options.seed),
+            compression: Duration::ZERO,
+            eval_units: util::EvalUnitCheck::default(),
+            repetitions: HashMap::new(),
+            result_mismatches: Vec::new(),
+            written: 0,
         }
     }
 
     fn reset_game(&mut self) {
+        // the boards are set up by apply_start_fen once the tags are known
         self.start_fen = None;
         self.result = 0;
-        self.chess = Chess::default();
-        self.binpack_board = SfPosition::default();
+        self.drawn = false;
+        self.termination = None;
+        self.chess960 = false;
         self.moves = 0;
+        self.scored = 0;
+        self.mate_seen = false;
         self.pending_entry = None;
         self.pending_score_set = false;
+        self.pending_clock = None;
+        self.last_score = None;
+        self.early_eval = None;
+        self.repetitions.clear();
+        self.game.clear();
+        self.game_sidecars.iter_mut().for_each(Vec::clear);
     }
 
     fn apply_start_fen(&mut self) -> Result<()> {
-        if let Some(fen) = &self.start_fen {
+        let Some(fen) = &self.start_fen else {
+            self.chess = self.start_cache.default_chess.clone();
+            self.binpack_board = self.start_cache.default_board;
+            return Ok(());
+        };
+
+        if let Some((chess, board)) = self.start_cache.lookup(fen) {
+            self.chess = chess;
+            self.binpack_board = board;
+            return Ok(());
+        }
+
+        let parse_chess = |fen: &str| -> Result<Chess> {
             let f = shakmaty::fen::Fen::from_ascii(fen.as_bytes())
                 .with_context(|| format!("parsing FEN: {}", fen))?;
+            // Shredder-FEN castling files or rooks off the corners mean Chess960
+            let mode = if self.chess960 {
+                CastlingMode::Chess960
+            } else {
+                CastlingMode::detect(f.as_setup())
+            };
+            let pos = f.into_position(mode);
+            let pos = if self.options.fen_normalization == FenNormalization::Shakmaty {
+                pos.or_else(PositionError::ignore_invalid_castling_rights)
+                    .or_else(PositionError::ignore_invalid_ep_square)
+            } else {
+                pos
+            };
+            pos.with_context(|| format!("creating position from FEN: {}", fen))
+        };
+        // sfbinpack validates FENs on its own and may reject what shakmaty accepts
+        let parse_board = |fen: &str| {
+            let fen = sfbinpack_fen(fen)?;
+            SfPosition::from_fen(&fen)
+                .map_err(|err| anyhow::anyhow!("sfbinpack rejected FEN {:?}: {err:?}", fen))
+        };
 
-            let pos = f
-                .into_position(shakmaty::CastlingMode::Standard)
-                .with_context(|| format!("creating position from FEN: {}", fen))?;
+        let (pos, board) = match self.options.fen_normalization {
+            FenNormalization::Raw => (parse_chess(fen)?, parse_board(fen)?),
+            FenNormalization::Shakmaty => {
+                let pos = parse_chess(fen)?;
+                let canonical = Fen::from_position(&pos, EnPassantMode::Legal).to_string();
+                (pos, parse_board(&canonical)?)
+            }
+            FenNormalization::Sfbinpack => {
+                let board = parse_board(fen)?;
+                let canonical = board
+                    .fen()
+                    .map_err(|err| anyhow::anyhow!("failed to render FEN {:?}: {err:?}", fen))?;
+                (parse_chess(&canonical)?, board)
+            }
+        };
 
-            self.chess = pos;
-            self.binpack_board = SfPosition::from_fen(fen).unwrap();
-        } else {
-            self.chess = Chess::default();
-            self.binpack_board = SfPosition::default();
-        }
+        self.chess = pos;
+        self.binpack_board = board;
+        self.binpack_board
+            .set_castling_rights(storable_castling_rights(&self.chess));
+        self.start_cache.last_fen = Some((fen.clone(), self.chess.clone(), self.binpack_board));
         Ok(())
     }
 
-    fn flush_pending(&mut self) -> Result<()> {
-        if let Some(entry) = self.pending_entry.take() {
-            self.writer
-                .write_entry(&entry)
-                .context("writing entry to binpack")?;
+    /// Writes the pending entry; `scored` tells whether its score came from an eval
+    /// rather than from --keep-unscored.
+    fn flush_pending(&mut self, scored: bool) -> Result<()> {
+        if let Some(mut entry) = self.pending_entry.take() {
+            if scored {
+                self.scored += 1;
+            }
+            if !self.pending_writable || self.mate_seen || (scored && self.drops_first_eval()) {
+                self.pending_score_set = false;
+                return Ok(());
+            }
+            // the writer starts a new chain after the gap
+            if self.options.collapse_equal_scores
+                && self.last_score.replace(entry.score) == Some(entry.score)
+            {
+                self.pending_score_set = false;
+                return Ok(());
+            }
+            if self.options.truncate_after_mate {
+                self.mate_seen = i32::from(entry.score).abs() >= wdl::MATE_SCORE_THRESHOLD;
+            }
+            if let Some(factor) = self.options.draw_dampen.filter(|_| self.result == 0) {
+                entry.score = scale_score(entry.score, factor);
+            }
+            if let Some(weight) = self.options.result_weight {
+                entry.score = scale_score(entry.score, weight.factor(entry.result));
+            }
+            if self.options.verify_on_write {
+                verify_entry(&entry, &self.chess)?;
+            }
+            if self.options.verify_fen_roundtrip {
+                verify_fen_roundtrip(&entry)?;
+            }
+            for (kind, lines) in self.options.sidecars.iter().zip(&mut self.game_sidecars) {
+                match kind {
+                    SidecarKind::Wdl => {
+                        // same position as the one the score was converted with
+                        let (win, draw, loss) =
+                            wdl::wdl_probabilities(i32::from(entry.score), &self.chess);
+                        writeln!(lines, "{:.4} {:.4} {:.4}", win, draw, loss)?;
+                    }
+                    SidecarKind::LegalMoves => writeln!(lines, "{}", self.pending_legal_moves)?,
+                    SidecarKind::BoardFlags => writeln!(lines, "{}", board_flags(&entry)?)?,
+                    SidecarKind::Termination => writeln!(
+                        lines,
+                        "{}",
+                        self.termination.as_deref().unwrap_or("unknown")
+                    )?,
+                    SidecarKind::Clock => match self.pending_clock {
+                        Some(seconds) => writeln!(lines, "{}", seconds)?,
+                        None => writeln!(lines)?,
+                    },
+                }
+            }
+            self.game.push(entry);
         } else if self.pending_score_set {
             anyhow::bail!("pending score set but no pending entry");
         } else {
@@ -178,10 +812,153 @@ impl<'a, T: Write + Seek> SfVisitor<'a, T> {
         Ok(())
     }
 
+    /// Whether the entry just counted is the first scored one of the game and
+    /// --drop-first-eval is set; that eval is usually a book move's 0.00.
+    fn drops_first_eval(&self) -> bool {
+        self.options.drop_first_eval && self.scored == 1
+    }
+
+    fn count_repetition(&mut self) {
+        let hash = self.chess.zobrist_hash::<Zobrist64>(EnPassantMode::Legal);
+        *self.repetitions.entry(hash.0).or_default() += 1;
+    }
+
+    /// Records the game if its result contradicts a checkmate or a draw by rule in
+    /// the final position.
+    fn check_result(&mut self) {
+        let reason = if self.chess.is_checkmate() {
+            // the side to move is mated
+            let winner = if self.chess.turn() == Color::White {
+                -1
+            } else {
+                1
+            };
+            (self.result == -winner || self.drawn).then_some("in checkmate")
+        } else {
+            let hash = self.chess.zobrist_hash::<Zobrist64>(EnPassantMode::Legal);
+            let draw = if self.chess.is_stalemate() {
+                Some("in stalemate")
+            } else if self.chess.is_insufficient_material() {
+                Some("with insufficient material")
+            } else if self.chess.halfmoves() >= 100 {
+                Some("by the fifty-move rule")
+            } else if self.repetitions.get(&hash.0).is_some_and(|&n| n >= 3) {
+                Some("by threefold repetition")
+            } else {
+                None
+            };
+            draw.filter(|_| self.result != 0)
+        };
+
+        if let Some(reason) = reason {
+            let result = match self.result {
+                1 => "1-0",
+                -1 => "0-1",
+                _ => "1/2-1/2",
+            };
+            self.result_mismatches.push(ResultMismatch {
+                // the selector has already counted this game
+                game: self.selector.next - 1,
+                result: result.to_string(),
+                reason,
+            });
+        }
+    }
+
+    /// Ply of the current position counted from the start of the game, taken from the
+    /// move counters that shakmaty carries on from the FEN tag (0 without one).
+    fn game_ply(&self) -> u16 {
+        let fullmoves = self.chess.fullmoves().get();
+        let ply = 2 * (fullmoves - 1) + u32::from(self.chess.turn() == Color::Black);
+        u16::try_from(ply).unwrap_or(u16::MAX)
+    }
+
+    /// Whether enough moves of the current game carried an eval.
+    fn meets_eval_coverage(&self) -> bool {
+        match self.options.min_eval_coverage {
+            Some(min) if self.moves > 0 => {
+                f64::from(self.scored) * 100.0 >= min * f64::from(self.moves)
+            }
+            _ => true,
+        }
+    }
+
+    /// Reduces the current game to a uniform sample of `reservoir_per_game` entries.
+    fn sample_game(&mut self) {
+        let Some(k) = self.options.reservoir_per_game else {
+            return;
+        };
+        if self.game.len() <= k {
+            return;
+        }
+
+        let keep = reservoir_sample(self.game.len(), k, &mut self.rng);
+        self.game = keep.iter().map(|&i| self.game[i]).collect();
+
+        // every entry has exactly one line per sidecar
+        for lines in &mut self.game_sidecars {
+            let all: Vec<&[u8]> = lines.split_inclusive(|&b| b == b'\n').collect();
+            *lines = keep.iter().flat_map(|&i| all[i].to_vec()).collect();
+        }
+    }
+
+    /// Writes the entries collected for the current game and returns its move count.
+    fn finish_game(&mut self) -> Result<u32> {
+        self.flush_unscored()?;
+        if self.options.check_results {
+            self.check_result();
+        }
+        if !self.meets_eval_coverage() {
+            return Ok(0);
+        }
+
+        if self.options.puzzle_mode {
+            if let Some(last) = self.game.last_mut() {
+                last.score = PUZZLE_SOLVED_SCORE;
+            }
+        }
+
+        self.sample_game();
+        self.write_game()?;
+        Ok(self.moves)
+    }
+
+    fn write_game(&mut self) -> Result<()> {
+        if let Some(budget) = &self.options.entry_budget {
+            let granted = budget.take(self.game.len() as u64) as usize;
+            // a prefix of a game is still a valid chain
+            self.game.truncate(granted);
+            for lines in &mut self.game_sidecars {
+                let end = lines
+                    .split_inclusive(|&b| b == b'\n')
+                    .take(granted)
+                    .map(<[u8]>::len)
+                    .sum();
+                lines.truncate(end);
+            }
+        }
+
+        if let Some(histogram) = &mut self.histogram {
+            for entry in &self.game {
+                histogram.add(i32::from(entry.score));
+            }
+        }
+        let t0 = Instant::now();
+        self.writer.write_game(&self.game)?;
+        self.written += self.game.len() as u64;
+        self.compression += t0.elapsed();
+
+        for (sidecar, lines) in self.sidecars.iter_mut().zip(&self.game_sidecars) {
+            sidecar.extend_from_slice(lines);
+        }
+        Ok(())
+    }
+
     fn handle_move(&mut self, mv: Move) -> Result<()> {
         self.moves += 1;
 
-        assert!(self.pending_entry.is_none());
+        // the previous move had no eval comment
+        self.flush_unscored()?;
 
         let sf_mv = util::convert_move(&mv, self.binpack_board.side_to_move());
 
@@ -195,27 +972,91 @@ impl<'a, T: Write + Seek> SfVisitor<'a, T> {
         let entry = TrainingDataEntry {
             pos: self.binpack_board,
             mv: sf_mv,
-            score: 0,                      // will update if a comment with eval follows
-            ply: self.binpack_board.ply(), // will use the ply from the fen tag if present
+            score: 0, // will update if a comment with eval follows
+            ply: self.game_ply(),
             result,
         };
 
         self.pending_entry = Some(entry);
         self.pending_score_set = false;
+        self.pending_writable = self.material_in_band() && is_storable_castle(&mv);
+        if self.options.sidecars.contains(&SidecarKind::LegalMoves) {
+            self.pending_legal_moves = self.chess.legal_moves().len();
+        }
+        self.pending_clock = None;
 
         self.chess.play_unchecked(mv);
-        self.binpack_board.do_move(sf_mv);
+        if is_storable_castle(&mv) {
+            self.binpack_board.do_move(sf_mv);
+        } else {
+            // sfbinpack would put the pieces on the wrong squares, the game goes on
+            // from shakmaty's board in a new chain
+            self.binpack_board = binpack_board_of(&self.chess)?;
+        }
+        if self.options.check_results {
+            self.count_repetition();
+        }
+
+        if self.options.score_from == ScoreFrom::None || self.options.puzzle_mode {
+            return self.flush_pending(true);
+        }
+        if let Some(cp) = self.early_eval.take() {
+            return self.apply_eval(cp);
+        }
 
         Ok(())
     }
 
+    /// Whether the current position lies within the requested material band.
+    fn material_in_band(&self) -> bool {
+        let (min, max) = (self.options.min_material, self.options.max_material);
+        if min.is_none() && max.is_none() {
+            return true;
+        }
+
+        let material = wdl::material_count(&self.chess);
+        min.is_none_or(|min| material >= min) && max.is_none_or(|max| material <= max)
+    }
+
     fn attach_comment_eval(&mut self, comment: &str) -> Result<()> {
-        let cp = match util::parse_eval_cp(comment) {
+        if self.pending_entry.is_some() && self.options.sidecars.contains(&SidecarKind::Clock) {
+            if let Some(seconds) = util::parse_clock(comment) {
+                self.pending_clock = Some(seconds);
+            }
+        }
+
+        let custom = self
+            .options
+            .eval_regex
+            .as_ref()
+            .and_then(|r| r.parse(comment));
+        let parsed = match (custom, self.options.score_from) {
+            (Some(cp), _) => Ok(Some(cp)),
+            (None, ScoreFrom::DeepestComment) => util::parse_deepest_eval_cp(comment),
+            (None, _) => util::parse_eval_cp(comment),
+        };
+        let cp = match parsed {
             Ok(Some(v)) => v,
             Ok(None) => return Ok(()), // known non-eval comment
             Err(_) => anyhow::bail!("failed to parse evaluation from comment: {}", comment),
         };
 
+        if self.pending_entry.is_none() {
+            // only an eval ahead of the first move, e.g. `{+0.20/12} 1. e4`, is known to
+            // annotate the next move; later ones follow a move that already has its eval
+            if self.moves == 0 {
+                self.eval_units.add(cp);
+                self.early_eval = Some(cp);
+            }
+            return Ok(());
+        }
+        self.eval_units.add(cp);
+
+        self.apply_eval(cp)
+    }
+
+    /// Sets the score of the pending entry from an eval in centipawns and writes it.
+    fn apply_eval(&mut self, cp: i16) -> Result<()> {
         let internal = wdl::external_cp_to_internal(cp as i32, &self.chess);
 
         let entry = self
@@ -226,17 +1067,40 @@ impl<'a, T: Write + Seek> SfVisitor<'a, T> {
         entry.score = internal;
         self.pending_score_set = true;
 
-        self.flush_pending()
+        self.flush_pending(true)
+    }
+
+    /// Writes the pending entry of a move without an eval with the --keep-unscored
+    /// score, or drops it without that option.
+    fn flush_unscored(&mut self) -> Result<()> {
+        let Some(cp) = self.options.keep_unscored else {
+            self.pending_entry = None;
+            return Ok(());
+        };
+        let Some(entry) = self.pending_entry.as_mut() else {
+            return Ok(());
+        };
+
+        entry.score = wdl::external_cp_to_internal(i32::from(cp), &self.chess);
+        self.flush_pending(false)
     }
 }
 
-impl<'a, T: Write + Seek> Visitor for SfVisitor<'a, T> {
+impl<'a, S: EntrySink> Visitor for SfVisitor<'a, S> {
     type Tags = ();
     type Movetext = ();
     type Output = Result<u32>; // number of moves processed per game
 
     fn begin_tags(&mut self) -> ControlFlow<Self::Output, Self::Tags> {
         self.reset_game();
+        if !self.selector.select_next() {
+            return ControlFlow::Break(Ok(0));
+        }
+        if let Some(budget) = &self.options.entry_budget {
+            if budget.exhausted() {
+                return ControlFlow::Break(Ok(0));
+            }
+        }
         ControlFlow::Continue(())
     }
 
@@ -246,7 +1110,7 @@ impl<'a, T: Write + Seek> Visitor for SfVisitor<'a, T> {
         name: &[u8],
         value: RawTag<'_>,
     ) -> ControlFlow<Self::Output> {
-        let n = match std::str::from_utf8(name) {
+        let n = match util::decode_pgn_text(name, self.options.encoding) {
             Ok(val) => val,
             Err(e) => {
                 return ControlFlow::Break(Err(anyhow::anyhow!(
@@ -257,7 +1121,7 @@ impl<'a, T: Write + Seek> Visitor for SfVisitor<'a, T> {
             }
         };
 
-        let v = match std::str::from_utf8(value.0) {
+        let v = match util::decode_pgn_text(value.0, self.options.encoding) {
             Ok(val) => val,
             Err(e) => {
                 return ControlFlow::Break(Err(anyhow::anyhow!(
@@ -268,10 +1132,10 @@ impl<'a, T: Write + Seek> Visitor for SfVisitor<'a, T> {
             }
         };
 
-        match n {
+        match n.as_ref() {
             "FEN" => self.start_fen = Some(v.to_string()),
             "Result" => {
-                self.result = match v {
+                self.result = match v.as_ref() {
                     "1-0" => 1,
                     "0-1" => -1,
                     "1/2-1/2" | "*" => 0,
@@ -281,14 +1145,25 @@ impl<'a, T: Write + Seek> Visitor for SfVisitor<'a, T> {
                             v
                         )))
                     }
-                }
-            }
-            "Variant" => {
-                return ControlFlow::Break(Err(anyhow::anyhow!("variant tag not supported")));
+                };
+                self.drawn = v.as_ref() == "1/2-1/2";
             }
+            "Variant" => match v.to_ascii_lowercase().as_str() {
+                "standard" => {}
+                "chess960" | "chess 960" | "fischerandom" | "fischer random" => {
+                    self.chess960 = true
+                }
+                _ => {
+                    return ControlFlow::Break(Err(anyhow::anyhow!(
+                        "variant tag not supported: {}",
+                        v
+                    )))
+                }
+            },
             "GameEndTime" => {
                 self.game_end_time = Some(v.to_string());
             }
+            "Termination" => self.termination = Some(v.to_string()),
             _ => {}
         }
 
@@ -296,9 +1171,22 @@ impl<'a, T: Write + Seek> Visitor for SfVisitor<'a, T> {
     }
 
     fn begin_movetext(&mut self, _tags: Self::Tags) -> ControlFlow<Self::Output, Self::Movetext> {
+        // without a setup position there is no puzzle
+        if self.options.puzzle_mode && self.start_fen.is_none() {
+            return ControlFlow::Break(Ok(0));
+        }
+        if let Some(termination) = &self.termination {
+            let mut dropped = self.options.drop_terminations.iter();
+            if dropped.any(|reason| reason.eq_ignore_ascii_case(termination)) {
+                return ControlFlow::Break(Ok(0));
+            }
+        }
         if let Err(e) = self.apply_start_fen() {
             return ControlFlow::Break(Err(e));
         }
+        if self.options.check_results {
+            self.count_repetition();
+        }
         ControlFlow::Continue(())
     }
 
@@ -307,6 +1195,17 @@ impl<'a, T: Write + Seek> Visitor for SfVisitor<'a, T> {
         _movetext: &mut Self::Movetext,
         san_plus: SanPlus,
     ) -> ControlFlow<Self::Output> {
+        if san_plus.san == San::Null {
+            return match self.options.on_null_move {
+                NullMovePolicy::Error => {
+                    ControlFlow::Break(Err(anyhow::anyhow!("null move in game")))
+                }
+                NullMovePolicy::SkipGame => ControlFlow::Break(Ok(0)),
+                // positions after a null move cannot be replayed, the game ends here
+                NullMovePolicy::StopGame => ControlFlow::Break(self.finish_game()),
+            };
+        }
+
         match san_plus.san.to_move(&self.chess) {
             Ok(mv) => {
                 if let Err(e) = self.handle_move(mv) {
@@ -331,12 +1230,16 @@ impl<'a, T: Write + Seek> Visitor for SfVisitor<'a, T> {
         _movetext: &mut Self::Movetext,
         comment: RawComment<'_>,
     ) -> ControlFlow<Self::Output> {
-        let c = match std::str::from_utf8(comment.0) {
+        if self.options.score_from == ScoreFrom::None || self.options.puzzle_mode {
+            return ControlFlow::Continue(());
+        }
+
+        let c = match util::decode_pgn_text(comment.0, self.options.encoding) {
             Ok(val) => val,
             Err(e) => return ControlFlow::Break(Err(anyhow::anyhow!(e))),
         };
 
-        if let Err(e) = self.attach_comment_eval(c) {
+        if let Err(e) = self.attach_comment_eval(&c) {
             return ControlFlow::Break(Err(e));
         }
 
@@ -351,12 +1254,172 @@ impl<'a, T: Write + Seek> Visitor for SfVisitor<'a, T> {
     }
 
     fn end_game(&mut self, _movetext: Self::Movetext) -> Self::Output {
-        Ok(self.moves)
+        self.finish_game()
+    }
+}
+
+/// Whether binpack can store a move: it only knows castles of a king on e1/e8 with a
+/// rook in a corner, which in Chess960 end on the same squares as in standard chess.
+fn is_storable_castle(mv: &Move) -> bool {
+    match *mv {
+        Move::Castle { king, rook } => matches!(
+            (king, rook),
+            (Square::E1, Square::A1 | Square::H1) | (Square::E8, Square::A8 | Square::H8)
+        ),
+        _ => true,
+    }
+}
+
+/// The castling rights of `pos` that binpack can store, see [`is_storable_castle`].
+fn storable_castling_rights(pos: &Chess) -> SfCastlingRights {
+    let storable = [
+        (Color::White, Square::H1, SfCastlingRights::WHITE_KING_SIDE),
+        (Color::White, Square::A1, SfCastlingRights::WHITE_QUEEN_SIDE),
+        (Color::Black, Square::H8, SfCastlingRights::BLACK_KING_SIDE),
+        (Color::Black, Square::A8, SfCastlingRights::BLACK_QUEEN_SIDE),
+    ];
+    let mut rights = SfCastlingRights::NONE;
+    for (color, rook, right) in storable {
+        let king = color.fold_wb(Square::E1, Square::E8);
+        if pos.castles().castling_rights().contains(rook)
+            && pos.board().king_of(color) == Some(king)
+        {
+            rights |= right;
+        }
+    }
+    rights
+}
+
+/// Sets up the sfbinpack board of a shakmaty position.
+fn binpack_board_of(pos: &Chess) -> Result<SfPosition> {
+    let fen = sfbinpack_fen(&Fen::from_position(pos, EnPassantMode::Legal).to_string())?;
+    let mut board = SfPosition::from_fen(&fen)
+        .map_err(|err| anyhow::anyhow!("sfbinpack rejected FEN {:?}: {err:?}", fen))?;
+    board.set_castling_rights(storable_castling_rights(pos));
+    Ok(board)
+}
+
+/// Checks a FEN up front for the fields sfbinpack's parser unwraps, filling in
+/// missing move counters with `0 1`.
+fn sfbinpack_fen(fen: &str) -> Result<String> {
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    if !(4..=6).contains(&fields.len()) {
+        bail!("FEN {:?} needs 4 to 6 fields", fen);
+    }
+    shakmaty::Board::from_ascii_board_fen(fields[0].as_bytes())
+        .with_context(|| format!("parsing board of FEN: {}", fen))?;
+    if !matches!(fields[1], "w" | "b") {
+        bail!("FEN {:?} has invalid side to move {:?}", fen, fields[1]);
+    }
+    if fields[3] != "-" && fields[3].parse::<Square>().is_err() {
+        bail!(
+            "FEN {:?} has invalid en passant square {:?}",
+            fen,
+            fields[3]
+        );
     }
+    let halfmove = fields.get(4).copied().unwrap_or("0");
+    let fullmove = fields.get(5).copied().unwrap_or("1");
+    // sfbinpack stores the clocks as u8 and u16
+    if halfmove.parse::<u8>().is_err() || fullmove.parse::<u16>().is_err() {
+        bail!("FEN {:?} has move counters out of range", fen);
+    }
+    Ok(format!(
+        "{} {} {} {} {} {}",
+        fields[0], fields[1], fields[2], fields[3], halfmove, fullmove
+    ))
+}
+
+/// Side to move, castling rights and en passant square of the entry's position,
+/// as sfbinpack renders them in its FEN.
+fn board_flags(entry: &TrainingDataEntry) -> Result<String> {
+    let fen = entry
+        .pos
+        .fen()
+        .map_err(|err| anyhow::anyhow!("failed to render FEN for entry: {err:?}"))?;
+    let fields: Vec<&str> = fen.split_whitespace().skip(1).take(3).collect();
+    if fields.len() != 3 {
+        bail!("entry FEN lacks side, castling or ep fields: {}", fen);
+    }
+    Ok(fields.join(" "))
+}
+
+/// Renders the entry's position as FEN and parses it back, failing with both FENs
+/// if sfbinpack does not reproduce the same position.
+fn verify_fen_roundtrip(entry: &TrainingDataEntry) -> Result<()> {
+    let fen = entry
+        .pos
+        .fen()
+        .map_err(|err| anyhow::anyhow!("failed to render FEN for entry: {err:?}"))?;
+    let parsed = SfPosition::from_fen(&fen)
+        .map_err(|err| anyhow::anyhow!("sfbinpack rejected its own FEN {:?}: {err:?}", fen))?;
+
+    if parsed != entry.pos {
+        let reparsed = parsed
+            .fen()
+            .map_err(|err| anyhow::anyhow!("failed to render reparsed FEN: {err:?}"))?;
+        bail!(
+            "FEN roundtrip changed the position at ply {}: {} became {}",
+            entry.ply,
+            fen,
+            reparsed
+        );
+    }
+
+    Ok(())
+}
+
+/// Replays the entry's move on the entry's position and checks that it
+/// leads to `expected`, the position the visitor reached with shakmaty.
+fn verify_entry(entry: &TrainingDataEntry, expected: &Chess) -> Result<()> {
+    let fen = entry
+        .pos
+        .fen()
+        .map_err(|err| anyhow::anyhow!("failed to render FEN for entry: {err:?}"))?;
+    let parsed =
+        Fen::from_ascii(fen.as_bytes()).with_context(|| format!("parsing entry FEN: {}", fen))?;
+    let mode = CastlingMode::detect(parsed.as_setup());
+    let mut pos: Chess = parsed
+        .into_position(mode)
+        .with_context(|| format!("creating position from entry FEN: {}", fen))?;
+
+    let mut uci = entry.mv.as_uci();
+    // as_uci moves the king two squares, which misses Chess960 castles; king onto
+    // rook is understood in either mode
+    if entry.mv.mtype() == SfMoveType::Castle {
+        let square = |index: u32| Square::new(index).to_string();
+        uci = format!(
+            "{}{}",
+            square(entry.mv.from().index()),
+            square(entry.mv.to().index())
+        );
+    }
+    let mv = uci
+        .parse::<UciMove>()
+        .ok()
+        .and_then(|uci| uci.to_move(&pos).ok())
+        .with_context(|| format!("move {} is not legal in {}", uci, fen))?;
+    pos.play_unchecked(mv);
+
+    let actual = Fen::from_position(&pos, EnPassantMode::Legal).to_string();
+    let expected = Fen::from_position(expected, EnPassantMode::Legal).to_string();
+    if actual != expected {
+        bail!(
+            "converted move {} from {} leads to {}, expected {}",
+            uci,
+            fen,
+            actual,
+            expected
+        );
+    }
+
+    Ok(())
 }
 
 struct ViriformatVisitor<'a, T: Write + Seek> {
     writer: &'a mut T,
+    encoding: PgnEncoding,
+    selector: GameSelector,
     start_fen: Option<String>,
     result: Option<GameOutcome>,
     chess: Chess,
@@ -368,9 +1431,11 @@ struct ViriformatVisitor<'a, T: Write + Seek> {
 }
 
 impl<'a, T: Write + Seek> ViriformatVisitor<'a, T> {
-    fn new(writer: &'a mut T) -> Self {
+    fn new(writer: &'a mut T, encoding: PgnEncoding, games: Option<Range<u64>>) -> Self {
         Self {
             writer,
+            encoding,
+            selector: GameSelector::new(games),
             start_fen: None,
             result: None,
             chess: Chess::default(),
@@ -478,6 +1543,9 @@ impl<'a, T: Write + Seek> Visitor for ViriformatVisitor<'a, T> {
 
     fn begin_tags(&mut self) -> ControlFlow<Self::Output, Self::Tags> {
         self.reset_game();
+        if !self.selector.select_next() {
+            return ControlFlow::Break(Ok(0));
+        }
         ControlFlow::Continue(())
     }
 
@@ -487,7 +1555,7 @@ impl<'a, T: Write + Seek> Visitor for ViriformatVisitor<'a, T> {
         name: &[u8],
         value: RawTag<'_>,
     ) -> ControlFlow<Self::Output> {
-        let n = match std::str::from_utf8(name) {
+        let n = match util::decode_pgn_text(name, self.encoding) {
             Ok(val) => val,
             Err(e) => {
                 return ControlFlow::Break(Err(anyhow::anyhow!(
@@ -498,7 +1566,7 @@ impl<'a, T: Write + Seek> Visitor for ViriformatVisitor<'a, T> {
             }
         };
 
-        let v = match std::str::from_utf8(value.0) {
+        let v = match util::decode_pgn_text(value.0, self.encoding) {
             Ok(val) => val,
             Err(e) => {
                 return ControlFlow::Break(Err(anyhow::anyhow!(
@@ -509,10 +1577,10 @@ impl<'a, T: Write + Seek> Visitor for ViriformatVisitor<'a, T> {
             }
         };
 
-        match n {
+        match n.as_ref() {
             "FEN" => self.start_fen = Some(v.to_string()),
             "Result" => {
-                self.result = match v {
+                self.result = match v.as_ref() {
                     "1-0" => Some(GameOutcome::WhiteWin(WinType::Adjudication)),
                     "0-1" => Some(GameOutcome::BlackWin(WinType::Adjudication)),
                     "1/2-1/2" => Some(GameOutcome::Draw(DrawType::Adjudication)),
@@ -568,12 +1636,12 @@ impl<'a, T: Write + Seek> Visitor for ViriformatVisitor<'a, T> {
         _movetext: &mut Self::Movetext,
         comment: RawComment<'_>,
     ) -> ControlFlow<Self::Output> {
-        let c = match std::str::from_utf8(comment.0) {
+        let c = match util::decode_pgn_text(comment.0, self.encoding) {
             Ok(val) => val,
             Err(e) => return ControlFlow::Break(Err(anyhow::anyhow!(e))),
         };
 
-        if let Err(e) = self.attach_comment_eval(c) {
+        if let Err(e) = self.attach_comment_eval(&c) {
             return ControlFlow::Break(Err(e));
         }
 
@@ -594,7 +1662,7 @@ impl<'a, T: Write + Seek> Visitor for ViriformatVisitor<'a, T> {
             ));
         }
 
-        let mut game = self
+        let game = self
             .game
             .take()
             .context("missing game state when finishing viriformat output")?;
@@ -604,3 +1672,801 @@ impl<'a, T: Write + Seek> Visitor for ViriformatVisitor<'a, T> {
         Ok(self.moves)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use sfbinpack::{
+        chess::{
+            coords::Square as SfSquare,
+            piece::Piece as SfPiece,
+            position::Position as SfPosition,
+            r#move::{Move as SfMove, MoveType as SfMoveType},
+        },
+        CompressedTrainingDataEntryReader, TrainingDataEntry,
+    };
+    use shakmaty::{Chess, Position, Square};
+
+    use super::{
+        verify_entry, BinpackBuilder, BuildOptions, ResultWeight, SidecarKind, PUZZLE_SOLVED_SCORE,
+    };
+    use crate::binpack::test_util::read_entries;
+    use crate::cli::{Backend, FenNormalization, NullMovePolicy, PgnEncoding, ScoreFrom};
+
+    fn convert(pgn: &str, options: BuildOptions) -> Vec<TrainingDataEntry> {
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            options,
+        );
+        builder
+            .create_binpack_from(pgn.as_bytes())
+            .expect("valid pgn");
+
+        let buffer = builder.into_inner().unwrap().into_inner();
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(buffer)).unwrap();
+        let mut entries = Vec::new();
+        while reader.has_next() {
+            entries.push(reader.next());
+        }
+        entries
+    }
+
+    const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    fn entry_with_move(from: Square, to: Square) -> TrainingDataEntry {
+        TrainingDataEntry {
+            pos: SfPosition::default(),
+            mv: SfMove::new(
+                SfSquare::new(from.to_u32()),
+                SfSquare::new(to.to_u32()),
+                SfMoveType::Normal,
+                SfPiece::none(),
+            ),
+            score: 0,
+            ply: 0,
+            result: 0,
+        }
+    }
+
+    fn after_e4() -> Chess {
+        let mut chess = Chess::default();
+        let mv = chess
+            .legal_moves()
+            .into_iter()
+            .find(|mv| mv.from() == Some(Square::E2) && mv.to() == Square::E4)
+            .expect("e2e4 is legal");
+        chess.play_unchecked(mv);
+        chess
+    }
+
+    #[test]
+    fn verify_accepts_matching_move() {
+        let entry = entry_with_move(Square::E2, Square::E4);
+        verify_entry(&entry, &after_e4()).expect("matching move");
+    }
+
+    #[test]
+    fn verify_rejects_corrupted_move() {
+        let entry = entry_with_move(Square::D2, Square::D4);
+        let err = verify_entry(&entry, &after_e4()).unwrap_err();
+        assert!(err.to_string().contains("d2d4"));
+    }
+
+    #[test]
+    fn cached_start_positions_match_fresh_ones() {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+        let pgn = format!(
+            "[Result \"*\"]\n\n1. e4 {{+0.10/10}} *\n\n\
+             [FEN \"{fen}\"]\n[Result \"*\"]\n\n1. e4 {{+0.20/10}} *\n\n\
+             [FEN \"{fen}\"]\n[Result \"*\"]\n\n1. Kd2 {{+0.30/10}} *\n\n\
+             [Result \"*\"]\n\n1. d4 {{+0.40/10}} *\n"
+        );
+
+        let entries = convert(&pgn, BuildOptions::default());
+        let fens: Vec<String> = entries.iter().map(|e| e.pos.fen().unwrap()).collect();
+
+        assert_eq!(fens, [START_FEN, fen, fen, START_FEN]);
+        assert_eq!(entries[2].mv.as_uci(), "e1d2");
+        assert_eq!(entries[3].mv.as_uci(), "d2d4");
+    }
+
+    #[test]
+    fn latin1_tags_parse_with_latin1_encoding() {
+        // "M\xfcller" is "Müller" in Latin-1 and not valid UTF-8
+        let pgn =
+            b"[White \"M\xfcller\"]\n[Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {-0.20/12} 1-0\n";
+
+        let build = |encoding| {
+            let options = BuildOptions {
+                encoding,
+                ..BuildOptions::default()
+            };
+            let mut builder = BinpackBuilder::new(
+                "test.pgn",
+                Cursor::new(Vec::new()),
+                Backend::Sfbinpack,
+                options,
+            );
+            builder
+                .create_binpack_from(&pgn[..])
+                .map(|_| builder.total_positions())
+        };
+
+        assert!(build(PgnEncoding::Utf8).is_err());
+        assert_eq!(build(PgnEncoding::Latin1).unwrap(), 2);
+    }
+
+    #[test]
+    fn start_fen_without_move_counters_is_handled() {
+        // shakmaty accepts FENs without halfmove and fullmove counters
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - -";
+        let pgn = format!("[FEN \"{fen}\"]\n[Result \"*\"]\n\n1. e4 {{+0.10/10}} *\n");
+
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            BuildOptions::default(),
+        );
+
+        // the counters default to `0 1` before sfbinpack sees the FEN
+        builder.create_binpack_from(pgn.as_bytes()).unwrap();
+        assert_eq!(builder.total_positions(), 1);
+
+        // a halfmove clock sfbinpack can't store fails the game instead of panicking
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 300 200";
+        let pgn = format!("[FEN \"{fen}\"]\n[Result \"*\"]\n\n1. e4 {{+0.10/10}} *\n");
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            BuildOptions::default(),
+        );
+        let err = builder.create_binpack_from(pgn.as_bytes()).unwrap_err();
+        assert!(format!("{err:#}").contains(fen), "{err:#}");
+    }
+
+    #[test]
+    fn score_from_none_ignores_comments() {
+        let pgn =
+            "[Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {not an eval} 2. Nf3 Nc6 {-0.10/12} 1-0\n";
+        let options = BuildOptions {
+            score_from: ScoreFrom::None,
+            ..BuildOptions::default()
+        };
+
+        let entries = convert(pgn, options);
+        let moves: Vec<String> = entries.iter().map(|e| e.mv.as_uci().to_string()).collect();
+
+        assert_eq!(moves, ["e2e4", "e7e5", "g1f3", "b8c6"]);
+        assert!(entries.iter().all(|e| e.score == 0));
+    }
+
+    #[test]
+    fn drops_games_below_eval_coverage() {
+        let pgn = "[Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {-0.20/12} 2. Nf3 {+0.25/12} Nc6 {-0.10/12} 1-0\n\n\
+                   [Result \"0-1\"]\n\n1. d4 {-1.20/12} d5 2. c4 {+0.10/12} e6 0-1\n";
+        let options = BuildOptions {
+            min_eval_coverage: Some(75.0),
+            ..BuildOptions::default()
+        };
+
+        assert_eq!(convert(pgn, BuildOptions::default()).len(), 6);
+
+        let entries = convert(pgn, options);
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].mv.as_uci(), "e2e4");
+        assert_eq!(entries[3].mv.as_uci(), "b8c6");
+    }
+
+    #[test]
+    fn histogram_counts_every_written_entry() {
+        let pgn =
+            "[Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {-0.20/12} 2. Nf3 {+M3/20} Nc6 {book} 1-0\n\n\
+                   [Result \"0-1\"]\n\n1. d4 {-1.20/12} d5 {No result} 0-1\n";
+        let options = BuildOptions {
+            eval_histogram: true,
+            ..BuildOptions::default()
+        };
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            options,
+        );
+        builder.create_binpack_from(pgn.as_bytes()).unwrap();
+
+        let histogram = builder.histogram().cloned().expect("histogram requested");
+        let buffer = builder.into_inner().unwrap().into_inner();
+        let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(buffer)).unwrap();
+        let mut written = 0;
+        while reader.has_next() {
+            reader.next();
+            written += 1;
+        }
+
+        assert_eq!(written, 5);
+        assert_eq!(histogram.total(), written);
+    }
+
+    #[test]
+    fn material_band_uses_piece_values() {
+        // four minor pieces (12) against a queen and a rook (14) on fewer squares
+        let pgn = "[FEN \"4k3/8/8/8/8/8/8/1NBBK1N1 w - - 0 1\"]\n[Result \"*\"]\n\n\
+                   1. Nf3 {+1.00/10} Kd7 {-1.00/10} *\n\n\
+                   [FEN \"4k3/8/8/8/8/8/8/R2QK3 w - - 0 1\"]\n[Result \"*\"]\n\n\
+                   1. Qd2 {+5.00/10} Kf7 {-5.00/10} *\n";
+        let moves = |min_material, max_material| {
+            let options = BuildOptions {
+                min_material,
+                max_material,
+                ..BuildOptions::default()
+            };
+            convert(pgn, options)
+                .iter()
+                .map(|e| e.mv.as_uci().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(moves(None, None).len(), 4);
+        assert_eq!(moves(Some(13), None), ["d1d2", "e8f7"]);
+        assert_eq!(moves(None, Some(12)), ["g1f3", "e8d7"]);
+    }
+
+    #[test]
+    fn puzzle_mode_writes_only_the_solution() {
+        let pgn = "[Result \"*\"]\n\n1. e4 e5 *\n\n\
+                   [FEN \"6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1\"]\n[Result \"*\"]\n\n\
+                   1. Ra8+ {the solution} *\n\n\
+                   [FEN \"r5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 0 1\"]\n[Result \"*\"]\n\n\
+                   1... Kf8 2. g3 Ra1+ *\n";
+        let options = BuildOptions {
+            puzzle_mode: true,
+            ..BuildOptions::default()
+        };
+
+        let entries = convert(pgn, options);
+        let moves: Vec<String> = entries.iter().map(|e| e.mv.as_uci().to_string()).collect();
+        let scores: Vec<i16> = entries.iter().map(|e| e.score).collect();
+
+        assert_eq!(moves, ["a1a8", "g8f8", "g2g3", "a8a1"]);
+        assert_eq!(scores, [PUZZLE_SOLVED_SCORE, 0, 0, PUZZLE_SOLVED_SCORE]);
+    }
+
+    #[test]
+    fn truncate_after_mate_drops_later_positions() {
+        let pgn =
+            "[Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {-0.20/12} 2. Qh5 {+M5/20} Nc6 {-M4/20} \
+                   3. Bc4 {+M3/20} Nf6 {-M2/20} 4. Qxf7# {+M1/20} 1-0\n\n\
+                   [Result \"*\"]\n\n1. d4 {+0.20/12} d5 {-0.10/12} *\n";
+        let options = BuildOptions {
+            truncate_after_mate: true,
+            ..BuildOptions::default()
+        };
+
+        assert_eq!(convert(pgn, BuildOptions::default()).len(), 9);
+
+        let moves: Vec<String> = convert(pgn, options)
+            .iter()
+            .map(|e| e.mv.as_uci().to_string())
+            .collect();
+        assert_eq!(moves, ["e2e4", "e7e5", "d1h5", "d2d4", "d7d5"]);
+    }
+
+    #[test]
+    fn centipawn_comments_trigger_the_unit_warning() {
+        let warns = |pgn: &str| {
+            let mut builder = BinpackBuilder::new(
+                "test.pgn",
+                Cursor::new(Vec::new()),
+                Backend::Sfbinpack,
+                BuildOptions::default(),
+            );
+            builder.create_binpack_from(pgn.as_bytes()).unwrap();
+            builder.eval_unit_warning()
+        };
+        let game = |white: &str, black: &str| {
+            format!(
+                "[Result \"*\"]\n\n1. Nf3 {{{white}/12}} Nf6 {{{black}/12}} 2. Ng1 {{{white}/12}} \
+                 Ng8 {{{black}/12}} 3. Nf3 {{{white}/12}} Nf6 {{{black}/12}} 4. Ng1 {{{white}/12}} \
+                 Ng8 {{{black}/12}} *\n"
+            )
+        };
+
+        assert!(warns(&game("+35", "-28")));
+        assert!(!warns(&game("+0.35", "-0.28")));
+        // too few evals to judge
+        assert!(!warns("[Result \"*\"]\n\n1. e4 {+35/12} e5 {-28/12} *\n"));
+    }
+
+    #[test]
+    fn null_moves_follow_the_policy() {
+        let pgn = "[Result \"*\"]\n\n1. e4 {+0.30/12} e5 {-0.20/12} 2. -- {+0.10/12} Nf6 {-0.10/12} *\n\n\
+                   [Result \"*\"]\n\n1. d4 {+0.20/12} -- {+0.20/12} 2. c4 {+0.30/12} *\n\n\
+                   [Result \"*\"]\n\n1. c4 {+0.10/12} *\n";
+        let moves = |on_null_move| {
+            let mut builder = BinpackBuilder::new(
+                "test.pgn",
+                Cursor::new(Vec::new()),
+                Backend::Sfbinpack,
+                BuildOptions {
+                    on_null_move,
+                    ..BuildOptions::default()
+                },
+            );
+            builder.create_binpack_from(pgn.as_bytes())?;
+            let buffer = builder.into_inner().unwrap().into_inner();
+            Ok::<_, anyhow::Error>(
+                read_entries(buffer)
+                    .iter()
+                    .map(|e| e.mv.as_uci().to_string())
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assert!(moves(NullMovePolicy::Error).is_err());
+        assert_eq!(moves(NullMovePolicy::SkipGame).unwrap(), ["c2c4"]);
+        assert_eq!(
+            moves(NullMovePolicy::StopGame).unwrap(),
+            ["e2e4", "e7e5", "d2d4", "c2c4"]
+        );
+    }
+
+    #[test]
+    fn result_weight_scales_by_outcome_for_the_side_to_move() {
+        let pgn = "[Result \"1/2-1/2\"]\n\n1. e4 {+0.30/12} e5 {-0.21/12} 1/2-1/2\n\n\
+                   [Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {-0.21/12} 1-0\n";
+        let options = BuildOptions {
+            result_weight: Some("2:0.5:0".parse().unwrap()),
+            ..BuildOptions::default()
+        };
+
+        let plain = convert(pgn, BuildOptions::default());
+        let weighted = convert(pgn, options);
+        let scaled = |score: i16, factor: f64| (f64::from(score) * factor).round() as i16;
+
+        assert_ne!(plain[1].score, 0);
+        // drawn game
+        assert_eq!(weighted[0].score, scaled(plain[0].score, 0.5));
+        assert_eq!(weighted[1].score, scaled(plain[1].score, 0.5));
+        // white won: white to move wins, black to move loses
+        assert_eq!(weighted[2].score, scaled(plain[2].score, 2.0));
+        assert_eq!(weighted[3].score, 0);
+
+        assert!("1:1".parse::<ResultWeight>().is_err());
+        assert!("1:-1:1".parse::<ResultWeight>().is_err());
+    }
+
+    #[test]
+    fn shakmaty_fen_normalization_drops_stale_castling_rights() {
+        // neither the a1 nor the h8 rook is left
+        let pgn = "[FEN \"r3k3/8/8/8/8/8/8/4K2R w KQkq - 0 1\"]\n[Result \"*\"]\n\n\
+                   1. Rh2 {+0.10/10} Ke7 {-0.10/10} *\n";
+
+        let mut raw = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            BuildOptions::default(),
+        );
+        assert!(raw.create_binpack_from(pgn.as_bytes()).is_err());
+
+        let entries = convert(
+            pgn,
+            BuildOptions {
+                fen_normalization: FenNormalization::Shakmaty,
+                ..BuildOptions::default()
+            },
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].pos.fen().unwrap(),
+            "r3k3/8/8/8/8/8/8/4K2R w Kq - 0 1"
+        );
+        assert_eq!(entries[1].pos.fen().unwrap().split(' ').nth(2), Some("q"));
+    }
+
+    #[test]
+    fn chess960_games_castle_king_onto_rook() {
+        let options = || BuildOptions {
+            verify_on_write: true,
+            ..BuildOptions::default()
+        };
+
+        // king on e1 with rooks in the corners: binpack stores these castles
+        let corners = "[Variant \"Chess960\"]\n\
+                       [FEN \"r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w HAha - 0 1\"]\n\
+                       [Result \"*\"]\n\n\
+                       1. O-O {+0.10/10} O-O-O {-0.10/10} 2. d4 {+0.20/10} *\n";
+        let entries = convert(corners, options());
+        assert_eq!(entries.len(), 3);
+        assert!(entries[..2]
+            .iter()
+            .all(|entry| entry.mv.mtype() == SfMoveType::Castle));
+        let squares = |entry: &TrainingDataEntry| (entry.mv.from().index(), entry.mv.to().index());
+        assert_eq!(
+            squares(&entries[0]),
+            (Square::E1.to_u32(), Square::H1.to_u32())
+        );
+        assert_eq!(
+            squares(&entries[1]),
+            (Square::E8.to_u32(), Square::A8.to_u32())
+        );
+        assert_eq!(
+            entries[2].pos.fen().unwrap(),
+            "2kr3r/pppppppp/8/8/8/8/PPPPPPPP/R4RK1 w - - 2 2"
+        );
+
+        // other castles are left out and the game goes on from the castled position
+        let pgn = "[Variant \"Chess960\"]\n\
+                   [FEN \"rk2r3/pppppppp/8/8/8/8/PPPPPPPP/RK2R3 w EAea - 0 1\"]\n\
+                   [Result \"*\"]\n\n\
+                   1. O-O {+0.10/10} O-O-O {-0.10/10} 2. d4 {+0.20/10} *\n";
+        let entries = convert(pgn, options());
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].pos.fen().unwrap(),
+            "2krr3/pppppppp/8/8/8/8/PPPPPPPP/R4RK1 w - - 2 2"
+        );
+
+        for entry in &entries {
+            let fen = entry.pos.fen().unwrap();
+            assert_eq!(SfPosition::from_fen(&fen).unwrap(), entry.pos);
+        }
+
+        // the same setup without the tag is recognized by its castling field
+        let untagged = pgn.replacen("[Variant \"Chess960\"]\n", "", 1);
+        assert_eq!(convert(&untagged, BuildOptions::default()).len(), 1);
+    }
+
+    #[test]
+    fn drop_first_eval_skips_the_first_scored_position_per_game() {
+        let pgn = "[Result \"*\"]\n\n1. e4 {book} e5 {-0.21/12} 2. Nf3 {+0.30/12} *\n\n\
+                   [Result \"*\"]\n\n1. d4 d5 {0.00/1} 2. c4 {+0.40/12} *\n";
+        let options = BuildOptions {
+            drop_first_eval: true,
+            ..BuildOptions::default()
+        };
+
+        assert_eq!(convert(pgn, BuildOptions::default()).len(), 5);
+        let plies: Vec<u16> = convert(pgn, options).iter().map(|e| e.ply).collect();
+        // 1. e4 of the first game and 1... d5 of the second are gone
+        assert_eq!(plies, [1, 2, 2]);
+    }
+
+    #[test]
+    fn skip_errors_drops_only_the_failing_game() {
+        let pgn = "[Result \"*\"]\n\n1. e4 {+0.20/12} e5 {-0.20/12} 2. Ke3 {+0.10/12} *\n\n\
+                   [Result \"*\"]\n\n1. d4 {+0.30/12} d5 {-0.30/12} *\n";
+        let options = BuildOptions {
+            skip_errors: true,
+            ..BuildOptions::default()
+        };
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            options,
+        );
+        builder.create_binpack_from(pgn.as_bytes()).unwrap();
+
+        assert_eq!(builder.skipped_games(), 1);
+        assert_eq!(builder.total_games(), 1);
+        let moves: Vec<String> = read_entries(builder.into_inner().unwrap().into_inner())
+            .iter()
+            .map(|e| e.mv.as_uci().to_string())
+            .collect();
+        // the moves of the failing game before 2. Ke3 are not written either
+        assert_eq!(moves, ["d2d4", "d7d5"]);
+
+        let mut strict = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            BuildOptions::default(),
+        );
+        assert!(strict.create_binpack_from(pgn.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn clock_sidecar_lines_follow_the_entries() {
+        let pgn = "[Result \"*\"]\n\n\
+                   1. e4 { [%eval 0.2] [%clk 0:05:00] } 1... e5 { [%clk 0:04:58] } \
+                   2. Nf3 { [%eval 0.3] [%clk 0:04:55] } 2... Nc6 { [%eval -0.3] } \
+                   3. Bb5 { [%eval 0.25] [%clk 4:41] } *\n";
+        let options = BuildOptions {
+            sidecars: vec![SidecarKind::Clock],
+            ..BuildOptions::default()
+        };
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            options,
+        );
+        builder.create_binpack_from(pgn.as_bytes()).unwrap();
+
+        let clocks = String::from_utf8(builder.take_sidecars().remove(0)).unwrap();
+        let moves: Vec<String> = read_entries(builder.into_inner().unwrap().into_inner())
+            .iter()
+            .map(|e| e.mv.as_uci().to_string())
+            .collect();
+
+        // 1... e5 carries no eval and is not written
+        assert_eq!(moves, ["e2e4", "g1f3", "b8c6", "f1b5"]);
+        assert_eq!(
+            clocks.lines().collect::<Vec<_>>(),
+            ["300", "295", "", "281"]
+        );
+    }
+
+    #[test]
+    fn keep_unscored_writes_every_move() {
+        let pgn = "[Result \"1-0\"]\n\n1. e4 e5 2. Nf3 Nc6 3. Bb5 1-0\n\n\
+                   [Result \"*\"]\n\n1. d4 d5 {-0.20/12} 2. c4 *\n";
+
+        assert_eq!(convert(pgn, BuildOptions::default()).len(), 1);
+
+        let options = BuildOptions {
+            keep_unscored: Some(0),
+            ..BuildOptions::default()
+        };
+        let entries = convert(pgn, options);
+        // one entry per move, the last move of each game included
+        assert_eq!(entries.len(), 8);
+        let plies: Vec<u16> = entries.iter().map(|e| e.ply).collect();
+        assert_eq!(plies, [0, 1, 2, 3, 4, 0, 1, 2]);
+        assert!(entries[..5].iter().all(|e| e.score == 0));
+        assert_ne!(entries[6].score, 0);
+    }
+
+    #[test]
+    fn draw_dampen_scales_only_drawn_games() {
+        let pgn = "[Result \"1/2-1/2\"]\n\n1. e4 {+0.30/12} e5 {-0.21/12} 1/2-1/2\n\n\
+                   [Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {-0.21/12} 1-0\n";
+        let options = BuildOptions {
+            draw_dampen: Some(0.5),
+            ..BuildOptions::default()
+        };
+
+        let plain: Vec<i16> = convert(pgn, BuildOptions::default())
+            .iter()
+            .map(|e| e.score)
+            .collect();
+        let dampened: Vec<i16> = convert(pgn, options).iter().map(|e| e.score).collect();
+
+        assert_ne!(plain[0], 0);
+        let halved = |score: i16| (f64::from(score) * 0.5).round() as i16;
+        assert_eq!(dampened[..2], [halved(plain[0]), halved(plain[1])]);
+        assert_eq!(dampened[2..], plain[2..]);
+    }
+
+    #[test]
+    fn collapse_equal_scores_keeps_the_first_of_a_run() {
+        let pgn = "[Result \"*\"]\n\n1. Nf3 {+0.20/12} Nf6 {+0.20/12} 2. Ng1 {+0.20/12} \
+                   Ng8 {+0.35/12} 3. e4 {+0.20/12} *\n";
+        let options = BuildOptions {
+            collapse_equal_scores: true,
+            ..BuildOptions::default()
+        };
+
+        let plain = convert(pgn, BuildOptions::default());
+        let collapsed = convert(pgn, options);
+        let moves: Vec<String> = collapsed
+            .iter()
+            .map(|e| e.mv.as_uci().to_string())
+            .collect();
+
+        assert_eq!(plain.len(), 5);
+        assert_eq!(plain[0].score, plain[2].score);
+        assert_eq!(moves, ["g1f3", "f6g8", "e2e4"]);
+    }
+
+    #[test]
+    fn check_results_reports_contradicted_results() {
+        let repeated = "1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8";
+        let pgn = format!(
+            "[Result \"1-0\"]\n\n{repeated} 1-0\n\n\
+             [Result \"1/2-1/2\"]\n\n{repeated} 1/2-1/2\n\n\
+             [Result \"0-1\"]\n\n1. f3 e5 2. g4 Qh4# 0-1\n\n\
+             [Result \"1/2-1/2\"]\n\n1. f3 e5 2. g4 Qh4# 1/2-1/2\n"
+        );
+        let options = BuildOptions {
+            check_results: true,
+            ..BuildOptions::default()
+        };
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            options,
+        );
+        builder.create_binpack_from(pgn.as_bytes()).unwrap();
+
+        let mismatches: Vec<_> = builder
+            .result_mismatches()
+            .iter()
+            .map(|m| (m.game, m.result.as_str(), m.reason))
+            .collect();
+        assert_eq!(
+            mismatches,
+            [
+                (0, "1-0", "by threefold repetition"),
+                (3, "1/2-1/2", "in checkmate")
+            ]
+        );
+    }
+
+    #[test]
+    fn fen_roundtrip_passes_on_normal_games() {
+        let pgn = "[Result \"1-0\"]\n\n1. e4 {+0.30/12} d5 {-0.20/12} 2. exd5 {+0.40/12} \
+                   c5 {-0.30/12} 3. dxc6 {+0.90/12} 1-0\n\n\
+                   [Result \"*\"]\n[FEN \"r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 4 30\"]\n\n\
+                   30. O-O {+0.10/12} O-O-O {-0.10/12} *\n";
+        let options = BuildOptions {
+            verify_fen_roundtrip: true,
+            ..BuildOptions::default()
+        };
+
+        assert_eq!(convert(pgn, options), convert(pgn, BuildOptions::default()));
+        assert_eq!(convert(pgn, BuildOptions::default()).len(), 7);
+    }
+
+    #[test]
+    fn eval_before_its_move_is_applied_to_that_move() {
+        let after = "[Result \"*\"]\n\n1. e4 {+0.30/12} e5 {-0.20/12} *\n";
+        let before = "[Result \"*\"]\n\n{+0.30/12} 1. e4 e5 {-0.20/12} *\n";
+
+        let entries = convert(before, BuildOptions::default());
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries, convert(after, BuildOptions::default()));
+    }
+
+    #[test]
+    fn second_eval_after_a_move_is_ignored() {
+        let twice = "[Result \"*\"]\n\n1. e4 {+0.3} {+0.5} e5 {-0.2} *\n";
+        let once = "[Result \"*\"]\n\n1. e4 {+0.3} e5 {-0.2} *\n";
+
+        let entries = convert(twice, BuildOptions::default());
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries, convert(once, BuildOptions::default()));
+    }
+
+    #[test]
+    fn dropped_terminations_skip_games() {
+        let pgn = "[Result \"1-0\"]\n[Termination \"Normal\"]\n\n1. e4 {+0.30/12} 1-0\n\n\
+                   [Result \"0-1\"]\n[Termination \"Abandoned\"]\n\n1. d4 {+0.20/12} 0-1\n\n\
+                   [Result \"*\"]\n\n1. c4 {+0.10/12} *\n";
+        let options = BuildOptions {
+            sidecars: vec![SidecarKind::Termination],
+            drop_terminations: vec!["abandoned".to_string()],
+            ..BuildOptions::default()
+        };
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            options,
+        );
+        builder.create_binpack_from(pgn.as_bytes()).unwrap();
+
+        let terminations = String::from_utf8(builder.take_sidecars().remove(0)).unwrap();
+        let moves: Vec<String> = read_entries(builder.into_inner().unwrap().into_inner())
+            .iter()
+            .map(|e| e.mv.as_uci().to_string())
+            .collect();
+
+        assert_eq!(moves, ["e2e4", "c2c4"]);
+        assert_eq!(
+            terminations.lines().collect::<Vec<_>>(),
+            ["Normal", "unknown"]
+        );
+    }
+
+    #[test]
+    fn board_flags_sidecar_follows_the_start_fen() {
+        let pgn = "[FEN \"r3k2r/8/8/8/4p3/8/3P4/R3K2R w Kq - 0 1\"]\n[Result \"*\"]\n\n\
+                   1. d4 {+0.10/12} Rb8 {-0.10/12} 2. Kf1 {+0.10/12} *\n";
+        let options = BuildOptions {
+            sidecars: vec![SidecarKind::BoardFlags],
+            ..BuildOptions::default()
+        };
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            options,
+        );
+        builder.create_binpack_from(pgn.as_bytes()).unwrap();
+
+        let flags = String::from_utf8(builder.take_sidecars().remove(0)).unwrap();
+        assert_eq!(
+            flags.lines().collect::<Vec<_>>(),
+            ["w Kq -", "b Kq d3", "w K -"]
+        );
+    }
+
+    #[test]
+    fn legal_moves_sidecar_counts_moves_of_each_entry() {
+        let pgn = "[Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {-0.20/12} 2. Qh5 {+0.10/12} 1-0\n";
+        let options = BuildOptions {
+            sidecars: vec![SidecarKind::Wdl, SidecarKind::LegalMoves],
+            ..BuildOptions::default()
+        };
+        let mut builder = BinpackBuilder::new(
+            "test.pgn",
+            Cursor::new(Vec::new()),
+            Backend::Sfbinpack,
+            options,
+        );
+        builder.create_binpack_from(pgn.as_bytes()).unwrap();
+
+        let sidecars = builder.take_sidecars();
+        let legal = String::from_utf8(sidecars[1].clone()).unwrap();
+
+        assert_eq!(sidecars.len(), 2);
+        assert_eq!(String::from_utf8_lossy(&sidecars[0]).lines().count(), 3);
+        assert_eq!(legal.lines().collect::<Vec<_>>(), ["20", "20", "29"]);
+    }
+
+    #[test]
+    fn plies_count_from_the_fen_move_number() {
+        let plies_of = |fen: Option<&str>, moves: &str| {
+            let tag = fen.map_or(String::new(), |fen| format!("[FEN \"{fen}\"]\n"));
+            let pgn = format!("{tag}[Result \"*\"]\n\n{moves} *\n");
+            let entries = convert(&pgn, BuildOptions::default());
+            entries.iter().map(|e| e.ply).collect::<Vec<_>>()
+        };
+
+        assert_eq!(plies_of(None, "1. e4 {+0.10/10} e5 {-0.10/10}"), [0, 1]);
+        assert_eq!(
+            plies_of(
+                Some("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"),
+                "3. Bb5 {+0.30/10} a6 {-0.30/10}"
+            ),
+            [4, 5]
+        );
+        // black to move in move 2
+        assert_eq!(
+            plies_of(
+                Some("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"),
+                "2... Nc6 {-0.20/10} 3. Bb5 {+0.30/10}"
+            ),
+            [3, 4]
+        );
+        // counters left out count as move 1
+        assert_eq!(
+            plies_of(Some("4k3/8/8/8/8/8/4P3/4K3 b - -"), "1... Kd7 {+0.50/10}"),
+            [1]
+        );
+    }
+
+    #[test]
+    fn reservoir_keeps_n_positions_per_game() {
+        // 100 plies of knight moves, all scored
+        let moves = "Nf3 {+0.10/10} Nf6 {-0.10/10} Ng1 {+0.10/10} Ng8 {-0.10/10} ".repeat(25);
+        let pgn = format!("[Result \"*\"]\n\n{moves}*\n");
+        let plies = |seed| {
+            let options = BuildOptions {
+                reservoir_per_game: Some(10),
+                seed,
+                ..BuildOptions::default()
+            };
+            convert(&pgn, options)
+                .iter()
+                .map(|e| e.ply)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(convert(&pgn, BuildOptions::default()).len(), 100);
+
+        let sample = plies(7);
+        assert_eq!(sample.len(), 10);
+        assert!(sample.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(plies(7), sample);
+        assert_ne!(plies(8), sample);
+    }
+}
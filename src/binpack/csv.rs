@@ -0,0 +1,122 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use sfbinpack::CompressedTrainingDataEntryReader;
+
+/// Header of the exported CSV.
+pub const CSV_HEADER: &str = "index,fen,uci,score,ply,result,is_continuation";
+
+/// Writes every entry of a binpack as a CSV row. Returns the number of rows.
+pub fn export_csv<R, W>(input: R, out: &mut W) -> Result<u64>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    let mut reader = CompressedTrainingDataEntryReader::new(input)?;
+    let mut count = 0u64;
+
+    writeln!(out, "{}", CSV_HEADER)?;
+    while reader.has_next() {
+        let is_continuation = count > 0 && reader.is_next_entry_continuation();
+        let entry = reader.next();
+        let fen = entry
+            .pos
+            .fen()
+            .map_err(|err| anyhow::anyhow!("failed to render FEN for entry: {err:?}"))?;
+
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{}",
+            count,
+            quote(&fen),
+            entry.mv.as_uci(),
+            entry.score,
+            entry.ply,
+            entry.result,
+            is_continuation
+        )?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Runs [`export_csv`] from a binpack file into a CSV file.
+pub fn export_csv_file(input: &Path, output: &Path) -> Result<u64> {
+    let reader = BufReader::new(
+        File::open(input).with_context(|| format!("opening binpack {}", input.display()))?,
+    );
+    let mut writer = BufWriter::new(
+        File::create(output).with_context(|| format!("creating {}", output.display()))?,
+    );
+    let count = export_csv(reader, &mut writer)?;
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Quotes a field that contains separators, spaces or quotes, doubling inner quotes.
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', ' ', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{export_csv, CSV_HEADER};
+    use crate::binpack::test_util::{game_entries, write_entries};
+
+    /// Splits a CSV line, honouring quoted fields.
+    fn fields(line: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(String::new()),
+                _ => fields.last_mut().unwrap().push(c),
+            }
+        }
+        fields
+    }
+
+    #[test]
+    fn csv_rows_round_trip_entries() {
+        let mut entries = game_entries(&[(12, 28, 35), (52, 36, -20)], 1);
+        entries.extend(game_entries(&[(11, 27, 7)], -1));
+
+        let mut out = Vec::new();
+        let count = export_csv(Cursor::new(write_entries(&entries)), &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let mut lines = csv.lines();
+
+        assert_eq!(count, 3);
+        assert_eq!(lines.next(), Some(CSV_HEADER));
+
+        for (i, (line, entry)) in lines.zip(&entries).enumerate() {
+            let row = fields(line);
+            assert_eq!(row.len(), 7, "{line}");
+            assert_eq!(row[0], i.to_string());
+            assert_eq!(row[1], entry.pos.fen().unwrap());
+            assert_eq!(row[2], entry.mv.as_uci());
+            assert_eq!(row[3], entry.score.to_string());
+            assert_eq!(row[4], entry.ply.to_string());
+            assert_eq!(row[5], entry.result.to_string());
+            assert_eq!(row[6], (i == 1).to_string());
+        }
+    }
+}
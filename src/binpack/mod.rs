@@ -1,3 +1,7 @@
 pub mod builder;
+pub mod csv;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub mod transform;
 
-pub use builder::BinpackBuilder;
+pub use builder::{BinpackBuilder, BuildOptions, EntryBudget};
@@ -0,0 +1,61 @@
+use std::io::Cursor;
+
+use sfbinpack::{
+    chess::{
+        coords::Square as SfSquare,
+        piece::Piece as SfPiece,
+        position::Position as SfPosition,
+        r#move::{Move as SfMove, MoveType as SfMoveType},
+    },
+    CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter, TrainingDataEntry,
+};
+
+pub fn sf_move(from: u32, to: u32) -> SfMove {
+    SfMove::new(
+        SfSquare::new(from),
+        SfSquare::new(to),
+        SfMoveType::Normal,
+        SfPiece::none(),
+    )
+}
+
+/// Entries of a game from the start position; moves are `(from, to, score)` with
+/// square indices a1 = 0 .. h8 = 63.
+pub fn game_entries(moves: &[(u32, u32, i16)], result: i16) -> Vec<TrainingDataEntry> {
+    let mut pos = SfPosition::default();
+    let mut entries = Vec::new();
+
+    for (i, &(from, to, score)) in moves.iter().enumerate() {
+        let mv = sf_move(from, to);
+        entries.push(TrainingDataEntry {
+            pos,
+            mv,
+            score,
+            ply: pos.ply(),
+            result: if i % 2 == 0 { result } else { -result },
+        });
+        pos.do_move(mv);
+    }
+
+    entries
+}
+
+pub fn write_entries(entries: &[TrainingDataEntry]) -> Vec<u8> {
+    let mut buffer = Cursor::new(Vec::new());
+    {
+        let mut writer = CompressedTrainingDataEntryWriter::new(&mut buffer).unwrap();
+        for entry in entries {
+            writer.write_entry(entry).unwrap();
+        }
+    }
+    buffer.into_inner()
+}
+
+pub fn read_entries(bytes: Vec<u8>) -> Vec<TrainingDataEntry> {
+    let mut reader = CompressedTrainingDataEntryReader::new(Cursor::new(bytes)).unwrap();
+    let mut entries = Vec::new();
+    while reader.has_next() {
+        entries.push(reader.next());
+    }
+    entries
+}
@@ -0,0 +1,1054 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context, Result};
+use sfbinpack::{
+    chess::color::Color as SfColor, CompressedTrainingDataEntryReader,
+    CompressedTrainingDataEntryWriter, TrainingDataEntry,
+};
+use shakmaty::{fen::Fen, CastlingMode, Chess};
+
+use crate::analytics::VALUE_NONE_SCORE;
+use crate::cli::ScoreDomain;
+use crate::util::rng::{shuffle, SplitMix64};
+use crate::wdl::wdl::{
+    external_cp_to_internal, internal_to_external_cp, material_count, MATE_SCORE_THRESHOLD,
+};
+
+/// Streams every entry of `input` through `f` into a new binpack written to `output`.
+/// Returns the number of entries written.
+pub fn transform_binpack<R, W, F>(input: R, output: W, mut f: F) -> Result<u64>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+    F: FnMut(&mut TrainingDataEntry),
+{
+    let mut reader = CompressedTrainingDataEntryReader::new(input)?;
+    let mut writer =
+        CompressedTrainingDataEntryWriter::new(output).context("creating binpack writer")?;
+    let mut count = 0u64;
+
+    while reader.has_next() {
+        let mut entry = reader.next();
+        f(&mut entry);
+        writer
+            .write_entry(&entry)
+            .context("writing entry to binpack")?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Visits every entry of a binpack without writing anything.
+pub fn for_each_entry<R, F>(input: R, mut f: F) -> Result<u64>
+where
+    R: Read + Seek,
+    F: FnMut(&TrainingDataEntry),
+{
+    let mut reader = CompressedTrainingDataEntryReader::new(input)?;
+    let mut count = 0u64;
+
+    while reader.has_next() {
+        f(&reader.next());
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Runs [`transform_binpack`] from one binpack file into another.
+pub fn transform_file<F>(input: &Path, output: &Path, f: F) -> Result<u64>
+where
+    F: FnMut(&mut TrainingDataEntry),
+{
+    let reader = BufReader::new(
+        File::open(input).with_context(|| format!("opening binpack {}", input.display()))?,
+    );
+    let writer = BufWriter::new(
+        File::create(output).with_context(|| format!("creating {}", output.display()))?,
+    );
+    transform_binpack(reader, writer, f)
+}
+
+/// Reads the next game, i.e. the next chain of continuation entries.
+fn next_game<R: Read + Seek>(
+    reader: &mut CompressedTrainingDataEntryReader<R>,
+) -> Option<Vec<TrainingDataEntry>> {
+    if !reader.has_next() {
+        return None;
+    }
+
+    let mut game = vec![reader.next()];
+    while reader.has_next() && reader.is_next_entry_continuation() {
+        game.push(reader.next());
+    }
+    Some(game)
+}
+
+/// Writes the games of all inputs round-robin, one game per input per turn,
+/// until every input is exhausted. Returns the number of entries written.
+pub fn interleave_binpacks<R, W>(inputs: Vec<R>, output: W) -> Result<u64>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    let mut readers = inputs
+        .into_iter()
+        .map(CompressedTrainingDataEntryReader::new)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut writer =
+        CompressedTrainingDataEntryWriter::new(output).context("creating binpack writer")?;
+    let mut count = 0u64;
+
+    while !readers.is_empty() {
+        // readers that ran out of games drop out of the rotation
+        let mut exhausted = Vec::new();
+
+        for (i, reader) in readers.iter_mut().enumerate() {
+            let Some(game) = next_game(reader) else {
+                exhausted.push(i);
+                continue;
+            };
+
+            for entry in &game {
+                writer
+                    .write_entry(entry)
+                    .context("writing entry to binpack")?;
+            }
+            count += game.len() as u64;
+        }
+
+        for i in exhausted.into_iter().rev() {
+            readers.remove(i);
+        }
+    }
+
+    Ok(count)
+}
+
+/// Runs [`interleave_binpacks`] over binpack files.
+pub fn interleave_files(inputs: &[PathBuf], output: &Path) -> Result<u64> {
+    let readers = inputs
+        .iter()
+        .map(|path| open_binpack(path))
+        .collect::<Result<Vec<_>>>()?;
+    let writer = BufWriter::new(
+        File::create(output).with_context(|| format!("creating {}", output.display()))?,
+    );
+    interleave_binpacks(readers, writer)
+}
+
+/// Field that sorted binpacks are ordered by, ascending.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    /// Material of the position (P=1 N=3 B=3 R=5 Q=9, both sides)
+    Material,
+    Ply,
+    Score,
+}
+
+impl SortKey {
+    pub fn of(&self, entry: &TrainingDataEntry) -> Result<i32> {
+        match self {
+            SortKey::Material => Ok(material_count(&entry_position(entry)?)),
+            SortKey::Ply => Ok(i32::from(entry.ply)),
+            SortKey::Score => Ok(i32::from(entry.score)),
+        }
+    }
+}
+
+/// The entry's position as a shakmaty board.
+fn entry_position(entry: &TrainingDataEntry) -> Result<Chess> {
+    let fen = entry
+        .pos
+        .fen()
+        .map_err(|err| anyhow::anyhow!("failed to render FEN for entry: {err:?}"))?;
+    Fen::from_ascii(fen.as_bytes())
+        .with_context(|| format!("parsing entry FEN: {}", fen))?
+        .into_position(CastlingMode::Chess960)
+        .with_context(|| format!("creating position from FEN: {}", fen))
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "material" => Ok(SortKey::Material),
+            "ply" => Ok(SortKey::Ply),
+            "score" => Ok(SortKey::Score),
+            _ => Err(format!(
+                "unknown sort key {:?}, expected material, ply or score",
+                s
+            )),
+        }
+    }
+}
+
+/// Merges binpacks that are each sorted by `key` into one sorted output. Games
+/// are kept whole and ordered by the key of their first entry; ties go to the
+/// earlier input. Returns the number of entries written.
+pub fn merge_sorted_binpacks<R, W>(inputs: Vec<R>, output: W, key: SortKey) -> Result<u64>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    let mut readers = inputs
+        .into_iter()
+        .map(CompressedTrainingDataEntryReader::new)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut writer =
+        CompressedTrainingDataEntryWriter::new(output).context("creating binpack writer")?;
+
+    // the next game of every input, and a min-heap of (key, input) over them
+    let mut pending: Vec<Option<Vec<TrainingDataEntry>>> = Vec::with_capacity(readers.len());
+    let mut heap = BinaryHeap::new();
+    for (i, reader) in readers.iter_mut().enumerate() {
+        let game = next_game(reader);
+        if let Some(game) = &game {
+            heap.push(Reverse((key.of(&game[0])?, i)));
+        }
+        pending.push(game);
+    }
+
+    let mut count = 0u64;
+    while let Some(Reverse((current, i))) = heap.pop() {
+        let game = pending[i].take().expect("queued input has a pending game");
+        for entry in &game {
+            writer
+                .write_entry(entry)
+                .context("writing entry to binpack")?;
+        }
+        count += game.len() as u64;
+
+        if let Some(next) = next_game(&mut readers[i]) {
+            let next_key = key.of(&next[0])?;
+            if next_key < current {
+                anyhow::bail!("input {} is not sorted by {:?}", i + 1, key);
+            }
+            heap.push(Reverse((next_key, i)));
+            pending[i] = Some(next);
+        }
+    }
+
+    Ok(count)
+}
+
+/// Runs [`merge_sorted_binpacks`] over binpack files.
+pub fn merge_sorted_files(inputs: &[PathBuf], output: &Path, key: SortKey) -> Result<u64> {
+    let readers = inputs
+        .iter()
+        .map(|path| open_binpack(path))
+        .collect::<Result<Vec<_>>>()?;
+    let writer = BufWriter::new(
+        File::create(output).with_context(|| format!("creating {}", output.display()))?,
+    );
+    merge_sorted_binpacks(readers, writer, key)
+}
+
+/// Shuffles the games of a binpack in two passes so that only one shard has to
+/// fit in memory: games are first spread over `shards` temp files at random,
+/// then the shards are read in random order and each is shuffled on its own.
+/// Returns the number of entries written.
+pub fn shuffle_binpack<R, W>(
+    input: R,
+    output: W,
+    shards: usize,
+    seed: u64,
+    tmp_dir: Option<&Path>,
+) -> Result<u64>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    let mut rng = SplitMix64::new(seed);
+    let mut files = (0..shards.max(1))
+        .map(|_| {
+            match tmp_dir {
+                Some(dir) => tempfile::tempfile_in(dir),
+                None => tempfile::tempfile(),
+            }
+            .context("creating shuffle shard")
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // pass 1: distribute the games
+    {
+        let mut reader = CompressedTrainingDataEntryReader::new(input)?;
+        let mut writers = files
+            .iter_mut()
+            .map(|file| CompressedTrainingDataEntryWriter::new(BufWriter::new(file)))
+            .collect::<Result<Vec<_>, _>>()
+            .context("creating shard writer")?;
+
+        while let Some(game) = next_game(&mut reader) {
+            let shard = rng.below(writers.len() as u64) as usize;
+            for entry in &game {
+                writers[shard]
+                    .write_entry(entry)
+                    .context("writing entry to shard")?;
+            }
+        }
+    }
+
+    // pass 2: shuffle within the shards, visited in random order
+    shuffle(&mut files, &mut rng);
+    let mut writer =
+        CompressedTrainingDataEntryWriter::new(output).context("creating binpack writer")?;
+    let mut count = 0u64;
+
+    for mut file in files {
+        file.seek(SeekFrom::Start(0))?;
+        if file.metadata()?.len() == 0 {
+            continue;
+        }
+
+        let mut reader = CompressedTrainingDataEntryReader::new(BufReader::new(file))?;
+        let mut games = Vec::new();
+        while let Some(game) = next_game(&mut reader) {
+            games.push(game);
+        }
+
+        shuffle(&mut games, &mut rng);
+        for entry in games.iter().flatten() {
+            writer
+                .write_entry(entry)
+                .context("writing entry to binpack")?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Runs [`shuffle_binpack`] from one binpack file into another.
+pub fn shuffle_file(
+    input: &Path,
+    output: &Path,
+    shards: usize,
+    seed: u64,
+    tmp_dir: Option<&Path>,
+) -> Result<u64> {
+    let writer = BufWriter::new(
+        File::create(output).with_context(|| format!("creating {}", output.display()))?,
+    );
+    shuffle_binpack(open_binpack(input)?, writer, shards, seed, tmp_dir)
+}
+
+/// Size of the header in front of every chunk of a binpack.
+const CHUNK_HEADER_BYTES: u64 = 8;
+
+/// Encoded size of a game without its chunk header, measured by writing it on its own.
+fn encoded_game_bytes(game: &[TrainingDataEntry]) -> Result<u64> {
+    let mut writer = CompressedTrainingDataEntryWriter::new(Cursor::new(Vec::new()))
+        .context("creating scratch writer")?;
+    for entry in game {
+        writer.write_entry(entry).context("encoding game")?;
+    }
+    writer.flush().context("encoding game")?;
+    let bytes = writer.into_inner()?.into_inner().len() as u64;
+    Ok(bytes - CHUNK_HEADER_BYTES)
+}
+
+/// Splits the games of a binpack over shards opened by `open_shard(index)`. Games are
+/// never split: a shard moves on to the next one before a game would take it past
+/// `max_bytes`, so only a game larger than `max_bytes` on its own overshoots. Each
+/// game is measured by encoding it separately, which ignores the header of every
+/// further 1 MiB chunk. Returns the number of entries per shard.
+pub fn split_binpack<R, W, F>(input: R, max_bytes: u64, mut open_shard: F) -> Result<Vec<u64>>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+    F: FnMut(usize) -> Result<W>,
+{
+    let mut reader = CompressedTrainingDataEntryReader::new(input)?;
+    let mut writer = None;
+    let mut shard_bytes = 0;
+    let mut counts: Vec<u64> = Vec::new();
+
+    while let Some(game) = next_game(&mut reader) {
+        let game_bytes = encoded_game_bytes(&game)?;
+        if writer.is_none() || shard_bytes + game_bytes > max_bytes {
+            // dropping the writer flushes the previous shard
+            drop(writer.take());
+            writer = Some(
+                CompressedTrainingDataEntryWriter::new(open_shard(counts.len())?)
+                    .context("creating shard writer")?,
+            );
+            shard_bytes = CHUNK_HEADER_BYTES;
+            counts.push(0);
+        }
+        shard_bytes += game_bytes;
+
+        let shard = writer.as_mut().expect("a shard is open");
+        for entry in &game {
+            shard.write_entry(entry).context("writing entry to shard")?;
+        }
+        *counts.last_mut().expect("a shard is open") += game.len() as u64;
+    }
+
+    Ok(counts)
+}
+
+/// Path of the `index`-th shard of `output`: `out.binpack` becomes `out.<index>.binpack`.
+pub fn shard_path(output: &Path, index: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, index),
+    };
+    output.with_file_name(name)
+}
+
+/// Runs [`split_binpack`] from a binpack file into the shards [`shard_path`] names
+/// after `output`. Existing shards are only replaced if `force` is set. Returns the
+/// shard paths with their entry counts.
+pub fn split_file(
+    input: &Path,
+    output: &Path,
+    max_bytes: u64,
+    force: bool,
+) -> Result<Vec<(PathBuf, u64)>> {
+    let mut paths = Vec::new();
+    let counts = split_binpack(open_binpack(input)?, max_bytes, |index| {
+        let path = shard_path(output, index);
+        let file = if force {
+            File::create(&path)
+        } else {
+            File::create_new(&path)
+        }
+        .with_context(|| format!("creating shard {}", path.display()))?;
+        paths.push(path);
+        Ok(BufWriter::new(file))
+    })?;
+
+    Ok(paths.into_iter().zip(counts).collect())
+}
+
+/// Files written by [`split_file_by_result`], for games White won, drew and lost.
+pub const RESULT_FILES: [&str; 3] = ["wins.binpack", "draws.binpack", "losses.binpack"];
+
+/// Partitions the games of a binpack by their result from White's point of view into
+/// `outputs` (wins, draws, losses). Whole games move, so their chains stay intact.
+/// Returns the number of entries per output.
+pub fn split_binpack_by_result<R, W>(input: R, outputs: [W; 3]) -> Result<[u64; 3]>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    let mut reader = CompressedTrainingDataEntryReader::new(input)?;
+    let mut writers = Vec::with_capacity(outputs.len());
+    for output in outputs {
+        writers.push(CompressedTrainingDataEntryWriter::new(output).context("creating writer")?);
+    }
+    let mut counts = [0; 3];
+
+    while let Some(game) = next_game(&mut reader) {
+        // entry results are from the side to move's point of view
+        let first = &game[0];
+        let white = match first.pos.side_to_move() {
+            SfColor::White => first.result,
+            SfColor::Black => -first.result,
+        };
+        let index = match white.signum() {
+            1 => 0,
+            0 => 1,
+            _ => 2,
+        };
+
+        for entry in &game {
+            writers[index].write_entry(entry).context("writing entry")?;
+        }
+        counts[index] += game.len() as u64;
+    }
+
+    Ok(counts)
+}
+
+/// Runs [`split_binpack_by_result`] from a binpack file into the [`RESULT_FILES`] of
+/// `out_dir`, which is created if missing. Existing files are only replaced if `force`
+/// is set. Returns the paths with their entry counts.
+pub fn split_file_by_result(
+    input: &Path,
+    out_dir: &Path,
+    force: bool,
+) -> Result<Vec<(PathBuf, u64)>> {
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("creating directory {}", out_dir.display()))?;
+
+    let paths = RESULT_FILES.map(|name| out_dir.join(name));
+    let create = |path: &Path| {
+        let file = if force {
+            File::create(path)
+        } else {
+            File::create_new(path)
+        }
+        .with_context(|| format!("creating {}", path.display()))?;
+        Ok::<_, anyhow::Error>(BufWriter::new(file))
+    };
+    let outputs = [create(&paths[0])?, create(&paths[1])?, create(&paths[2])?];
+
+    let counts = split_binpack_by_result(open_binpack(input)?, outputs)?;
+    Ok(paths.into_iter().zip(counts).collect())
+}
+
+fn open_binpack(path: &Path) -> Result<BufReader<File>> {
+    Ok(BufReader::new(File::open(path).with_context(|| {
+        format!("opening binpack {}", path.display())
+    })?))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScoreScale {
+    /// Multiply every score by this factor
+    Factor(f64),
+    /// Scale so that the largest non-mate score magnitude becomes this value
+    TargetMax(i32),
+    /// Squash scores through a logistic function, see [`ScoreTransform`]
+    Transform(ScoreTransform),
+    /// Clip scores to the band between their P-th and (100-P)-th percentile
+    ClipPercentile(f64),
+    /// Scale so that the standard deviation of the evals matches that of this binpack
+    MatchScale(PathBuf),
+    /// Convert between internal scores and centipawns
+    Domain(ScoreDomain),
+}
+
+/// Upper bound of sigmoid-transformed scores; 0 maps to half of it.
+pub const SIGMOID_RANGE: f64 = 1000.0;
+
+/// Non-linear score mapping, written as `sigmoid[:K]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScoreTransform {
+    /// `SIGMOID_RANGE / (1 + exp(-score / k))`, a win probability scaled to `0..=1000`
+    Sigmoid { k: f64 },
+}
+
+impl ScoreTransform {
+    /// Default K of `sigmoid`, in internal score units.
+    pub const DEFAULT_SIGMOID_K: f64 = 400.0;
+
+    /// Maps a stored score; VALUE_NONE is kept so that it stays recognisable.
+    pub fn apply(&self, score: i16) -> i16 {
+        if i32::from(score).abs() == VALUE_NONE_SCORE {
+            return score;
+        }
+
+        match *self {
+            ScoreTransform::Sigmoid { k } => {
+                let p = 1.0 / (1.0 + (-f64::from(score) / k).exp());
+                (p * SIGMOID_RANGE).round() as i16
+            }
+        }
+    }
+}
+
+impl FromStr for ScoreTransform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (s, None),
+        };
+
+        match name {
+            "sigmoid" => {
+                let k = match param {
+                    Some(k) => k
+                        .parse::<f64>()
+                        .map_err(|e| format!("invalid sigmoid K {:?}: {}", k, e))?,
+                    None => Self::DEFAULT_SIGMOID_K,
+                };
+                if !(k.is_finite() && k > 0.0) {
+                    return Err(format!("sigmoid K must be positive, got {}", k));
+                }
+                Ok(ScoreTransform::Sigmoid { k })
+            }
+            _ => Err(format!(
+                "unknown score transform {:?}, expected sigmoid[:K]",
+                name
+            )),
+        }
+    }
+}
+
+/// Whether a score is an eval that post-passes may rescale. VALUE_NONE and mate
+/// scores keep their meaning and are left untouched.
+pub fn is_scalable(score: i16) -> bool {
+    let score = i32::from(score);
+    score.abs() != VALUE_NONE_SCORE && score.abs() < MATE_SCORE_THRESHOLD
+}
+
+/// Distribution of the scalable scores of a binpack, for percentile queries.
+#[derive(Clone, Debug)]
+pub struct ScoreDistribution {
+    /// Score + 32768 -> count
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Default for ScoreDistribution {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; 1 << 16],
+            total: 0,
+        }
+    }
+}
+
+impl ScoreDistribution {
+    pub fn add(&mut self, score: i16) {
+        if is_scalable(score) {
+            self.counts[(i32::from(score) + 32768) as usize] += 1;
+            self.total += 1;
+        }
+    }
+
+    /// Score at the given percentile (nearest rank), or `None` without scores.
+    pub fn percentile(&self, p: f64) -> Option<i16> {
+        if self.total == 0 {
+            return None;
+        }
+
+        let rank = ((p / 100.0 * self.total as f64).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0u64;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some((i as i32 - 32768) as i16);
+            }
+        }
+        unreachable!("rank is at most the number of scores")
+    }
+
+    /// The P-th and (100-P)-th percentile.
+    pub fn clip_bounds(&self, p: f64) -> Option<(i16, i16)> {
+        Some((self.percentile(p)?, self.percentile(100.0 - p)?))
+    }
+
+    /// Population standard deviation, or `None` without scores.
+    pub fn std_dev(&self) -> Option<f64> {
+        if self.total == 0 {
+            return None;
+        }
+
+        let scores = || {
+            self.counts
+                .iter()
+                .enumerate()
+                .map(|(i, &count)| (f64::from(i as i32 - 32768), count as f64))
+        };
+        let total = self.total as f64;
+        let mean = scores().map(|(score, count)| score * count).sum::<f64>() / total;
+        let variance = scores()
+            .map(|(score, count)| (score - mean).powi(2) * count)
+            .sum::<f64>()
+            / total;
+        Some(variance.sqrt())
+    }
+
+    /// Distribution of all scores of a binpack file.
+    pub fn of_file(path: &Path) -> Result<Self> {
+        let mut distribution = Self::default();
+        for_each_entry(open_binpack(path)?, |entry| distribution.add(entry.score))?;
+        Ok(distribution)
+    }
+
+    /// Factor that scales this distribution to the spread of `reference`.
+    pub fn match_factor(&self, reference: &Self) -> Option<f64> {
+        let own = self.std_dev()?;
+        let target = reference.std_dev()?;
+        (own > 0.0).then(|| target / own)
+    }
+}
+
+/// Scales an eval, clamping the result below the mate range.
+pub fn scale_score(score: i16, factor: f64) -> i16 {
+    if !is_scalable(score) {
+        return score;
+    }
+
+    let limit = f64::from(MATE_SCORE_THRESHOLD - 1);
+    (f64::from(score) * factor).round().clamp(-limit, limit) as i16
+}
+
+/// Converts an eval into `domain`, by the material of `pos` like Stockfish.
+pub fn convert_domain(score: i16, domain: ScoreDomain, pos: &Chess) -> i16 {
+    match domain {
+        ScoreDomain::Cp => internal_to_external_cp(i32::from(score), pos),
+        ScoreDomain::Internal => external_cp_to_internal(i32::from(score), pos),
+    }
+}
+
+/// Writes a copy of `input` with all evals rescaled.
+pub fn normalize_scores(input: &Path, output: &Path, scale: ScoreScale) -> Result<u64> {
+    let factor = match scale {
+        ScoreScale::Transform(transform) => {
+            println!("Transforming scores with {:?}", transform);
+            return transform_file(input, output, |entry| {
+                entry.score = transform.apply(entry.score)
+            });
+        }
+        ScoreScale::ClipPercentile(p) => {
+            let distribution = ScoreDistribution::of_file(input)?;
+
+            let Some((low, high)) = distribution.clip_bounds(p) else {
+                anyhow::bail!("no evals found in {} to clip", input.display());
+            };
+
+            println!("Clipping scores to [{}, {}]", low, high);
+            return transform_file(input, output, |entry| {
+                if is_scalable(entry.score) {
+                    entry.score = entry.score.clamp(low, high);
+                }
+            });
+        }
+        ScoreScale::Domain(domain) => {
+            println!("Converting scores to {:?}", domain);
+            let mut failure = None;
+            let count = transform_file(input, output, |entry| {
+                if failure.is_some() || !is_scalable(entry.score) {
+                    return;
+                }
+                match entry_position(entry) {
+                    Ok(pos) => entry.score = convert_domain(entry.score, domain, &pos),
+                    Err(e) => failure = Some(e),
+                }
+            })?;
+            return match failure {
+                Some(e) => Err(e),
+                None => Ok(count),
+            };
+        }
+        ScoreScale::Factor(factor) => factor,
+        ScoreScale::MatchScale(reference) => {
+            let own = ScoreDistribution::of_file(input)?;
+            let target = ScoreDistribution::of_file(&reference)?;
+
+            match own.match_factor(&target) {
+                Some(factor) => factor,
+                None => anyhow::bail!(
+                    "cannot match the scale of {}: need varying evals in both {} and it",
+                    reference.display(),
+                    input.display()
+                ),
+            }
+        }
+        ScoreScale::TargetMax(target) => {
+            let mut max_abs = 0i32;
+            for_each_entry(open_binpack(input)?, |entry| {
+                if is_scalable(entry.score) {
+                    max_abs = max_abs.max(i32::from(entry.score).abs());
+                }
+            })?;
+
+            if max_abs == 0 {
+                anyhow::bail!("no evals found in {} to scale", input.display());
+            }
+
+            f64::from(target) / f64::from(max_abs)
+        }
+    };
+
+    println!("Scaling scores by {:.4}", factor);
+    transform_file(input, output, |entry| {
+        entry.score = scale_score(entry.score, factor)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::Path;
+
+    use sfbinpack::{
+        chess::position::Position as SfPosition, CompressedTrainingDataEntryReader,
+        TrainingDataEntry,
+    };
+
+    use super::{
+        interleave_binpacks, merge_sorted_binpacks, next_game, open_binpack, scale_score,
+        shuffle_binpack, split_file, split_file_by_result, transform_binpack, ScoreDistribution,
+        ScoreTransform, SortKey, SIGMOID_RANGE,
+    };
+    use crate::analytics::VALUE_NONE_SCORE;
+    use crate::binpack::test_util::{game_entries, read_entries, sf_move, write_entries};
+
+    #[test]
+    fn scales_and_clamps_scores() {
+        let value_none = VALUE_NONE_SCORE as i16;
+        let entries = game_entries(
+            &[
+                (12, 28, 100),
+                (52, 36, -20000),
+                (6, 21, 31990),
+                (57, 42, value_none),
+            ],
+            0,
+        );
+        let input = write_entries(&entries);
+
+        let mut output = Cursor::new(Vec::new());
+        let count = transform_binpack(Cursor::new(input), &mut output, |entry| {
+            entry.score = scale_score(entry.score, 2.0)
+        })
+        .unwrap();
+
+        let scores: Vec<i16> = read_entries(output.into_inner())
+            .iter()
+            .map(|e| e.score)
+            .collect();
+
+        assert_eq!(count, 4);
+        assert_eq!(scores, [200, -28999, 31990, value_none]);
+    }
+
+    #[test]
+    fn sigmoid_maps_into_bounded_range() {
+        let sigmoid: ScoreTransform = "sigmoid".parse().unwrap();
+        let range = SIGMOID_RANGE as i16;
+
+        assert_eq!(sigmoid.apply(0), range / 2);
+        assert!(sigmoid.apply(3000) >= range - 1);
+        assert!(sigmoid.apply(-3000) <= 1);
+        assert_eq!(sigmoid.apply(31990), range);
+        assert_eq!(sigmoid.apply(-31990), 0);
+        assert_eq!(
+            sigmoid.apply(VALUE_NONE_SCORE as i16),
+            VALUE_NONE_SCORE as i16
+        );
+
+        // a smaller K saturates sooner
+        let steep: ScoreTransform = "sigmoid:100".parse().unwrap();
+        assert!(steep.apply(200) > sigmoid.apply(200));
+        assert!("sigmoid:0".parse::<ScoreTransform>().is_err());
+        assert!("tanh".parse::<ScoreTransform>().is_err());
+    }
+
+    #[test]
+    fn clip_bounds_match_percentiles() {
+        let mut distribution = ScoreDistribution::default();
+        for score in 1..=100 {
+            distribution.add(score);
+        }
+        // mate scores and VALUE_NONE do not count towards the percentiles
+        distribution.add(31990);
+        distribution.add(VALUE_NONE_SCORE as i16);
+
+        assert_eq!(distribution.clip_bounds(5.0), Some((5, 95)));
+        assert_eq!(distribution.clip_bounds(0.0), Some((1, 100)));
+        assert_eq!(distribution.percentile(50.0), Some(50));
+        assert_eq!(ScoreDistribution::default().clip_bounds(5.0), None);
+    }
+
+    #[test]
+    fn match_factor_aligns_std_devs() {
+        let scores = |factor: i16| -> Vec<i16> { (-50..=50).map(|s| s * factor).collect() };
+        let distribution = |scores: &[i16]| {
+            let mut distribution = ScoreDistribution::default();
+            scores.iter().for_each(|&score| distribution.add(score));
+            distribution
+        };
+
+        let own = distribution(&scores(3));
+        let reference = distribution(&scores(7));
+        let factor = own.match_factor(&reference).unwrap();
+
+        let scaled: Vec<i16> = scores(3).iter().map(|&s| scale_score(s, factor)).collect();
+        let scaled_std = distribution(&scaled).std_dev().unwrap();
+        let reference_std = reference.std_dev().unwrap();
+        assert!((scaled_std - reference_std).abs() < 1.0);
+
+        // a constant input has no spread to scale
+        assert_eq!(distribution(&[10, 10]).match_factor(&reference), None);
+    }
+
+    #[test]
+    fn split_by_result_moves_whole_games() {
+        let win = game_entries(&[(12, 28, 30), (52, 36, -25), (6, 21, 40)], 1);
+        let loss = game_entries(&[(11, 27, -50), (51, 35, 60)], -1);
+        let draw = game_entries(&[(10, 18, 0)], 0);
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.binpack");
+        std::fs::write(
+            &input,
+            write_entries(&[&win[..], &loss, &draw, &win].concat()),
+        )
+        .unwrap();
+
+        let out = dir.path().join("by-result");
+        let files = split_file_by_result(&input, &out, false).unwrap();
+        assert_eq!(files[0], (out.join("wins.binpack"), 6));
+
+        let games = |path: &Path| {
+            let mut reader =
+                CompressedTrainingDataEntryReader::new(open_binpack(path).unwrap()).unwrap();
+            std::iter::from_fn(|| next_game(&mut reader)).collect::<Vec<_>>()
+        };
+        assert_eq!(games(&files[0].0), [win.clone(), win]);
+        assert_eq!(games(&files[1].0), [draw]);
+        assert_eq!(games(&files[2].0), [loss]);
+
+        assert!(split_file_by_result(&input, &out, false).is_err());
+    }
+
+    #[test]
+    fn split_keeps_games_whole_and_shards_near_the_limit() {
+        let games: Vec<Vec<TrainingDataEntry>> = (1..=30)
+            .map(|score| game_entries(&[(12, 28, score), (52, 36, score), (6, 21, score)], 0))
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.binpack");
+        std::fs::write(&input, write_entries(&games.concat())).unwrap();
+        let max_bytes = write_entries(&games[..5].concat()).len() as u64;
+
+        let shards = split_file(&input, &dir.path().join("out.binpack"), max_bytes, false).unwrap();
+        assert!(shards.len() > 1);
+        assert_eq!(shards[0].0, dir.path().join("out.0.binpack"));
+
+        let mut contents = Vec::new();
+        for (path, count) in &shards {
+            let bytes = std::fs::read(path).unwrap();
+            // every shard stays within the limit and starts at a game start
+            assert!(bytes.len() as u64 <= max_bytes);
+            let entries = read_entries(bytes);
+            assert_eq!(entries.len() as u64, *count);
+            assert_eq!(entries.len() % 3, 0);
+            contents.push(entries);
+        }
+        // a shard only moves on when the next game would no longer fit
+        for pair in contents.windows(2) {
+            let with_next = write_entries(&[&pair[0][..], &pair[1][..3]].concat());
+            assert!(with_next.len() as u64 > max_bytes);
+        }
+        assert_eq!(contents.concat(), games.concat());
+
+        // shards are not overwritten without force
+        assert!(split_file(&input, &dir.path().join("out.binpack"), max_bytes, false).is_err());
+    }
+
+    #[test]
+    fn shuffle_permutes_games_reproducibly() {
+        // each game is tagged by its score
+        let input: Vec<_> = (1..=20)
+            .flat_map(|score| game_entries(&[(12, 28, score), (52, 36, score)], 0))
+            .collect();
+        let shuffled = |seed| {
+            let mut output = Cursor::new(Vec::new());
+            let count = shuffle_binpack(
+                Cursor::new(write_entries(&input)),
+                &mut output,
+                4,
+                seed,
+                None,
+            )
+            .unwrap();
+            assert_eq!(count, 40);
+            read_entries(output.into_inner())
+                .iter()
+                .map(|e| e.score)
+                .collect::<Vec<i16>>()
+        };
+
+        let first = shuffled(7);
+        // games stay whole
+        assert!(first.chunks(2).all(|game| game[0] == game[1]));
+        let mut sorted = first.clone();
+        sorted.sort_unstable();
+        let mut expected: Vec<i16> = input.iter().map(|e| e.score).collect();
+        expected.sort_unstable();
+        assert_eq!(sorted, expected);
+
+        assert_eq!(shuffled(7), first);
+        assert_ne!(shuffled(8), first);
+    }
+
+    #[test]
+    fn interleave_alternates_games() {
+        // each game is tagged by its score
+        let game = |score| game_entries(&[(12, 28, score), (52, 36, score)], 0);
+        let a: Vec<_> = [1, 2, 3].into_iter().flat_map(game).collect();
+        let b = game(101);
+
+        let mut output = Cursor::new(Vec::new());
+        let count = interleave_binpacks(
+            vec![
+                Cursor::new(write_entries(&a)),
+                Cursor::new(write_entries(&b)),
+            ],
+            &mut output,
+        )
+        .unwrap();
+
+        let scores: Vec<i16> = read_entries(output.into_inner())
+            .iter()
+            .map(|e| e.score)
+            .collect();
+
+        assert_eq!(count, 8);
+        assert_eq!(scores, [1, 1, 101, 101, 2, 2, 3, 3]);
+    }
+
+    #[test]
+    fn merge_sort_keeps_material_order_and_games() {
+        // single positions with the given material, scored with a tag
+        let single = |fen: &str, score| {
+            let pos = SfPosition::from_fen(fen).unwrap();
+            TrainingDataEntry {
+                pos,
+                mv: sf_move(4, 12),
+                score,
+                ply: pos.ply(),
+                result: 0,
+            }
+        };
+        let kings = "4k3/8/8/8/8/8/8/4K3 w - - 0 1";
+        let rook = "4k3/8/8/8/8/8/8/R3K3 w - - 0 1";
+        let queen = "4k3/8/8/8/8/8/8/3QK3 w - - 0 1";
+        let queens = "3qk3/8/8/8/8/8/8/3QK3 w - - 0 1";
+
+        let mut a = vec![single(kings, 1), single(queen, 2)];
+        a.extend(game_entries(&[(12, 28, 3), (52, 36, 3)], 0));
+        let b = vec![single(kings, 11), single(rook, 12), single(queens, 13)];
+
+        let mut output = Cursor::new(Vec::new());
+        let count = merge_sorted_binpacks(
+            vec![
+                Cursor::new(write_entries(&a)),
+                Cursor::new(write_entries(&b)),
+            ],
+            &mut output,
+            SortKey::Material,
+        )
+        .unwrap();
+
+        let merged = read_entries(output.into_inner());
+        let scores: Vec<i16> = merged.iter().map(|e| e.score).collect();
+        let materials: Vec<i32> = merged
+            .iter()
+            .map(|e| SortKey::Material.of(e).unwrap())
+            .collect();
+
+        assert_eq!(count, 7);
+        assert_eq!(scores, [1, 11, 12, 2, 13, 3, 3]);
+        assert!(materials.windows(2).all(|w| w[0] <= w[1]), "{materials:?}");
+
+        // unsorted input is rejected
+        let mut output = Cursor::new(Vec::new());
+        let unsorted = vec![single(queen, 1), single(kings, 2)];
+        assert!(merge_sorted_binpacks(
+            vec![Cursor::new(write_entries(&unsorted))],
+            &mut output,
+            SortKey::Material
+        )
+        .is_err());
+    }
+}
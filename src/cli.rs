@@ -1,19 +1,96 @@
 use clap::{Parser, ValueEnum};
 use std::path::PathBuf;
 
+use crate::analytics::filter::EntryFilter;
+use crate::binpack::builder::ResultWeight;
+use crate::binpack::transform::ScoreTransform;
+use crate::process::GamesRange;
+use crate::util::util::EvalRegex;
+
 #[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq)]
 pub enum Backend {
     Sfbinpack,
     Viriformat,
 }
 
+/// How converted entries reach the output file.
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq)]
+pub enum WriterMode {
+    /// Convert each file separately, then write the results in one go (see --memory)
+    #[default]
+    PerFile,
+    /// Write every game directly to one binpack writer shared by all threads
+    Shared,
+}
+
+/// Source of the scores written during a build.
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq)]
+pub enum ScoreFrom {
+    /// Parse evals from the move comments
+    #[default]
+    Comment,
+    /// Parse evals from the move comments, taking the deepest `eval/depth` when a
+    /// comment (or comments nested in it) holds several
+    DeepestComment,
+    /// Skip comments and write every move with score 0
+    None,
+}
+
+/// Score scale a binpack is converted into with --score-domain.
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq)]
+pub enum ScoreDomain {
+    /// Stored internal scores to centipawns as Stockfish reports them
+    Cp,
+    /// Centipawns back to internal scores
+    Internal,
+}
+
+/// What to do with games that contain a null move (`--`).
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq)]
+pub enum NullMovePolicy {
+    /// Fail the file, like any other unparseable move
+    #[default]
+    Error,
+    /// Drop the whole game
+    SkipGame,
+    /// End the game at the null move, keeping the positions before it
+    StopGame,
+}
+
+/// How the FEN of a game's `FEN` tag is handed to shakmaty and sfbinpack.
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq)]
+pub enum FenNormalization {
+    /// Give the tag as is to both libraries
+    #[default]
+    Raw,
+    /// Parse with shakmaty, dropping castling rights and ep squares that do not apply,
+    /// and give its FEN to sfbinpack
+    Shakmaty,
+    /// Parse with sfbinpack and give its FEN to shakmaty
+    Sfbinpack,
+}
+
+/// Text encoding of the PGN input.
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq, Eq)]
+pub enum PgnEncoding {
+    #[default]
+    Utf8,
+    /// Latin-1 / Windows-1252, common in older PGN collections
+    Latin1,
+}
+
 #[derive(Parser)]
 #[command(name = "pgn2binpack")]
 #[command(about = "Convert PGN chess files to binpack format", long_about = None)]
 pub struct Cli {
-    /// Directory to search for PGN files
+    /// Directories to search for PGN files
     #[arg(value_name = "DIR")]
-    pub input_dir: Option<PathBuf>,
+    pub input_dirs: Vec<PathBuf>,
+
+    /// Convert the PGN files listed in FILE (one path per line, `#` starts a comment)
+    /// instead of searching DIR
+    #[arg(long, value_name = "FILE", conflicts_with = "input_dirs")]
+    pub input_list: Option<PathBuf>,
 
     /// Output binpack file
     #[arg(short, long)]
@@ -23,6 +100,16 @@ pub struct Cli {
     #[arg(short, long)]
     pub threads: Option<usize>,
 
+    /// Print the positions, games and time of every converted file instead of a
+    /// progress counter
+    #[arg(long)]
+    pub verbose: bool,
+
+    /// Write every failed file and game to FILE (JSON if it ends in .json, text
+    /// otherwise); implies --skip-errors
+    #[arg(long, value_name = "FILE")]
+    pub error_report: Option<PathBuf>,
+
     /// Overwrite output file if it exists
     #[arg(short = 'f', long)]
     pub force: bool,
@@ -31,18 +118,366 @@ pub struct Cli {
     #[arg(short, long, default_missing_value="true", default_value = "true", num_args=0..=1)]
     pub memory: bool,
 
+    /// How converted entries are written: per-file buffers or one shared writer (sfbinpack only)
+    #[arg(long, value_enum, default_value_t = WriterMode::PerFile)]
+    pub writer: WriterMode,
+
+    /// Replay every converted move with shakmaty before writing it (sfbinpack only)
+    #[arg(long)]
+    pub verify_on_write: bool,
+
+    /// Check that every written position survives a FEN roundtrip through sfbinpack and
+    /// fail with both FENs otherwise (sfbinpack only)
+    #[arg(long)]
+    pub verify_fen_roundtrip: bool,
+
+    /// Encoding of tag values and comments in the input PGNs
+    #[arg(long, value_enum, default_value_t = PgnEncoding::Utf8)]
+    pub pgn_encoding: PgnEncoding,
+
+    /// Keep at most about N file handles open while converting (default: half the
+    /// soft limit on open files)
+    #[arg(long, value_name = "N")]
+    pub max_open_files: Option<usize>,
+
+    /// Skip input PGN files larger than N megabytes
+    #[arg(long, value_name = "N")]
+    pub max_file_size_mb: Option<u64>,
+
+    /// Only convert the files directly in the input directories, not in their
+    /// subdirectories
+    #[arg(long)]
+    pub no_recursive: bool,
+
+    /// Only convert the games with index in [START, END), counted across the input
+    /// files in order, e.g. to shard a corpus over machines
+    #[arg(long, value_name = "START:END")]
+    pub games_range: Option<GamesRange>,
+
+    /// Stop the build once N entries were written in total (sfbinpack only)
+    #[arg(long, value_name = "N")]
+    pub max_entries: Option<u64>,
+
+    /// Where scores come from; `none` writes every move with score 0 (sfbinpack only)
+    #[arg(long, value_enum, default_value_t = ScoreFrom::Comment)]
+    pub score_from: ScoreFrom,
+
+    /// Regex for eval comments with a named group `cp` (centipawns) or `pawns`, tried
+    /// before the built-in formats, e.g. `score=(?P<cp>-?\d+)` (sfbinpack only)
+    #[arg(long, value_name = "REGEX")]
+    pub eval_regex: Option<EvalRegex>,
+
+    /// Canonicalize the FEN tag through one library before both parse it, so that they
+    /// agree on castling rights and ep square (sfbinpack only)
+    #[arg(long, value_enum, default_value_t = FenNormalization::Raw)]
+    pub fen_normalization: FenNormalization,
+
+    /// How to handle games with null moves (`--`) (sfbinpack only)
+    #[arg(long, value_enum, default_value_t = NullMovePolicy::Error)]
+    pub on_null_move: NullMovePolicy,
+
+    /// Stop writing a game's positions after the first one with a mate score (sfbinpack only)
+    #[arg(long)]
+    pub truncate_after_mate: bool,
+
+    /// Multiply the scores of drawn games by FACTOR (0 to 1) to reflect lower confidence
+    /// (sfbinpack only)
+    #[arg(long, value_name = "FACTOR")]
+    pub draw_dampen: Option<f64>,
+
+    /// Multiply scores by a factor per outcome for the side to move, e.g. 1.2:0.8:1.2
+    /// to emphasize decisive games (sfbinpack only)
+    #[arg(long, value_name = "WIN:DRAW:LOSS", conflicts_with = "draw_dampen")]
+    pub result_weight: Option<ResultWeight>,
+
+    /// Also write moves without an eval comment, with score CP (default 0) (sfbinpack only)
+    #[arg(long, value_name = "CP", num_args = 0..=1, default_missing_value = "0")]
+    pub keep_unscored: Option<i16>,
+
+    /// Drop the first position with an eval of every game, which is often a book move
+    /// scored 0.00 (sfbinpack only)
+    #[arg(long)]
+    pub drop_first_eval: bool,
+
+    /// Within a game, keep only the first of consecutive positions with the same score
+    /// (sfbinpack only)
+    #[arg(long)]
+    pub collapse_equal_scores: bool,
+
+    /// Warn about games whose result contradicts a checkmate, stalemate, insufficient
+    /// material, fifty-move rule or threefold repetition in the final position (sfbinpack only)
+    #[arg(long)]
+    pub check_results: bool,
+
+    /// Keep N uniformly sampled positions of every longer game (sfbinpack only)
+    #[arg(long, value_name = "N")]
+    pub reservoir_per_game: Option<usize>,
+
+    /// Seed for the random sampling options and --shuffle
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Treat each game as a puzzle: write every mainline move after the FEN, give the
+    /// final solution position a winning score and skip games without FEN (sfbinpack only)
+    #[arg(long)]
+    pub puzzle_mode: bool,
+
+    /// Log and skip games that fail to convert (bad SAN, illegal moves, unsupported
+    /// variants) instead of aborting their file
+    #[arg(long)]
+    pub skip_errors: bool,
+
+    /// Drop games in which fewer than PCT percent of the moves carry an eval (sfbinpack only)
+    #[arg(long, value_name = "PCT")]
+    pub min_eval_coverage: Option<f64>,
+
+    /// Only write positions with at least N material, counting P=1 N=3 B=3 R=5 Q=9 for
+    /// both sides (sfbinpack only)
+    #[arg(long, value_name = "N")]
+    pub min_material: Option<i32>,
+
+    /// Only write positions with at most N material (sfbinpack only)
+    #[arg(long, value_name = "N")]
+    pub max_material: Option<i32>,
+
+    /// Print a histogram of the written scores after the build (sfbinpack only)
+    #[arg(long)]
+    pub eval_histogram: bool,
+
+    /// Print the time spent parsing, compressing and writing after the build (sfbinpack only)
+    #[arg(long)]
+    pub profile: bool,
+
+    /// Write "win draw loss" probabilities derived from each score to this file,
+    /// one line per entry in binpack order (sfbinpack only)
+    #[arg(long, value_name = "PATH")]
+    pub wdl_sidecar: Option<PathBuf>,
+
+    /// Write the number of legal moves of each entry's position to this file, one line
+    /// per entry in binpack order (sfbinpack only)
+    #[arg(long, value_name = "PATH")]
+    pub legal_moves_sidecar: Option<PathBuf>,
+
+    /// Write the side to move, castling rights and en passant square of each entry's
+    /// position to this file, one line per entry in binpack order (sfbinpack only)
+    #[arg(long, value_name = "PATH")]
+    pub board_flags_sidecar: Option<PathBuf>,
+
+    /// Write the `Termination` tag of each entry's game to this file, one line per entry
+    /// in binpack order (sfbinpack only)
+    #[arg(long, value_name = "PATH")]
+    pub termination_sidecar: Option<PathBuf>,
+
+    /// Write the seconds left on the mover's clock from `[%clk ...]` comments to this
+    /// file, one line per entry in binpack order, empty without a clock (sfbinpack only)
+    #[arg(long, value_name = "PATH")]
+    pub clock_sidecar: Option<PathBuf>,
+
+    /// Skip games whose `Termination` tag is REASON, e.g. "Abandoned"; repeatable, case
+    /// insensitive (sfbinpack only)
+    #[arg(long, value_name = "REASON")]
+    pub drop_termination: Vec<String>,
+
+    /// Append to the output file instead of requiring a new one
+    #[arg(long)]
+    pub append: bool,
+
+    /// Record the converted input files in this manifest (one path per line)
+    #[arg(long, value_name = "FILE")]
+    pub manifest: Option<PathBuf>,
+
+    /// Skip input files listed in MANIFEST and append the rest to the output (and MANIFEST)
+    #[arg(long, value_name = "MANIFEST")]
+    pub skip_existing: Option<PathBuf>,
+
+    /// Directory for intermediate part files when not using memory (default: system temp dir)
+    #[arg(long, value_name = "DIR")]
+    pub tmp_dir: Option<PathBuf>,
+
+    /// Name part files `part-<hash>.binpack` after their source file instead of randomly,
+    /// so that re-runs overwrite the same files (disk mode)
+    #[arg(long)]
+    pub deterministic_parts: bool,
+
+    /// Read the output back after concatenating the part files and fail if it is not a
+    /// readable binpack (disk mode, sfbinpack only)
+    #[arg(long)]
+    pub concat_validate: bool,
+
+    /// Read the output back after the build and fail unless it holds exactly the
+    /// entries that were written (sfbinpack only)
+    #[arg(long)]
+    pub verify_count: bool,
+
     /// Count unique positions in a binpack file
     #[arg(short, long, num_args=0..=1, value_name = "FILE")]
     pub unique: Option<PathBuf>,
 
+    /// Ignore en passant squares without a legal capture when hashing positions
+    /// (with --unique or --detect-duplicates-across)
+    #[arg(long)]
+    pub dedup_ignore_ep: bool,
+
+    /// Write the Zobrist hash of every position of the --unique FILE to PATH, as 16 hex
+    /// digits per line in entry order
+    #[arg(long, value_name = "PATH", requires = "unique")]
+    pub dump_zobrist: Option<PathBuf>,
+
     /// Limit the number of entries processed (only with --unique or --view)
     #[arg(long)]
     pub limit: Option<usize>,
 
-    /// View contents of a binpack file
+    /// Skip the first N entries (only with --view)
+    #[arg(long, value_name = "N")]
+    pub skip: Option<usize>,
+
+    /// Resume --view or --unique at entry N, the [index] printed by an interrupted --view
+    #[arg(long, value_name = "N", conflicts_with = "skip")]
+    pub resume_from_index: Option<usize>,
+
+    /// View contents of a binpack file, or of a binpack piped to stdin with `-`
     #[arg(short, long)]
     pub view: Option<PathBuf>,
 
+    /// Only consider entries that carry an eval (with --unique or --view)
+    #[arg(long)]
+    pub eval_only: bool,
+
+    /// Only show entries matching EXPR with --view, e.g. "score>100 && result==1 && ply<40"
+    /// (fields: score, result, ply, pieces, cont)
+    #[arg(long, value_name = "EXPR")]
+    pub filter: Option<EntryFilter>,
+
+    /// Emit a JSON summary of --unique or --view to stdout, or to FILE if given (required
+    /// with --view)
+    #[arg(long, num_args=0..=1, value_name = "FILE")]
+    pub summary_json: Option<Option<PathBuf>>,
+
+    /// Convert PGN again and check that every entry of BINPACK matches it (uses
+    /// --score-from and --pgn-encoding of the original build)
+    #[arg(long, num_args = 2, value_names = ["BINPACK", "PGN"])]
+    pub verify_against_pgn: Option<Vec<PathBuf>>,
+
+    /// Report how many positions of binpack B already occur in binpack A
+    #[arg(long, num_args = 2, value_names = ["A", "B"])]
+    pub detect_duplicates_across: Option<Vec<PathBuf>>,
+
+    /// Write the games of several binpack files round-robin into --output
+    #[arg(long, num_args = 2.., value_name = "FILE")]
+    pub interleave: Option<Vec<PathBuf>>,
+
+    /// Merge binpack files that are each sorted by KEY (material, ply or score) into
+    /// one sorted --output, keeping games intact
+    #[arg(long, num_args = 3.., value_names = ["KEY", "FILE"])]
+    pub merge_sort: Option<Vec<String>>,
+
+    /// Shuffle the games of a binpack file into --output in two passes over temp
+    /// shards, so that the file does not need to fit in memory
+    #[arg(long, value_name = "FILE")]
+    pub shuffle: Option<PathBuf>,
+
+    /// Number of temp shards used by --shuffle; each shard is shuffled in memory
+    #[arg(long, value_name = "K", default_value_t = 64)]
+    pub shuffle_shards: usize,
+
+    /// Split a binpack file into shards `<output stem>.<k>.<ext>` of about --split-bytes
+    /// each, without splitting games
+    #[arg(long, value_name = "FILE", requires = "split_bytes")]
+    pub split: Option<PathBuf>,
+
+    /// Start a new shard with --split before the next game would take the current one
+    /// past N bytes; only a single game larger than N overshoots
+    #[arg(long, value_name = "N")]
+    pub split_bytes: Option<u64>,
+
+    /// Partition the games of a binpack file by result into wins.binpack, draws.binpack
+    /// and losses.binpack (from White's point of view) in the directory --output
+    #[arg(long, value_name = "FILE")]
+    pub split_by_result: Option<PathBuf>,
+
+    /// Rescale the scores of a binpack file into --output (with --scale-factor, --target-max,
+    /// --score-transform, --clip-percentile, --match-scale or --score-domain)
+    #[arg(long, value_name = "FILE")]
+    pub normalize_scores: Option<PathBuf>,
+
+    /// Factor to multiply scores by with --normalize-scores
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        conflicts_with_all = [
+            "target_max",
+            "score_transform",
+            "clip_percentile",
+            "match_scale",
+            "score_domain"
+        ]
+    )]
+    pub scale_factor: Option<f64>,
+
+    /// Scale scores so the largest non-mate magnitude becomes N with --normalize-scores
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["score_transform", "clip_percentile", "match_scale", "score_domain"]
+    )]
+    pub target_max: Option<i32>,
+
+    /// Map scores through `sigmoid[:K]` (default K 400) into 0..=1000 with --normalize-scores
+    #[arg(
+        long,
+        value_name = "SPEC",
+        conflicts_with_all = ["clip_percentile", "match_scale", "score_domain"]
+    )]
+    pub score_transform: Option<ScoreTransform>,
+
+    /// Clip scores to their P-th and (100-P)-th percentile with --normalize-scores
+    #[arg(long, value_name = "P", conflicts_with_all = ["match_scale", "score_domain"])]
+    pub clip_percentile: Option<f64>,
+
+    /// Scale scores so their standard deviation matches the evals of REF_BINPACK with
+    /// --normalize-scores, e.g. to combine data from different engines
+    #[arg(long, value_name = "REF_BINPACK", conflicts_with = "score_domain")]
+    pub match_scale: Option<PathBuf>,
+
+    /// Convert scores between internal units and centipawns with --normalize-scores,
+    /// using each position's material like Stockfish
+    #[arg(long, value_enum, value_name = "DOMAIN")]
+    pub score_domain: Option<ScoreDomain>,
+
+    /// Export a binpack file as CSV (index,fen,uci,score,ply,result,is_continuation) to
+    /// --output
+    #[arg(long, value_name = "FILE")]
+    pub to_csv: Option<PathBuf>,
+
+    /// Print how often each piece stands on each square in a binpack file (64x12 table)
+    #[arg(long, value_name = "FILE")]
+    pub occupancy: Option<PathBuf>,
+
+    /// Print the most frequent moves of a binpack file with their counts
+    #[arg(long, value_name = "FILE")]
+    pub dump_moves: Option<PathBuf>,
+
+    /// Number of moves --dump-moves prints
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    pub top: usize,
+
+    /// Check that every continuation entry of a binpack file follows from the previous
+    /// entry's move, and report the first entry where it does not
+    #[arg(long, value_name = "FILE")]
+    pub validate_continuity: Option<PathBuf>,
+
+    /// Check that decisive scores of a binpack file mostly agree with the game results
+    #[arg(long, value_name = "FILE")]
+    pub score_sign_check: Option<PathBuf>,
+
+    /// Parse every PGN file in a directory and report errors without writing output
+    #[arg(long, value_name = "DIR")]
+    pub check: Option<PathBuf>,
+
+    /// Tally the Variant tags of all PGN files in a directory without converting
+    #[arg(long, value_name = "DIR")]
+    pub scan_variants: Option<PathBuf>,
+
     /// Output backend (sfbinpack or viriformat)
     #[arg(long, value_enum, default_value_t = Backend::Sfbinpack)]
     pub backend: Backend,
@@ -1,29 +1,99 @@
 use std::{
+    collections::HashSet,
     fs::File,
-    io::{BufWriter, Write},
+    io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
-    sync::mpsc,
+    sync::{mpsc, Condvar, Mutex},
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
 use tempfile::NamedTempFile;
 use walkdir::WalkDir;
 
-pub fn collect_pgn_files(root: &Path) -> Result<Vec<PathBuf>> {
-    let mut files = Vec::new();
+/// Files found below a directory, with counts of what was left out.
+#[derive(Debug, Default)]
+pub struct PgnScan {
+    pub files: Vec<PathBuf>,
+    /// Regular files looked at
+    pub scanned: usize,
+    /// Files without a `.pgn`, `.pgn.gz` or `.pgn.bz2` extension
+    pub skipped_ext: usize,
+    /// PGN files over the size limit
+    pub skipped_size: usize,
+}
+
+/// Finds all PGN files below `root`. Files larger than `max_size` bytes are
+/// skipped with a warning.
+pub fn collect_pgn_files(root: &Path, max_size: Option<u64>) -> Result<Vec<PathBuf>> {
+    Ok(scan_pgn_files(root, max_size, true)?.files)
+}
 
-    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
+/// Like [`collect_pgn_files`], but also reports how many files were skipped.
+/// Without `recursive` only the files directly in `root` are looked at.
+pub fn scan_pgn_files(root: &Path, max_size: Option<u64>, recursive: bool) -> Result<PgnScan> {
+    let mut scan = PgnScan::default();
+
+    let walk = if recursive {
+        WalkDir::new(root)
+    } else {
+        WalkDir::new(root).max_depth(1)
+    };
+    for entry in walk.into_iter().filter_map(Result::ok) {
         if !entry.file_type().is_file() {
             continue;
         }
 
+        scan.scanned += 1;
         let path = entry.path();
-        if is_pgn_file(path) {
-            files.push(path.to_path_buf());
+        if !is_pgn_file(path) {
+            scan.skipped_ext += 1;
+            continue;
         }
+
+        if let Some(max_size) = max_size {
+            let size = entry.metadata()?.len();
+            if size > max_size {
+                eprintln!(
+                    "Warning: skipping {} ({} bytes exceeds the size limit)",
+                    path.display(),
+                    size
+                );
+                scan.skipped_size += 1;
+                continue;
+            }
+        }
+
+        scan.files.push(path.to_path_buf());
+    }
+
+    scan.files.sort();
+    Ok(scan)
+}
+
+/// Reads PGN paths from a list file, one per line; blank lines and lines
+/// starting with `#` are ignored.
+pub fn read_input_list(list: &Path) -> Result<Vec<PathBuf>> {
+    let file =
+        File::open(list).with_context(|| format!("opening input list {}", list.display()))?;
+    let mut files = Vec::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let path = PathBuf::from(line);
+        if !path.is_file() {
+            anyhow::bail!("file listed in {} does not exist: {}", list.display(), line);
+        }
+        files.push(path);
     }
 
-    files.sort();
     Ok(files)
 }
 
@@ -32,30 +102,184 @@ fn is_pgn_file(path: &Path) -> bool {
         .and_then(|ext| ext.to_str())
         .map(|s| {
             let lower = s.to_ascii_lowercase();
-            lower == "pgn" || path.to_str().map_or(false, |p| p.ends_with(".pgn.gz"))
+            lower == "pgn"
+                || path
+                    .to_str()
+                    .is_some_and(|p| p.ends_with(".pgn.gz") || p.ends_with(".pgn.bz2"))
         })
         .unwrap_or(false)
 }
 
-pub fn create_temp_file() -> Result<(File, PathBuf)> {
-    let tmp = NamedTempFile::with_prefix("pgn2binpack_")?;
+pub fn open_pgn(path: &Path) -> Result<Box<dyn Read>> {
+    let reader: Box<dyn Read> = if path.extension().and_then(|s| s.to_str()) == Some("gz") {
+        let file = File::open(path).with_context(|| format!("opening gz file {:?}", path))?;
+        Box::new(MultiGzDecoder::new(file))
+    } else if path.extension().and_then(|s| s.to_str()) == Some("bz2") {
+        let file = File::open(path).with_context(|| format!("opening bz2 file {:?}", path))?;
+        // archives made by pbzip2 or by concatenating files hold several streams
+        Box::new(MultiBzDecoder::new(BufReader::new(file)))
+    } else {
+        let file = File::open(path).with_context(|| format!("opening file {:?}", path))?;
+        Box::new(file)
+    };
+
+    Ok(reader)
+}
+
+pub fn create_temp_file(dir: Option<&Path>) -> Result<(File, PathBuf)> {
+    let tmp = match dir {
+        Some(dir) => NamedTempFile::with_prefix_in("pgn2binpack_", dir)
+            .with_context(|| format!("creating temp file in {}", dir.display()))?,
+        None => NamedTempFile::with_prefix("pgn2binpack_")?,
+    };
     Ok(tmp.keep()?)
 }
 
-pub fn write_output(path: &Path, rx: mpsc::Receiver<Vec<u8>>) -> Result<()> {
-    let file = File::create(path)?;
-    let mut writer = BufWriter::new(file);
+/// Creates (or truncates) the part file `part-<hash><suffix>` for `source`, where the
+/// hash is taken over the source path, so that re-runs reuse the same names.
+pub fn create_part_file(
+    dir: Option<&Path>,
+    source: &Path,
+    suffix: &str,
+) -> Result<(File, PathBuf)> {
+    // FNV-1a, stable across runs and toolchains unlike the std hasher
+    let hash = source
+        .as_os_str()
+        .as_encoded_bytes()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+
+    let dir = dir.map_or_else(std::env::temp_dir, Path::to_path_buf);
+    let path = dir.join(format!("part-{:016x}{}", hash, suffix));
+    let file =
+        File::create(&path).with_context(|| format!("creating part file {}", path.display()))?;
+    Ok((file, path))
+}
+
+/// File handles a converted file keeps open at once: its PGN and its output part.
+const HANDLES_PER_FILE: usize = 2;
+
+/// Counting semaphore that bounds how many files are converted at once, so that
+/// at most about `max_open_files` handles are open.
+pub struct FileSlots {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// A taken slot, given back on drop.
+pub struct FileSlot<'a> {
+    slots: &'a FileSlots,
+}
+
+impl FileSlots {
+    pub fn new(max_open_files: usize) -> Self {
+        Self {
+            free: Mutex::new((max_open_files / HANDLES_PER_FILE).max(1)),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a slot is free.
+    pub fn acquire(&self) -> FileSlot<'_> {
+        let mut free = self.free.lock().unwrap();
+        while *free == 0 {
+            free = self.freed.wait(free).unwrap();
+        }
+        *free -= 1;
+        FileSlot { slots: self }
+    }
+}
+
+impl Drop for FileSlot<'_> {
+    fn drop(&mut self) {
+        *self.slots.free.lock().unwrap() += 1;
+        self.slots.freed.notify_one();
+    }
+}
+
+/// Half of the soft limit on open files, leaving room for the output, sidecars and
+/// whatever else the process holds.
+pub fn default_max_open_files() -> usize {
+    #[cfg(unix)]
+    {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: getrlimit only writes to the struct we pass
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0 {
+            let soft = usize::try_from(limit.rlim_cur).unwrap_or(usize::MAX);
+            return (soft / 2).max(HANDLES_PER_FILE);
+        }
+    }
+    // the smallest common default soft limit
+    256
+}
+
+/// Copies a non-seekable stream such as stdin into an anonymous temp file and
+/// rewinds it, so that it can be read by the seeking binpack readers.
+pub fn spool_to_temp<R: Read>(mut input: R) -> Result<File> {
+    let mut file = tempfile::tempfile().context("creating spool file")?;
+    std::io::copy(&mut input, &mut file).context("spooling input")?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+/// Opens the output file, either truncating it or appending to existing content.
+/// Binpack blocks are self-contained, so appending yields a valid file.
+pub fn open_output(path: &Path, append: bool) -> Result<File> {
+    let file = if append {
+        File::options().create(true).append(true).open(path)
+    } else {
+        File::create(path)
+    };
+    file.with_context(|| format!("opening output file {}", path.display()))
+}
+
+/// Converted output of one input file: the binpack bytes and the lines of
+/// each requested sidecar for the same entries.
+pub struct FileOutput {
+    pub binpack: Vec<u8>,
+    pub sidecars: Vec<Vec<u8>>,
+}
 
-    for buffer in rx {
-        writer.write_all(&buffer)?;
+/// Writes buffers in the order they arrive. Sidecar data is written alongside
+/// so that its lines stay aligned with the binpack entries. Returns the time
+/// spent writing, not counting the wait for buffers.
+pub fn write_output(
+    path: &Path,
+    sidecars: &[PathBuf],
+    append: bool,
+    rx: mpsc::Receiver<FileOutput>,
+) -> Result<Duration> {
+    let mut busy = Duration::ZERO;
+    let mut writer = BufWriter::new(open_output(path, append)?);
+    let mut sidecar_writers = sidecars
+        .iter()
+        .map(|path| Ok(BufWriter::new(open_output(path, append)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    for output in rx {
+        let t0 = Instant::now();
+        writer.write_all(&output.binpack)?;
+        for (w, data) in sidecar_writers.iter_mut().zip(&output.sidecars) {
+            w.write_all(data)?;
+        }
+        busy += t0.elapsed();
     }
 
+    let t0 = Instant::now();
     writer.flush()?;
-    Ok(())
+    for mut w in sidecar_writers {
+        w.flush()?;
+    }
+    Ok(busy + t0.elapsed())
 }
 
-pub fn concatenate_files(parts: &[PathBuf], output: &Path) -> Result<()> {
-    let file = File::create(output)?;
+pub fn concatenate_files(parts: &[PathBuf], output: &Path, append: bool) -> Result<()> {
+    let file = open_output(output, append)?;
 
     let mut writer = BufWriter::new(file);
 
@@ -68,3 +292,140 @@ pub fn concatenate_files(parts: &[PathBuf], output: &Path) -> Result<()> {
     writer.flush()?;
     Ok(())
 }
+
+/// Writer that discards everything, for runs that only need the conversion itself.
+#[derive(Debug, Default)]
+pub struct NullWriter {
+    pos: u64,
+    len: u64,
+}
+
+impl Write for NullWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pos += buf.len() as u64;
+        self.len = self.len.max(self.pos);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for NullWriter {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(offset) => self.len.saturating_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.saturating_add_signed(offset),
+        };
+        Ok(self.pos)
+    }
+}
+
+/// Reads the input files recorded by previous runs; a missing manifest is empty.
+pub fn read_manifest(path: &Path) -> Result<HashSet<PathBuf>> {
+    if !path.exists() {
+        return Ok(HashSet::new());
+    }
+
+    let file = File::open(path).with_context(|| format!("opening manifest {}", path.display()))?;
+    let mut files = HashSet::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            files.insert(PathBuf::from(line.trim()));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Records converted input files, one path per line.
+pub fn append_manifest(path: &Path, files: &[PathBuf]) -> Result<()> {
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("opening manifest {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    for file in files {
+        writeln!(writer, "{}", file.display())?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{collect_pgn_files, create_part_file, create_temp_file, scan_pgn_files};
+
+    #[test]
+    fn temp_files_are_created_in_requested_dir() {
+        let dir = tempfile::tempdir().expect("tempdir");
+
+        let (_file, path) = create_temp_file(Some(dir.path())).expect("temp file");
+
+        assert_eq!(path.parent(), Some(dir.path()));
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn part_files_are_named_after_the_source() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let part = |source: &str| {
+            let (_file, path) =
+                create_part_file(Some(dir.path()), Path::new(source), ".binpack").unwrap();
+            path
+        };
+
+        let first = part("games/a.pgn");
+        assert_eq!(part("games/a.pgn"), first);
+        assert_ne!(part("games/b.pgn"), first);
+
+        let name = first.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("part-") && name.ends_with(".binpack"));
+        assert_eq!(first.parent(), Some(dir.path()));
+    }
+
+    #[test]
+    fn non_recursive_scan_ignores_subdirectories() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        std::fs::write(dir.path().join("top.pgn"), "x").unwrap();
+        std::fs::write(dir.path().join("nested").join("deep.pgn"), "x").unwrap();
+
+        let names = |recursive| {
+            let scan = scan_pgn_files(dir.path(), None, recursive).unwrap();
+            let mut names: Vec<_> = scan
+                .files
+                .iter()
+                .map(|f| f.file_name().unwrap().to_owned())
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(names(false), ["top.pgn"]);
+        assert_eq!(names(true), ["deep.pgn", "top.pgn"]);
+    }
+
+    #[test]
+    fn oversized_files_are_skipped() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        std::fs::write(dir.path().join("a.pgn"), "x".repeat(10)).unwrap();
+        std::fs::write(dir.path().join("big.pgn"), "x".repeat(100)).unwrap();
+        std::fs::write(dir.path().join("c.pgn"), "x".repeat(50)).unwrap();
+
+        let files = collect_pgn_files(dir.path(), Some(50)).unwrap();
+        let names: Vec<_> = files.iter().map(|f| f.file_name().unwrap()).collect();
+
+        assert_eq!(names, ["a.pgn", "c.pgn"]);
+        assert_eq!(collect_pgn_files(dir.path(), None).unwrap().len(), 3);
+    }
+}
@@ -1,5 +1,7 @@
-use anyhow::Result;
-use clap::Parser;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
 
 mod analytics;
 mod binpack;
@@ -9,8 +11,15 @@ mod process;
 mod util;
 mod wdl;
 
-use crate::cli::Cli;
-use crate::process::process_pgn_files;
+use crate::analytics::errors::ErrorSummary;
+use crate::analytics::summary::Summary;
+use crate::analytics::unique::UniqueOptions;
+use crate::analytics::view::ViewOptions;
+use crate::binpack::builder::SidecarKind;
+use crate::binpack::transform::{self, ScoreScale, SortKey};
+use crate::binpack::BuildOptions;
+use crate::cli::{Backend, Cli, FenNormalization, NullMovePolicy, ScoreFrom, WriterMode};
+use crate::process::{check_pgn_files, process_pgn_files, PgnSource, ProcessOptions};
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -19,13 +28,93 @@ fn main() -> Result<()> {
         anyhow::bail!("--limit can only be used with --unique or --view");
     }
 
+    if cli.dedup_ignore_ep && cli.unique.is_none() && cli.detect_duplicates_across.is_none() {
+        anyhow::bail!(
+            "--dedup-ignore-ep can only be used with --unique or --detect-duplicates-across"
+        );
+    }
+
+    if cli.skip.is_some() && cli.view.is_none() {
+        anyhow::bail!("--skip can only be used with --view");
+    }
+
+    if cli.resume_from_index.is_some() && cli.unique.is_none() && cli.view.is_none() {
+        anyhow::bail!("--resume-from-index can only be used with --unique or --view");
+    }
+
+    if cli.summary_json.is_some() && cli.unique.is_none() && cli.view.is_none() {
+        anyhow::bail!("--summary-json can only be used with --unique or --view");
+    }
+
+    // --view prints its entries to stdout, so its JSON has to go to a file
+    if cli.view.is_some() && matches!(cli.summary_json, Some(None)) {
+        anyhow::bail!("--summary-json needs a FILE with --view, which prints entries to stdout");
+    }
+
+    if cli.filter.is_some() && cli.view.is_none() {
+        anyhow::bail!("--filter can only be used with --view");
+    }
+
+    if cli.eval_only && cli.unique.is_none() && cli.view.is_none() {
+        anyhow::bail!("--eval-only can only be used with --unique or --view");
+    }
+
     if let Some(threads) = cli.threads {
         rayon::ThreadPoolBuilder::new()
             .num_threads(threads)
             .build_global()?;
     }
 
-    if let Some(input) = cli.input_dir {
+    // the same options drive the conversion, --check and --verify-against-pgn, so the
+    // validators judge games exactly as they are converted
+    let sidecars: Vec<(SidecarKind, PathBuf)> = [
+        (SidecarKind::Wdl, cli.wdl_sidecar.clone()),
+        (SidecarKind::LegalMoves, cli.legal_moves_sidecar.clone()),
+        (SidecarKind::BoardFlags, cli.board_flags_sidecar.clone()),
+        (SidecarKind::Termination, cli.termination_sidecar.clone()),
+        (SidecarKind::Clock, cli.clock_sidecar.clone()),
+    ]
+    .into_iter()
+    .filter_map(|(kind, path)| Some((kind, path?)))
+    .collect();
+
+    let build = BuildOptions {
+        verify_on_write: cli.verify_on_write,
+        verify_fen_roundtrip: cli.verify_fen_roundtrip,
+        encoding: cli.pgn_encoding,
+        eval_histogram: cli.eval_histogram,
+        profile: cli.profile,
+        sidecars: sidecars.iter().map(|(kind, _)| *kind).collect(),
+        min_eval_coverage: cli.min_eval_coverage,
+        score_from: cli.score_from,
+        on_null_move: cli.on_null_move,
+        fen_normalization: cli.fen_normalization,
+        eval_regex: cli.eval_regex.clone(),
+        drop_terminations: cli.drop_termination.clone(),
+        min_material: cli.min_material,
+        max_material: cli.max_material,
+        games: None,
+        entry_budget: None,
+        truncate_after_mate: cli.truncate_after_mate,
+        draw_dampen: cli.draw_dampen,
+        result_weight: cli.result_weight,
+        collapse_equal_scores: cli.collapse_equal_scores,
+        keep_unscored: cli.keep_unscored,
+        drop_first_eval: cli.drop_first_eval,
+        check_results: cli.check_results,
+        reservoir_per_game: cli.reservoir_per_game,
+        seed: cli.seed,
+        puzzle_mode: cli.puzzle_mode,
+        skip_errors: cli.skip_errors || cli.error_report.is_some(),
+    };
+
+    let source = match (cli.input_dirs.is_empty(), cli.input_list) {
+        (false, _) => Some(PgnSource::Dirs(cli.input_dirs)),
+        (true, Some(list)) => Some(PgnSource::List(list)),
+        (true, None) => None,
+    };
+
+    if let Some(source) = source {
         if let Some(ref output) = cli.output {
             if output.is_dir() {
                 anyhow::bail!("Output path is a directory: {:?}", output);
@@ -36,60 +125,735 @@ fn main() -> Result<()> {
 
         let output = cli.output.as_ref().unwrap();
 
-        if output.exists() {
+        // skipping already converted files only makes sense when adding to their output
+        let append = cli.append || cli.skip_existing.is_some();
+
+        if output.exists() && !append {
             if !cli.force {
                 anyhow::bail!(
-                    "Output file already exists: {:?}. Use --force to overwrite.",
+                    "Output file already exists: {:?}. Use --force to overwrite or --append to extend it.",
                     output
                 );
             }
-            std::fs::remove_file(&output)?;
+            std::fs::remove_file(output)?;
+        }
+
+        match &source {
+            PgnSource::Dirs(dirs) => {
+                if let Some(dir) = dirs.iter().find(|dir| !dir.exists()) {
+                    anyhow::bail!("Input directory does not exist: {:?}", dir)
+                }
+            }
+            PgnSource::List(list) if !list.is_file() => {
+                anyhow::bail!("Input list does not exist: {:?}", list)
+            }
+            _ => {}
+        }
+
+        if cli.verify_on_write && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--verify-on-write is only supported with the sfbinpack backend");
+        }
+
+        if cli.verify_fen_roundtrip && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--verify-fen-roundtrip is only supported with the sfbinpack backend");
+        }
+
+        if cli.eval_histogram && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--eval-histogram is only supported with the sfbinpack backend");
+        }
+
+        if cli.profile && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--profile is only supported with the sfbinpack backend");
+        }
+
+        if !sidecars.is_empty() && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("sidecar files are only supported with the sfbinpack backend");
+        }
+
+        if cli.writer == WriterMode::Shared {
+            if cli.backend != Backend::Sfbinpack {
+                anyhow::bail!("--writer shared is only supported with the sfbinpack backend");
+            }
+            if !sidecars.is_empty() {
+                anyhow::bail!("sidecar files cannot be combined with --writer shared");
+            }
+        }
+
+        if cli.score_from != ScoreFrom::Comment && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!(
+                "--score-from {} is only supported with the sfbinpack backend",
+                cli.score_from.to_possible_value().unwrap().get_name()
+            );
         }
 
-        if !input.exists() {
-            anyhow::bail!("Input directory does not exist: {:?}", input);
+        if !cli.drop_termination.is_empty() && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--drop-termination is only supported with the sfbinpack backend");
         }
 
-        println!("Searching directory: {}", input.display());
+        if cli.eval_regex.is_some() && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--eval-regex is only supported with the sfbinpack backend");
+        }
+
+        if cli.fen_normalization != FenNormalization::Raw && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--fen-normalization is only supported with the sfbinpack backend");
+        }
+
+        if cli.on_null_move != NullMovePolicy::Error && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--on-null-move is only supported with the sfbinpack backend");
+        }
+
+        if let Some(pct) = cli.min_eval_coverage {
+            if cli.backend != Backend::Sfbinpack {
+                anyhow::bail!("--min-eval-coverage is only supported with the sfbinpack backend");
+            }
+            if !(0.0..=100.0).contains(&pct) {
+                anyhow::bail!("--min-eval-coverage must be between 0 and 100, got {}", pct);
+            }
+        }
+
+        if cli.verify_count && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--verify-count is only supported with the sfbinpack backend");
+        }
+
+        if cli.concat_validate {
+            if cli.backend != Backend::Sfbinpack {
+                anyhow::bail!("--concat-validate is only supported with the sfbinpack backend");
+            }
+            if cli.memory || cli.writer == WriterMode::Shared {
+                anyhow::bail!("--concat-validate can only be used with --memory false");
+            }
+        }
+
+        if cli.truncate_after_mate && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--truncate-after-mate is only supported with the sfbinpack backend");
+        }
+
+        if cli.collapse_equal_scores && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--collapse-equal-scores is only supported with the sfbinpack backend");
+        }
+
+        if cli.keep_unscored.is_some() && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--keep-unscored is only supported with the sfbinpack backend");
+        }
+
+        if cli.drop_first_eval && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--drop-first-eval is only supported with the sfbinpack backend");
+        }
+
+        if cli.check_results && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--check-results is only supported with the sfbinpack backend");
+        }
+
+        if let Some(factor) = cli.draw_dampen {
+            if cli.backend != Backend::Sfbinpack {
+                anyhow::bail!("--draw-dampen is only supported with the sfbinpack backend");
+            }
+            if !(0.0..=1.0).contains(&factor) {
+                anyhow::bail!("--draw-dampen must be between 0 and 1, got {}", factor);
+            }
+        }
+
+        if cli.result_weight.is_some() && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--result-weight is only supported with the sfbinpack backend");
+        }
+
+        if cli.reservoir_per_game.is_some() && cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--reservoir-per-game is only supported with the sfbinpack backend");
+        }
+
+        if cli.puzzle_mode {
+            if cli.backend != Backend::Sfbinpack {
+                anyhow::bail!("--puzzle-mode is only supported with the sfbinpack backend");
+            }
+            if cli.wdl_sidecar.is_some() {
+                anyhow::bail!("--puzzle-mode cannot be combined with --wdl-sidecar");
+            }
+        }
+
+        if cli.games_range.is_some() && (cli.manifest.is_some() || cli.skip_existing.is_some()) {
+            anyhow::bail!("--games-range cannot be combined with --manifest or --skip-existing");
+        }
+
+        if cli.max_entries.is_some() {
+            if cli.backend != Backend::Sfbinpack {
+                anyhow::bail!("--max-entries is only supported with the sfbinpack backend");
+            }
+            // files cut short by the cap would be recorded as converted
+            if cli.manifest.is_some() || cli.skip_existing.is_some() {
+                anyhow::bail!(
+                    "--max-entries cannot be combined with --manifest or --skip-existing"
+                );
+            }
+        }
+
+        if cli.min_material.is_some() || cli.max_material.is_some() {
+            if cli.backend != Backend::Sfbinpack {
+                anyhow::bail!(
+                    "--min-material/--max-material are only supported with the sfbinpack backend"
+                );
+            }
+            if let (Some(min), Some(max)) = (cli.min_material, cli.max_material) {
+                if min > max {
+                    anyhow::bail!("--min-material {} exceeds --max-material {}", min, max);
+                }
+            }
+        }
+
+        if cli.max_open_files == Some(0) {
+            anyhow::bail!("--max-open-files must be at least 1");
+        }
+
+        if let Some(dir) = &cli.tmp_dir {
+            if !dir.is_dir() {
+                anyhow::bail!("Temp directory does not exist: {:?}", dir);
+            }
+        }
+
+        let options = ProcessOptions {
+            use_memory: cli.memory,
+            writer: cli.writer,
+            backend: cli.backend,
+            tmp_dir: cli.tmp_dir.clone(),
+            deterministic_parts: cli.deterministic_parts,
+            append,
+            manifest: cli.manifest.clone().or_else(|| cli.skip_existing.clone()),
+            skip_existing: cli.skip_existing.clone(),
+            sidecars: sidecars.iter().map(|(_, path)| path.clone()).collect(),
+            max_file_size: cli.max_file_size_mb.map(|mb| mb * 1024 * 1024),
+            recursive: !cli.no_recursive,
+            games_range: cli.games_range,
+            concat_validate: cli.concat_validate,
+            verify_count: cli.verify_count,
+            max_entries: cli.max_entries,
+            max_open_files: cli
+                .max_open_files
+                .unwrap_or_else(io::default_max_open_files),
+            verbose: cli.verbose,
+            error_report: cli.error_report.clone(),
+            build: build.clone(),
+        };
+
+        match &source {
+            PgnSource::Dirs(dirs) => {
+                for dir in dirs {
+                    println!("Searching directory: {}", dir.display());
+                }
+            }
+            PgnSource::List(list) => println!("Reading input list: {}", list.display()),
+        }
         println!("Output file: {}", output.display());
         println!("Using {} threads", rayon::current_num_threads());
-        println!("Using memory: {}", if cli.memory { "yes" } else { "no" });
+        match cli.writer {
+            WriterMode::PerFile => {
+                println!("Using memory: {}", if cli.memory { "yes" } else { "no" })
+            }
+            WriterMode::Shared => println!("Using a shared writer"),
+        }
         println!();
 
         let t0 = std::time::Instant::now();
-        let count = process_pgn_files(&input, &output, cli.memory, cli.backend)?;
+        let stats = process_pgn_files(&source, output, &options)?;
         println!("Time taken: {:.2?}", t0.elapsed());
 
-        let filesize = std::fs::metadata(&output)?.len();
+        let filesize = std::fs::metadata(output)?.len();
         println!("\n✓ Binpack created successfully");
         println!("  Output: {}", output.display());
         println!("  Size: {}", human_bytes::human_bytes(filesize as f64));
-        println!("  Positions: {}", count);
+        println!("  Positions: {}", stats.positions);
+        if cli.backend == Backend::Sfbinpack {
+            println!("  Entries: {}", stats.entries);
+        }
+        if options.build.skip_errors {
+            println!("  Skipped games: {}", stats.skipped_games);
+        }
+        if let Some(report) = &cli.error_report {
+            println!("  Error report: {}", report.display());
+        }
+        if let Some(slowest) = stats
+            .file_reports
+            .iter()
+            .max_by_key(|report| report.elapsed)
+        {
+            println!("  Slowest file: {}", slowest);
+        }
+
+        if stats.eval_unit_warnings > 0 {
+            eprintln!(
+                "\nWarning: {} input files had evals that look like centipawns, see above",
+                stats.eval_unit_warnings
+            );
+        }
+
+        if stats.errors.total() > 0 {
+            println!();
+            stats.errors.print();
+        }
+
+        if stats.result_mismatches > 0 {
+            eprintln!(
+                "\nWarning: {} games had a result that contradicts their final position, see above",
+                stats.result_mismatches
+            );
+        }
+
+        if let Some(histogram) = &stats.histogram {
+            println!();
+            histogram.print();
+        }
+
+        if let Some(profile) = &stats.profile {
+            println!();
+            profile.print();
+        }
     }
 
     if let Some(unique) = cli.unique {
-        let file = std::fs::File::options()
-            .read(true)
-            .write(false)
-            .create(false)
-            .open(&unique)?;
-        let t0 = std::time::Instant::now();
-        let unique_count =
-            analytics::unique::unique_positions_from_file(file, cli.limit, cli.backend)?;
-        println!("Completed in {:.2?}", t0.elapsed());
-        println!("Unique positions (Zobrist hashes): {}", unique_count);
+        let file = open_input(&unique)?;
+        let t0 = std::time::Instant::now();
+        let unique_count = analytics::unique::unique_positions_from_file(
+            file,
+            cli.limit,
+            cli.backend,
+            &UniqueOptions {
+                eval_only: cli.eval_only,
+                ignore_ep: cli.dedup_ignore_ep,
+                skip: cli.resume_from_index.unwrap_or(0),
+            },
+        )?;
+        let elapsed = t0.elapsed();
+        // keep stdout to the JSON summary alone when it goes there, so that it parses
+        let json_on_stdout = matches!(cli.summary_json, Some(None));
+        let report = |line: String| {
+            if json_on_stdout {
+                eprintln!("{}", line);
+            } else {
+                println!("{}", line);
+            }
+        };
+        report(format!("Completed in {:.2?}", elapsed));
+        report(format!(
+            "Unique positions (Zobrist hashes): {}",
+            unique_count
+        ));
+
+        if let Some(target) = &cli.summary_json {
+            Summary::new("unique")
+                .string("file", &unique.display().to_string())
+                .number("unique", unique_count)
+                .number("elapsed_ms", elapsed.as_millis())
+                .emit(target.as_deref())?;
+        }
+
+        if let Some(dump) = &cli.dump_zobrist {
+            let out = std::io::BufWriter::new(
+                std::fs::File::create(dump)
+                    .with_context(|| format!("creating {}", dump.display()))?,
+            );
+            let count = analytics::unique::dump_position_hashes(
+                open_input(&unique)?,
+                cli.limit,
+                cli.resume_from_index.unwrap_or(0),
+                cli.backend,
+                analytics::unique::ep_mode(cli.dedup_ignore_ep),
+                out,
+            )?;
+            report(format!("Wrote {} hashes to {}", count, dump.display()));
+        }
     }
 
     if let Some(path) = cli.view {
-        let file = std::fs::File::options()
-            .read(true)
-            .write(false)
-            .create(false)
-            .open(&path)?;
+        // `-` reads the binpack from stdin, which has to be spooled since the readers seek
+        let file = if path.as_os_str() == "-" {
+            io::spool_to_temp(std::io::stdin().lock())?
+        } else {
+            open_input(&path)?
+        };
         let t0 = std::time::Instant::now();
-        analytics::view::view_entries(file, cli.limit, cli.backend)?;
+        let options = ViewOptions {
+            eval_only: cli.eval_only,
+            filter: cli.filter.clone(),
+            skip: cli.skip.or(cli.resume_from_index).unwrap_or(0),
+        };
+        let positions = analytics::view::view_entries(file, cli.limit, cli.backend, &options)?;
+        let elapsed = t0.elapsed();
+        println!("Completed in {:.2?}", elapsed);
+
+        if let Some(target) = &cli.summary_json {
+            Summary::new("view")
+                .string("file", &path.display().to_string())
+                .number("positions", positions)
+                .number("elapsed_ms", elapsed.as_millis())
+                .emit(target.as_deref())?;
+        }
+    }
+
+    if let Some(files) = cli.verify_against_pgn {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--verify-against-pgn is only supported with the sfbinpack backend");
+        }
+
+        let t0 = std::time::Instant::now();
+        let check = analytics::pgn_check::verify_against_pgn(
+            open_input(&files[0])?,
+            io::open_pgn(&files[1])?,
+            &build,
+        )?;
+        println!("Completed in {:.2?}", t0.elapsed());
+
+        match check.divergence {
+            None => println!(
+                "All {} entries of {} match {}",
+                check.matched,
+                files[0].display(),
+                files[1].display()
+            ),
+            Some(d) => anyhow::bail!(
+                "entry {} of {} differs from {} in its {}: expected {}, found {}",
+                d.index,
+                files[0].display(),
+                files[1].display(),
+                d.field,
+                d.expected,
+                d.found
+            ),
+        }
+    }
+
+    if let Some(files) = cli.detect_duplicates_across {
+        let t0 = std::time::Instant::now();
+        let overlap = analytics::overlap::overlap_between(
+            open_input(&files[0])?,
+            open_input(&files[1])?,
+            cli.backend,
+            cli.dedup_ignore_ep,
+        )?;
+        let share = |part: u64, total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                100.0 * part as f64 / total as f64
+            }
+        };
+
+        println!("Completed in {:.2?}", t0.elapsed());
+        println!(
+            "Positions in {} also in {}: {} of {} ({:.2}%)",
+            files[1].display(),
+            files[0].display(),
+            overlap.duplicated,
+            overlap.positions,
+            share(overlap.duplicated, overlap.positions)
+        );
+        println!(
+            "Unique positions shared: {} of {} ({:.2}%)",
+            overlap.unique_duplicated,
+            overlap.unique,
+            share(overlap.unique_duplicated, overlap.unique)
+        );
+    }
+
+    if let Some(input) = cli.normalize_scores {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--normalize-scores is only supported with the sfbinpack backend");
+        }
+
+        let scale = match (
+            cli.scale_factor,
+            cli.target_max,
+            cli.score_transform,
+            cli.clip_percentile,
+            cli.match_scale,
+            cli.score_domain,
+        ) {
+            (Some(factor), None, None, None, None, None) => ScoreScale::Factor(factor),
+            (None, Some(target), None, None, None, None) => ScoreScale::TargetMax(target),
+            (None, None, Some(transform), None, None, None) => ScoreScale::Transform(transform),
+            (None, None, None, None, Some(reference), None) => ScoreScale::MatchScale(reference),
+            (None, None, None, None, None, Some(domain)) => ScoreScale::Domain(domain),
+            (None, None, None, Some(p), None, None) => {
+                if !(0.0..50.0).contains(&p) {
+                    anyhow::bail!("--clip-percentile must be at least 0 and below 50, got {}", p);
+                }
+                ScoreScale::ClipPercentile(p)
+            }
+            _ => anyhow::bail!(
+                "--normalize-scores requires --scale-factor, --target-max, --score-transform, --clip-percentile, --match-scale or --score-domain"
+            ),
+        };
+        let output = prepare_output(cli.output.as_deref(), cli.force)?;
+
+        let t0 = std::time::Instant::now();
+        let count = transform::normalize_scores(&input, output, scale)?;
+        println!("Completed in {:.2?}", t0.elapsed());
+        println!("Wrote {} entries to {}", count, output.display());
+    }
+
+    if let Some(inputs) = cli.interleave {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--interleave is only supported with the sfbinpack backend");
+        }
+
+        let output = prepare_output(cli.output.as_deref(), cli.force)?;
+
+        let t0 = std::time::Instant::now();
+        let count = transform::interleave_files(&inputs, output)?;
+        println!("Completed in {:.2?}", t0.elapsed());
+        println!("Wrote {} entries to {}", count, output.display());
+    }
+
+    if let Some(input) = cli.shuffle {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--shuffle is only supported with the sfbinpack backend");
+        }
+        if cli.shuffle_shards == 0 {
+            anyhow::bail!("--shuffle-shards must be at least 1");
+        }
+
+        let output = prepare_output(cli.output.as_deref(), cli.force)?;
+
+        let t0 = std::time::Instant::now();
+        let count = transform::shuffle_file(
+            &input,
+            output,
+            cli.shuffle_shards,
+            cli.seed,
+            cli.tmp_dir.as_deref(),
+        )?;
+        println!("Completed in {:.2?}", t0.elapsed());
+        println!("Wrote {} entries to {}", count, output.display());
+    }
+
+    if let Some(input) = cli.split {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--split is only supported with the sfbinpack backend");
+        }
+        let Some(output) = cli.output.as_deref() else {
+            anyhow::bail!("Output file must be specified with --output");
+        };
+        let max_bytes = cli.split_bytes.unwrap_or_default();
+        if max_bytes == 0 {
+            anyhow::bail!("--split-bytes must be at least 1");
+        }
+
+        let t0 = std::time::Instant::now();
+        let shards = transform::split_file(&input, output, max_bytes, cli.force)?;
+        println!("Completed in {:.2?}", t0.elapsed());
+        for (path, count) in &shards {
+            println!("Wrote {} entries to {}", count, path.display());
+        }
+    }
+
+    if let Some(input) = cli.split_by_result {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--split-by-result is only supported with the sfbinpack backend");
+        }
+        let Some(out_dir) = cli.output.as_deref() else {
+            anyhow::bail!("Output directory must be specified with --output");
+        };
+
+        let t0 = std::time::Instant::now();
+        let files = transform::split_file_by_result(&input, out_dir, cli.force)?;
+        println!("Completed in {:.2?}", t0.elapsed());
+        for (path, count) in &files {
+            println!("Wrote {} entries to {}", count, path.display());
+        }
+    }
+
+    if let Some(args) = cli.merge_sort {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--merge-sort is only supported with the sfbinpack backend");
+        }
+
+        let key: SortKey = args[0].parse().map_err(anyhow::Error::msg)?;
+        let inputs: Vec<PathBuf> = args[1..].iter().map(PathBuf::from).collect();
+        let output = prepare_output(cli.output.as_deref(), cli.force)?;
+
+        let t0 = std::time::Instant::now();
+        let count = transform::merge_sorted_files(&inputs, output, key)?;
+        println!("Completed in {:.2?}", t0.elapsed());
+        println!("Wrote {} entries to {}", count, output.display());
+    }
+
+    if let Some(input) = cli.to_csv {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--to-csv is only supported with the sfbinpack backend");
+        }
+
+        let output = prepare_output(cli.output.as_deref(), cli.force)?;
+
+        let t0 = std::time::Instant::now();
+        let count = binpack::csv::export_csv_file(&input, output)?;
+        println!("Completed in {:.2?}", t0.elapsed());
+        println!("Wrote {} rows to {}", count, output.display());
+    }
+
+    if let Some(path) = cli.occupancy {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--occupancy is only supported with the sfbinpack backend");
+        }
+
+        let t0 = std::time::Instant::now();
+        let occupancy = analytics::occupancy::occupancy_from_file(open_input(&path)?)?;
+        println!("Completed in {:.2?}", t0.elapsed());
+        println!(
+            "Share of {} positions with each piece on each square:",
+            occupancy.positions()
+        );
+        occupancy.print();
+    }
+
+    if let Some(path) = cli.dump_moves {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--dump-moves is only supported with the sfbinpack backend");
+        }
+
+        let t0 = std::time::Instant::now();
+        let counts = analytics::moves::move_counts_from_file(open_input(&path)?)?;
+        println!("Completed in {:.2?}", t0.elapsed());
+        println!(
+            "Top {} of {} distinct moves in {} entries:",
+            cli.top.min(counts.distinct()),
+            counts.distinct(),
+            counts.entries()
+        );
+        counts.print(cli.top);
+    }
+
+    if let Some(path) = cli.validate_continuity {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--validate-continuity is only supported with the sfbinpack backend");
+        }
+
+        let t0 = std::time::Instant::now();
+        let report = analytics::continuity::check_continuity_of_file(open_input(&path)?)?;
+        println!("Completed in {:.2?}", t0.elapsed());
+        println!(
+            "Checked {} continuations in {} entries",
+            report.continuations, report.entries
+        );
+
+        if let Some(index) = report.first_violation {
+            anyhow::bail!(
+                "the move of entry {} in {} does not lead to the position of the continuation after it",
+                index,
+                path.display()
+            );
+        }
+    }
+
+    if let Some(path) = cli.score_sign_check {
+        if cli.backend != Backend::Sfbinpack {
+            anyhow::bail!("--score-sign-check is only supported with the sfbinpack backend");
+        }
+
+        let t0 = std::time::Instant::now();
+        let check = analytics::sign_check::check_score_signs(open_input(&path)?)?;
+        println!("Completed in {:.2?}", t0.elapsed());
+        println!(
+            "Decisive entries (|score| >= {}): {}",
+            analytics::sign_check::DECISIVE_SCORE,
+            check.decisive
+        );
+        println!("  favour the winner: {}", check.agree);
+        println!(
+            "  favour the loser:  {} ({:.2}%)",
+            check.disagree,
+            100.0 * check.disagreement_rate()
+        );
+
+        if check.looks_flipped() {
+            anyhow::bail!(
+                "most decisive scores in {} favour the losing side; the score sign is likely flipped",
+                path.display()
+            );
+        }
+    }
+
+    if let Some(dir) = cli.check {
+        if !dir.exists() {
+            anyhow::bail!("Input directory does not exist: {:?}", dir);
+        }
+
+        let t0 = std::time::Instant::now();
+        let report = check_pgn_files(&dir, cli.backend, &build)?;
+        let mut errors = ErrorSummary::default();
+        for file in &report {
+            match &file.error {
+                Some(error) => {
+                    errors.add(error);
+                    println!("FAIL {}: {}", file.path.display(), error);
+                }
+                None => println!(
+                    "ok   {}: {} games, {} positions",
+                    file.path.display(),
+                    file.games,
+                    file.positions
+                ),
+            }
+        }
+        println!(
+            "Checked {} files in {:.2?}: {} games, {} positions",
+            report.len(),
+            t0.elapsed(),
+            report.iter().map(|file| file.games).sum::<u64>(),
+            report.iter().map(|file| file.positions).sum::<u64>()
+        );
+
+        if errors.total() > 0 {
+            println!();
+            errors.print();
+            anyhow::bail!(
+                "{} of {} files failed to parse",
+                errors.total(),
+                report.len()
+            );
+        }
+    }
+
+    if let Some(dir) = cli.scan_variants {
+        if !dir.exists() {
+            anyhow::bail!("Input directory does not exist: {:?}", dir);
+        }
+
+        let t0 = std::time::Instant::now();
+        let counts = analytics::variants::scan_variants(&dir)?;
+        println!("{:<24} {:>12}", "Variant", "Games");
+        for (variant, count) in &counts {
+            println!("{:<24} {:>12}", variant, count);
+        }
         println!("Completed in {:.2?}", t0.elapsed());
     }
 
     Ok(())
 }
+
+fn open_input(path: &Path) -> Result<std::fs::File> {
+    Ok(std::fs::File::options()
+        .read(true)
+        .write(false)
+        .create(false)
+        .open(path)?)
+}
+
+/// Checks the --output path of commands that write a new binpack.
+fn prepare_output(output: Option<&Path>, force: bool) -> Result<&Path> {
+    let Some(output) = output else {
+        anyhow::bail!("Output file must be specified with --output");
+    };
+
+    if output.is_dir() {
+        anyhow::bail!("Output path is a directory: {:?}", output);
+    }
+
+    if output.exists() {
+        if !force {
+            anyhow::bail!(
+                "Output file already exists: {:?}. Use --force to overwrite.",
+                output
+            );
+        }
+        std::fs::remove_file(output)?;
+    }
+
+    Ok(output)
+}
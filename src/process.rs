@@ -1,47 +1,448 @@
 use std::{
-    io::Cursor,
+    collections::{HashMap, HashSet},
+    io::{BufWriter, Cursor},
+    ops::Range,
     path::{Path, PathBuf},
+    str::FromStr,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        mpsc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
     },
     thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use rayon::prelude::*;
+use sfbinpack::{CompressedTrainingDataEntryReader, CompressedTrainingDataEntryWriter};
 
-use crate::binpack::BinpackBuilder;
-use crate::cli::Backend;
-use crate::io::{collect_pgn_files, create_temp_file, write_output};
+use crate::analytics::errors::{ErrorReport, ErrorSummary};
+use crate::analytics::histogram::EvalHistogram;
+use crate::analytics::profile::Profile;
+use crate::analytics::variants::count_games;
+use crate::binpack::{BinpackBuilder, BuildOptions, EntryBudget};
+use crate::cli::{Backend, WriterMode};
+use crate::io::{
+    append_manifest, collect_pgn_files, create_part_file, create_temp_file, open_output, open_pgn,
+    read_input_list, read_manifest, scan_pgn_files, write_output, FileOutput, FileSlots,
+    NullWriter, PgnScan,
+};
+
+/// Where the PGN files of a run come from.
+#[derive(Clone, Debug)]
+pub enum PgnSource {
+    /// All PGN files below one or more directories
+    Dirs(Vec<PathBuf>),
+    /// The files named in a list file
+    List(PathBuf),
+}
+
+/// Game indices `[start, end)` counted across all input files in order,
+/// written as `START:END`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GamesRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl FromStr for GamesRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected START:END, got {:?}", s))?;
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<u64>()
+                .map_err(|e| format!("invalid game index {:?}: {}", v, e))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+
+        if start >= end {
+            return Err(format!("empty games range {}:{}", start, end));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+/// Settings for a directory conversion run.
+#[derive(Clone, Debug)]
+pub struct ProcessOptions {
+    pub use_memory: bool,
+    pub writer: WriterMode,
+    pub backend: Backend,
+    /// Directory for the part files of the disk mode (system temp dir if unset)
+    pub tmp_dir: Option<PathBuf>,
+    /// Name part files after a hash of their source file instead of randomly
+    pub deterministic_parts: bool,
+    /// Append to the output file instead of creating it
+    pub append: bool,
+    /// Record the input files converted without errors or skipped games here
+    pub manifest: Option<PathBuf>,
+    /// Skip input files already recorded in this manifest
+    pub skip_existing: Option<PathBuf>,
+    /// Where to write the sidecars requested in `build.sidecars`, in the same order
+    pub sidecars: Vec<PathBuf>,
+    /// Skip input files larger than this many bytes
+    pub max_file_size: Option<u64>,
+    /// Also convert files in subdirectories of the input directories
+    pub recursive: bool,
+    /// Only convert these games, counted across the input files
+    pub games_range: Option<GamesRange>,
+    /// Read the output back after concatenating part files (disk mode)
+    pub concat_validate: bool,
+    /// Stop once this many entries were written in total
+    pub max_entries: Option<u64>,
+    /// Read the output back at the end and fail unless it holds every written entry
+    pub verify_count: bool,
+    /// Bound on the file handles the workers hold at once
+    pub max_open_files: usize,
+    /// Print a [`FileReport`] after each file instead of the progress counter
+    pub verbose: bool,
+    /// List every failed file and skipped game here, as JSON for a `.json` path;
+    /// games are only skipped if `build.skip_errors` is set too
+    pub error_report: Option<PathBuf>,
+    pub build: BuildOptions,
+}
+
+/// Totals of a conversion run.
+#[derive(Clone, Debug, Default)]
+pub struct ProcessStats {
+    pub positions: u64,
+    /// Scores of all written entries, if `build.eval_histogram` was set
+    pub histogram: Option<EvalHistogram>,
+    /// Time per phase, if `build.profile` was set
+    pub profile: Option<Profile>,
+    /// Input files whose evals looked like centipawns
+    pub eval_unit_warnings: usize,
+    /// Games whose result contradicted their final position, if `build.check_results` was set
+    pub result_mismatches: usize,
+    /// Entries written to the output (sfbinpack only)
+    pub entries: u64,
+    /// Games dropped for an error, if `build.skip_errors` was set
+    pub skipped_games: u64,
+    /// Input files that failed, by cause
+    pub errors: ErrorSummary,
+    /// One report per input file in path order, if `verbose` was set
+    pub file_reports: Vec<FileReport>,
+}
+
+/// What one input file contributed to a build.
+#[derive(Clone, Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub positions: u64,
+    pub games: u64,
+    pub elapsed: Duration,
+}
+
+impl std::fmt::Display for FileReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} positions, {} games in {:.2?}",
+            self.path.display(),
+            self.positions,
+            self.games,
+            self.elapsed
+        )
+    }
+}
 
 pub fn process_pgn_files(
-    pgn_root: &Path,
+    source: &PgnSource,
     output_file: &Path,
-    use_memory: bool,
-    backend: Backend,
-) -> Result<u64> {
-    let files = collect_pgn_files(pgn_root)?;
+    options: &ProcessOptions,
+) -> Result<ProcessStats> {
+    let mut files = match source {
+        PgnSource::Dirs(roots) => {
+            let mut scan = PgnScan::default();
+            for root in roots {
+                let found = scan_pgn_files(root, options.max_file_size, options.recursive)?;
+                scan.files.extend(found.files);
+                scan.scanned += found.scanned;
+                scan.skipped_ext += found.skipped_ext;
+                scan.skipped_size += found.skipped_size;
+            }
 
-    if files.is_empty() {
-        anyhow::bail!("No PGN files found in {}", pgn_root.display());
+            if scan.files.is_empty() {
+                let roots: Vec<_> = roots
+                    .iter()
+                    .map(|root| root.display().to_string())
+                    .collect();
+                let mut hint = String::new();
+                if scan.skipped_ext > 0 {
+                    hint.push_str(
+                        "; only .pgn, .pgn.gz and .pgn.bz2 are picked up, so rename other files \
+                         or name them in a file passed to --input-list",
+                    );
+                }
+                if scan.skipped_size > 0 {
+                    hint.push_str("; raise --max-file-size-mb to include the larger files");
+                }
+                anyhow::bail!(
+                    "No PGN files found in {}: scanned {} files, {} skipped for not ending in \
+                     .pgn, .pgn.gz or .pgn.bz2, {} skipped for exceeding the size limit{}",
+                    roots.join(", "),
+                    scan.scanned,
+                    scan.skipped_ext,
+                    scan.skipped_size,
+                    hint
+                );
+            }
+            scan.files
+        }
+        PgnSource::List(list) => read_input_list(list)?,
+    };
+
+    if let (PgnSource::List(list), true) = (source, files.is_empty()) {
+        anyhow::bail!("No PGN files listed in {}", list.display());
     }
 
+    // manifests store canonical paths so that runs with differently spelled roots agree
+    files = files
+        .iter()
+        .map(|file| file.canonicalize())
+        .collect::<std::io::Result<_>>()?;
+
+    // overlapping directories or repeated list lines name the same file more than once
+    let mut seen = HashSet::new();
+    files.retain(|file| seen.insert(file.clone()));
+
+    if let Some(manifest) = &options.skip_existing {
+        let done = read_manifest(manifest)?;
+        let before = files.len();
+        files.retain(|file| !done.contains(file));
+        println!(
+            "Skipping {} files already listed in {}",
+            before - files.len(),
+            manifest.display()
+        );
+    }
+
+    let game_ranges = match options.games_range {
+        Some(range) => {
+            let ranges = split_games_range(&files, range)?;
+            files.retain(|file| ranges.contains_key(file));
+            ranges
+        }
+        None => HashMap::new(),
+    };
+
     println!("Found {} PGN files to process", files.len());
     let completed = AtomicUsize::new(0);
+    let eval_unit_warnings = AtomicUsize::new(0);
+    let result_mismatches = AtomicUsize::new(0);
+    let entries = AtomicU64::new(0);
+    let skipped_games = AtomicU64::new(0);
+    let errors = Mutex::new(ErrorSummary::default());
+    let histogram = Mutex::new(EvalHistogram::default());
+    let profile = Mutex::new(Profile::default());
+    let file_slots = FileSlots::new(options.max_open_files);
+    let file_reports = Mutex::new(Vec::new());
+    let error_report = Mutex::new(ErrorReport::default());
+    let converted = Mutex::new(Vec::new());
+    let shared = Shared {
+        completed: &completed,
+        eval_unit_warnings: &eval_unit_warnings,
+        result_mismatches: &result_mismatches,
+        entries: &entries,
+        skipped_games: &skipped_games,
+        errors: &errors,
+        histogram: &histogram,
+        profile: &profile,
+        file_slots: &file_slots,
+        file_reports: options.verbose.then_some(&file_reports),
+        error_report: options.error_report.is_some().then_some(&error_report),
+        converted: &converted,
+        budget: options.max_entries.map(|n| Arc::new(EntryBudget::new(n))),
+        game_ranges: &game_ranges,
+    };
+
+    // entries of an output that is appended to
+    let existing = if options.verify_count && options.append && output_file.exists() {
+        validate_binpack(output_file)?
+    } else {
+        0
+    };
 
-    if use_memory {
-        process_with_memory_buffer(files, output_file, &completed, backend)
+    let positions = if files.is_empty() {
+        0
+    } else if options.writer == WriterMode::Shared {
+        process_with_shared_writer(&files, output_file, &shared, options)?
+    } else if options.use_memory {
+        process_with_memory_buffer(&files, output_file, &shared, options)?
     } else {
-        process_with_temp_files(files, output_file, &completed, backend)
+        process_with_temp_files(&files, output_file, &shared, options)?
+    };
+
+    let entries = entries.into_inner();
+    if options.verify_count {
+        verify_entry_count(output_file, existing + entries)?;
+        println!(
+            "Verified {}: {} entries",
+            output_file.display(),
+            existing + entries
+        );
+    }
+
+    if let Some(manifest) = &options.manifest {
+        let mut converted = converted.into_inner().unwrap();
+        converted.sort();
+        append_manifest(manifest, &converted)?;
+    }
+
+    if let Some(path) = &options.error_report {
+        error_report.into_inner().unwrap().write(path)?;
+    }
+
+    Ok(ProcessStats {
+        positions,
+        histogram: options
+            .build
+            .eval_histogram
+            .then(|| histogram.into_inner().unwrap()),
+        profile: options.build.profile.then(|| profile.into_inner().unwrap()),
+        eval_unit_warnings: eval_unit_warnings.into_inner(),
+        result_mismatches: result_mismatches.into_inner(),
+        entries,
+        skipped_games: skipped_games.into_inner(),
+        errors: errors.into_inner().unwrap(),
+        file_reports: {
+            let mut reports = file_reports.into_inner().unwrap();
+            reports.sort_by(|a, b| a.path.cmp(&b.path));
+            reports
+        },
+    })
+}
+
+/// Maps a global games range onto the files it covers, as per-file game ranges.
+fn split_games_range(files: &[PathBuf], range: GamesRange) -> Result<HashMap<PathBuf, Range<u64>>> {
+    let counts = files
+        .par_iter()
+        .map(|file| {
+            count_games(open_pgn(file)?)
+                .with_context(|| format!("counting games in {}", file.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut ranges = HashMap::new();
+    let mut offset = 0;
+    for (file, count) in files.iter().zip(counts) {
+        let start = range.start.max(offset);
+        let end = range.end.min(offset + count);
+        if start < end {
+            ranges.insert(file.clone(), start - offset..end - offset);
+        }
+        offset += count;
+    }
+
+    Ok(ranges)
+}
+
+/// State shared by the per-file workers.
+struct Shared<'a> {
+    completed: &'a AtomicUsize,
+    eval_unit_warnings: &'a AtomicUsize,
+    result_mismatches: &'a AtomicUsize,
+    entries: &'a AtomicU64,
+    skipped_games: &'a AtomicU64,
+    errors: &'a Mutex<ErrorSummary>,
+    histogram: &'a Mutex<EvalHistogram>,
+    profile: &'a Mutex<Profile>,
+    /// Taken by a worker for as long as it holds the files of one input
+    file_slots: &'a FileSlots,
+    /// Collects a report per file under --verbose
+    file_reports: Option<&'a Mutex<Vec<FileReport>>>,
+    /// Collects failed files and skipped games under --error-report
+    error_report: Option<&'a Mutex<ErrorReport>>,
+    /// Input files converted without errors, for the manifest
+    converted: &'a Mutex<Vec<PathBuf>>,
+    /// Entries left under --max-entries, shared by all builders
+    budget: Option<Arc<EntryBudget>>,
+    /// Games to convert per file when a games range is set
+    game_ranges: &'a HashMap<PathBuf, Range<u64>>,
+}
+
+impl Shared<'_> {
+    fn build_options(&self, pgn_file: &Path, options: &ProcessOptions) -> BuildOptions {
+        BuildOptions {
+            games: self.game_ranges.get(pgn_file).cloned(),
+            entry_budget: self.budget.clone(),
+            ..options.build.clone()
+        }
+    }
+
+    fn report_error(&self, pgn_file: &Path, e: &anyhow::Error) {
+        eprintln!("\nError processing file {}: {:?}", pgn_file.display(), e);
+        self.errors.lock().unwrap().add(&format!("{:#}", e));
+        if let Some(report) = self.error_report {
+            report
+                .lock()
+                .unwrap()
+                .add(pgn_file, None, format!("{:#}", e));
+        }
+    }
+
+    /// Merges what `builder` collected; `converted` tells whether the file went through
+    /// without an error.
+    fn finish_file<T: std::io::Write + std::io::Seek>(
+        &self,
+        pgn_file: &Path,
+        builder: &BinpackBuilder<T>,
+        converted: bool,
+        started: Instant,
+        total: usize,
+    ) {
+        // files with skipped games are retried by a later --skip-existing run
+        if converted && builder.skipped_games() == 0 {
+            self.converted.lock().unwrap().push(pgn_file.to_path_buf());
+        }
+        if let Some(histogram) = builder.histogram() {
+            self.histogram.lock().unwrap().merge(histogram);
+        }
+        if let Some(profile) = builder.profile() {
+            self.profile.lock().unwrap().merge(profile);
+        }
+        if builder.eval_unit_warning() {
+            self.eval_unit_warnings.fetch_add(1, Ordering::Relaxed);
+        }
+        self.result_mismatches
+            .fetch_add(builder.result_mismatches().len(), Ordering::Relaxed);
+        self.entries
+            .fetch_add(builder.entries_written(), Ordering::Relaxed);
+        self.skipped_games
+            .fetch_add(builder.skipped_games(), Ordering::Relaxed);
+        if let Some(report) = self.error_report {
+            let mut report = report.lock().unwrap();
+            for error in builder.game_errors() {
+                report.add(pgn_file, Some(error.game), error.message.clone());
+            }
+        }
+
+        match self.file_reports {
+            Some(reports) => {
+                let report = FileReport {
+                    path: pgn_file.to_path_buf(),
+                    positions: builder.total_positions(),
+                    games: builder.total_games(),
+                    elapsed: started.elapsed(),
+                };
+                println!("{}", report);
+                self.completed.fetch_add(1, Ordering::SeqCst);
+                reports.lock().unwrap().push(report);
+            }
+            None => update_progress(self.completed, total),
+        }
     }
 }
 
 fn process_with_memory_buffer(
-    files: Vec<PathBuf>,
+    files: &[PathBuf],
     output_file: &Path,
-    completed: &AtomicUsize,
-    backend: Backend,
+    shared: &Shared,
+    options: &ProcessOptions,
 ) -> Result<u64> {
     let total = files.len();
     let (tx, rx) = mpsc::channel();
@@ -49,83 +450,275 @@ fn process_with_memory_buffer(
     // writer thread
     let writer = thread::spawn({
         let path = output_file.to_path_buf();
-        move || write_output(&path, rx)
+        let sidecars = options.sidecars.clone();
+        let append = options.append;
+        move || write_output(&path, &sidecars, append, rx)
     });
 
     // produce buffers in parallel and send to writer
     let positions: Vec<u64> = files
         .par_iter()
-        .map(|file| process_single_file_memory(file, &tx, completed, total, backend))
+        .map(|file| process_single_file_memory(file, &tx, shared, total, options))
         .collect();
 
     // drop the sender to close the channel
     drop(tx);
-    writer.join().unwrap()?;
+    shared.profile.lock().unwrap().writing += writer.join().unwrap()?;
 
     Ok(positions.into_iter().sum())
 }
 
 fn process_single_file_memory(
     pgn_file: &Path,
-    tx: &mpsc::Sender<Vec<u8>>,
-    completed: &AtomicUsize,
+    tx: &mpsc::Sender<FileOutput>,
+    shared: &Shared,
     total: usize,
-    backend: Backend,
+    options: &ProcessOptions,
 ) -> u64 {
-    let mut builder = BinpackBuilder::new(pgn_file, Cursor::new(Vec::new()), backend);
+    let _slot = shared.file_slots.acquire();
+    let t0 = Instant::now();
+    let mut builder = BinpackBuilder::new(
+        pgn_file,
+        Cursor::new(Vec::new()),
+        options.backend,
+        shared.build_options(pgn_file, options),
+    );
 
-    if let Err(e) = builder.create_binpack() {
-        eprintln!("\nError processing file {}: {:?}", pgn_file.display(), e);
+    let converted = builder.create_binpack();
+    if let Err(e) = &converted {
+        shared.report_error(pgn_file, e);
     }
 
     let positions = builder.total_positions();
+    shared.finish_file(pgn_file, &builder, converted.is_ok(), t0, total);
+
+    let sidecars = builder.take_sidecars();
     let buffer = builder.into_inner().unwrap().into_inner();
-    let _ = tx.send(buffer);
+    let _ = tx.send(FileOutput {
+        binpack: buffer,
+        sidecars,
+    });
 
-    update_progress(completed, total);
     positions
 }
 
+fn process_with_shared_writer(
+    files: &[PathBuf],
+    output_file: &Path,
+    shared: &Shared,
+    options: &ProcessOptions,
+) -> Result<u64> {
+    let total = files.len();
+    let output = BufWriter::new(open_output(output_file, options.append)?);
+    let writer = Mutex::new(
+        CompressedTrainingDataEntryWriter::new(output).context("creating binpack writer")?,
+    );
+
+    let positions = files
+        .par_iter()
+        .map(|file| process_single_file_shared(file, &writer, shared, total, options))
+        .sum();
+
+    println!();
+
+    // the writer flushes its last block when dropped
+    let t0 = Instant::now();
+    drop(writer);
+    shared.profile.lock().unwrap().writing += t0.elapsed();
+    Ok(positions)
+}
+
+fn process_single_file_shared<W: std::io::Write + std::io::Seek>(
+    pgn_file: &Path,
+    writer: &Mutex<CompressedTrainingDataEntryWriter<W>>,
+    shared: &Shared,
+    total: usize,
+    options: &ProcessOptions,
+) -> u64 {
+    let _slot = shared.file_slots.acquire();
+    let t0 = Instant::now();
+    // the builder's own output stays empty, entries go to the shared writer
+    let mut builder = BinpackBuilder::new(
+        pgn_file,
+        Cursor::new(Vec::new()),
+        options.backend,
+        shared.build_options(pgn_file, options),
+    );
+
+    let converted = builder.create_binpack_shared(writer);
+    if let Err(e) = &converted {
+        shared.report_error(pgn_file, e);
+    }
+
+    shared.finish_file(pgn_file, &builder, converted.is_ok(), t0, total);
+    builder.total_positions()
+}
+
 fn process_with_temp_files(
-    files: Vec<PathBuf>,
+    files: &[PathBuf],
     output_file: &Path,
-    completed: &AtomicUsize,
-    backend: Backend,
+    shared: &Shared,
+    options: &ProcessOptions,
 ) -> Result<u64> {
     let total = files.len();
 
+    // a file whose parts could not be written is left out like a failed conversion
     let results: Vec<_> = files
         .par_iter()
-        .map(|file| process_single_file_temp(file, completed, total, backend))
+        .filter_map(|file| {
+            process_single_file_temp(file, shared, total, options)
+                .map_err(|e| shared.report_error(file, &e))
+                .ok()
+        })
         .collect();
 
     println!();
 
-    let total_positions = results.iter().map(|(_, n)| n).sum();
-    let temp_files: Vec<_> = results.into_iter().map(|(p, _)| p).collect();
+    let total_positions = results.iter().map(|(_, _, n)| n).sum();
+    let mut temp_files = Vec::with_capacity(results.len());
+    // part files per sidecar, in file order
+    let mut sidecar_files = vec![Vec::new(); options.sidecars.len()];
+    for (path, sidecars, _) in results {
+        temp_files.push(path);
+        for (parts, part) in sidecar_files.iter_mut().zip(sidecars) {
+            parts.push(part);
+        }
+    }
+
+    let t0 = Instant::now();
+    crate::io::concatenate_files(&temp_files, output_file, options.append)?;
+    for (sidecar, parts) in options.sidecars.iter().zip(&sidecar_files) {
+        crate::io::concatenate_files(parts, sidecar, options.append)?;
+    }
+    shared.profile.lock().unwrap().writing += t0.elapsed();
 
-    crate::io::concatenate_files(&temp_files, output_file)?;
+    if options.concat_validate {
+        let entries = validate_binpack(output_file)?;
+        println!("Validated {}: {} entries", output_file.display(), entries);
+    }
     Ok(total_positions)
 }
 
+/// Fails unless the binpack at `path` can be read back with exactly `expected` entries,
+/// which catches blocks that were lost or corrupted on the way to disk.
+pub fn verify_entry_count(path: &Path, expected: u64) -> Result<()> {
+    let found = validate_binpack(path)?;
+    if found != expected {
+        anyhow::bail!(
+            "{} holds {} readable entries but {} were written",
+            path.display(),
+            found,
+            expected
+        );
+    }
+    Ok(())
+}
+
+/// Reads a binpack from start to end and returns its entry count, failing if
+/// any block cannot be decoded.
+pub fn validate_binpack(path: &Path) -> Result<u64> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("opening {} for validation", path.display()))?;
+
+    // the reader panics on blocks it cannot decode
+    let count = std::panic::catch_unwind(move || -> Result<u64> {
+        let mut reader = CompressedTrainingDataEntryReader::new(std::io::BufReader::new(file))?;
+        let mut count = 0;
+        while reader.has_next() {
+            reader.next();
+            count += 1;
+        }
+        Ok(count)
+    });
+
+    match count {
+        Ok(count) => count.with_context(|| format!("{} is not a valid binpack", path.display())),
+        Err(_) => anyhow::bail!("{} is not a valid binpack", path.display()),
+    }
+}
+
+/// Converts one file into its binpack part and sidecar parts. Failing to write the
+/// parts is returned as an error, after which none of them are kept.
 fn process_single_file_temp(
     pgn_file: &Path,
-    completed: &AtomicUsize,
+    shared: &Shared,
     total: usize,
-    backend: Backend,
-) -> (PathBuf, u64) {
-    let (file, path) = create_temp_file().expect("failed to create tempfile");
+    options: &ProcessOptions,
+) -> Result<(PathBuf, Vec<PathBuf>, u64)> {
+    let _slot = shared.file_slots.acquire();
+    let t0 = Instant::now();
+    let create_part = |suffix: &str| {
+        if options.deterministic_parts {
+            create_part_file(options.tmp_dir.as_deref(), pgn_file, suffix)
+        } else {
+            create_temp_file(options.tmp_dir.as_deref())
+        }
+    };
+    let (file, path) = create_part(".binpack")?;
 
-    let mut builder = BinpackBuilder::new(pgn_file, file, backend);
+    let build = shared.build_options(pgn_file, options);
+    let mut builder = BinpackBuilder::new(pgn_file, file, options.backend, build);
 
-    if let Err(e) = builder.create_binpack() {
-        eprintln!("\nError processing file {}: {:?}", pgn_file.display(), e);
+    let converted = builder.create_binpack();
+    if let Err(e) = &converted {
+        shared.report_error(pgn_file, e);
+    }
+
+    // sidecar parts go next to the binpack part and are concatenated in the same order
+    let mut sidecars = Vec::new();
+    for (i, data) in builder.take_sidecars().into_iter().enumerate() {
+        let written = create_part(&format!(".sidecar{}", i)).and_then(|(mut file, part)| {
+            sidecars.push(part.clone());
+            std::io::Write::write_all(&mut file, &data)
+                .with_context(|| format!("writing sidecar part {}", part.display()))
+        });
+        if let Err(e) = written {
+            for part in sidecars.iter().chain([&path]) {
+                let _ = std::fs::remove_file(part);
+            }
+            return Err(e);
+        }
     }
 
     let positions = builder.total_positions();
-    update_progress(completed, total);
+    shared.finish_file(pgn_file, &builder, converted.is_ok(), t0, total);
+
+    Ok((path, sidecars, positions))
+}
+
+/// Outcome of parsing one file with [`check_pgn_files`].
+#[derive(Debug)]
+pub struct FileCheck {
+    pub path: PathBuf,
+    pub games: u64,
+    pub positions: u64,
+    pub error: Option<String>,
+}
+
+/// Runs the full conversion over every file without writing any output, so
+/// that parse errors show up before a long build.
+pub fn check_pgn_files(
+    root: &Path,
+    backend: Backend,
+    build: &BuildOptions,
+) -> Result<Vec<FileCheck>> {
+    let files = collect_pgn_files(root, None)?;
 
-    (path, positions)
+    Ok(files
+        .par_iter()
+        .map(|file| {
+            let mut builder =
+                BinpackBuilder::new(file, NullWriter::default(), backend, build.clone());
+            let error = builder.create_binpack().err().map(|e| format!("{:#}", e));
+
+            FileCheck {
+                path: file.clone(),
+                games: builder.total_games(),
+                positions: builder.total_positions(),
+                error,
+            }
+        })
+        .collect())
 }
 
 fn update_progress(completed: &AtomicUsize, total: usize) {
@@ -135,3 +728,615 @@ fn update_progress(completed: &AtomicUsize, total: usize) {
     print!("\rProcessing: {}/{}", done, total);
     let _ = std::io::stdout().flush();
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{
+        check_pgn_files, process_pgn_files, validate_binpack, verify_entry_count, GamesRange,
+        PgnSource, ProcessOptions,
+    };
+    use crate::analytics::errors::{ErrorCategory, ErrorSummary};
+    use crate::binpack::test_util::read_entries;
+    use crate::binpack::test_util::{game_entries, write_entries};
+    use crate::binpack::BuildOptions;
+    use crate::cli::{Backend, WriterMode};
+    use crate::io::concatenate_files;
+
+    const GAME: &str = "[Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {-0.20/12} 1-0\n";
+
+    fn options(dir: &Path) -> ProcessOptions {
+        ProcessOptions {
+            use_memory: true,
+            writer: WriterMode::PerFile,
+            backend: Backend::Sfbinpack,
+            tmp_dir: None,
+            deterministic_parts: false,
+            append: true,
+            manifest: Some(dir.join("manifest.txt")),
+            skip_existing: Some(dir.join("manifest.txt")),
+            sidecars: Vec::new(),
+            max_file_size: None,
+            recursive: true,
+            games_range: None,
+            concat_validate: false,
+            max_entries: None,
+            verify_count: false,
+            max_open_files: 256,
+            verbose: false,
+            error_report: None,
+            build: BuildOptions::default(),
+        }
+    }
+
+    #[test]
+    fn unwritable_part_files_are_reported_as_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let pgns = dir.path().join("pgns");
+        std::fs::create_dir(&pgns).unwrap();
+        std::fs::write(pgns.join("a.pgn"), GAME).unwrap();
+        std::fs::write(pgns.join("b.pgn"), GAME).unwrap();
+
+        for deterministic_parts in [false, true] {
+            let options = ProcessOptions {
+                use_memory: false,
+                tmp_dir: Some(dir.path().join("missing")),
+                deterministic_parts,
+                manifest: None,
+                skip_existing: None,
+                ..options(dir.path())
+            };
+            let stats = process_pgn_files(
+                &PgnSource::Dirs(vec![pgns.clone()]),
+                &dir.path().join("out.binpack"),
+                &options,
+            )
+            .unwrap();
+            assert_eq!(stats.errors.total(), 2);
+            assert_eq!(stats.positions, 0);
+        }
+    }
+
+    #[test]
+    fn skip_existing_retries_failed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let pgns = dir.path().join("pgns");
+        std::fs::create_dir(&pgns).unwrap();
+        let output = dir.path().join("out.binpack");
+        let source = PgnSource::Dirs(vec![pgns.clone()]);
+
+        std::fs::write(pgns.join("a.pgn"), GAME).unwrap();
+        std::fs::write(
+            pgns.join("b.pgn"),
+            "[Result \"*\"]\n\n1. e4 {+0.20/12} Ke7 *\n",
+        )
+        .unwrap();
+        let first = process_pgn_files(&source, &output, &options(dir.path())).unwrap();
+        assert_eq!(first.errors.total(), 1);
+
+        let manifest = dir.path().join("manifest.txt");
+        let recorded = std::fs::read_to_string(&manifest).unwrap();
+        assert_eq!(
+            recorded.lines().collect::<Vec<_>>(),
+            [pgns.join("a.pgn").canonicalize().unwrap().to_str().unwrap()]
+        );
+
+        // once fixed, the failed file is converted by the next run
+        std::fs::write(pgns.join("b.pgn"), GAME).unwrap();
+        let second = process_pgn_files(&source, &output, &options(dir.path())).unwrap();
+        assert_eq!(second.positions, 2);
+        assert_eq!(second.errors.total(), 0);
+        assert_eq!(
+            std::fs::read_to_string(&manifest).unwrap().lines().count(),
+            2
+        );
+    }
+
+    #[test]
+    fn skip_existing_only_processes_new_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let pgns = dir.path().join("pgns");
+        std::fs::create_dir(&pgns).unwrap();
+        let output = dir.path().join("out.binpack");
+
+        std::fs::write(pgns.join("a.pgn"), GAME).unwrap();
+        let source = PgnSource::Dirs(vec![pgns.clone()]);
+        let first = process_pgn_files(&source, &output, &options(dir.path())).unwrap();
+        assert_eq!(first.positions, 2);
+
+        std::fs::write(pgns.join("b.pgn"), GAME).unwrap();
+        std::fs::write(pgns.join("c.pgn"), GAME).unwrap();
+        let second = process_pgn_files(&source, &output, &options(dir.path())).unwrap();
+        assert_eq!(second.positions, 4);
+
+        let manifest = std::fs::read_to_string(dir.path().join("manifest.txt")).unwrap();
+        assert_eq!(manifest.lines().count(), 3);
+    }
+
+    #[test]
+    fn shared_writer_matches_other_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let pgns = dir.path().join("pgns");
+        std::fs::create_dir(&pgns).unwrap();
+        for name in ["a.pgn", "b.pgn", "c.pgn"] {
+            std::fs::write(pgns.join(name), GAME.repeat(3)).unwrap();
+        }
+
+        let entries = |use_memory, writer| {
+            let output = dir.path().join("out.binpack");
+            let options = ProcessOptions {
+                use_memory,
+                writer,
+                append: false,
+                manifest: None,
+                skip_existing: None,
+                ..options(dir.path())
+            };
+            let stats =
+                process_pgn_files(&PgnSource::Dirs(vec![pgns.clone()]), &output, &options).unwrap();
+            let count = read_entries(std::fs::read(&output).unwrap()).len();
+            (stats.positions, count)
+        };
+
+        let memory = entries(true, WriterMode::PerFile);
+        assert_eq!(memory, (18, 18));
+        assert_eq!(entries(false, WriterMode::PerFile), memory);
+        assert_eq!(entries(true, WriterMode::Shared), memory);
+    }
+
+    #[test]
+    fn bzip2_input_matches_plain_input() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (plain, packed) = (dir.path().join("plain"), dir.path().join("packed"));
+        std::fs::create_dir(&plain).unwrap();
+        std::fs::create_dir(&packed).unwrap();
+        std::fs::write(plain.join("games.pgn"), GAME.repeat(4)).unwrap();
+
+        // two streams, like pbzip2 writes
+        let mut bz2 = Vec::new();
+        for _ in 0..2 {
+            let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+            encoder.write_all(GAME.repeat(2).as_bytes()).unwrap();
+            bz2.extend(encoder.finish().unwrap());
+        }
+        std::fs::write(packed.join("games.pgn.bz2"), bz2).unwrap();
+
+        let entries = |input: &Path| {
+            let output = input.with_extension("binpack");
+            let options = ProcessOptions {
+                manifest: None,
+                skip_existing: None,
+                ..options(dir.path())
+            };
+            let stats = process_pgn_files(
+                &PgnSource::Dirs(vec![input.to_path_buf()]),
+                &output,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(stats.errors.total(), 0);
+            read_entries(std::fs::read(&output).unwrap())
+                .iter()
+                .map(|entry| (entry.ply, entry.score))
+                .collect::<Vec<_>>()
+        };
+
+        let expected = entries(&plain);
+        assert_eq!(expected.len(), 8);
+        assert_eq!(entries(&packed), expected);
+    }
+
+    #[test]
+    fn tiny_open_file_limit_still_converts_every_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let pgns = dir.path().join("pgns");
+        std::fs::create_dir(&pgns).unwrap();
+        for i in 0..8 {
+            std::fs::write(pgns.join(format!("{}.pgn", i)), GAME.repeat(2)).unwrap();
+        }
+
+        for (use_memory, writer) in [
+            (true, WriterMode::PerFile),
+            (false, WriterMode::PerFile),
+            (true, WriterMode::Shared),
+        ] {
+            let output = dir.path().join("out.binpack");
+            let options = ProcessOptions {
+                use_memory,
+                writer,
+                append: false,
+                manifest: None,
+                skip_existing: None,
+                max_open_files: 1,
+                ..options(dir.path())
+            };
+            let stats =
+                process_pgn_files(&PgnSource::Dirs(vec![pgns.clone()]), &output, &options).unwrap();
+            assert_eq!(stats.positions, 32);
+            assert_eq!(read_entries(std::fs::read(&output).unwrap()).len(), 32);
+            assert_eq!(stats.errors.total(), 0);
+        }
+    }
+
+    #[test]
+    fn error_report_lists_bad_games_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let pgns = dir.path().join("pgns");
+        std::fs::create_dir(&pgns).unwrap();
+        let bad_game = "[Result \"*\"]\n\n1. e4 {+0.20/12} e5 {-0.20/12} 2. Ke3 {+0.10/12} *\n";
+        std::fs::write(pgns.join("a.pgn"), [GAME, bad_game, GAME].join("\n")).unwrap();
+        std::fs::write(pgns.join("b.pgn.gz"), "not gzip").unwrap();
+        let a = pgns.join("a.pgn").canonicalize().unwrap();
+        let b = pgns.join("b.pgn.gz").canonicalize().unwrap();
+        let output = dir.path().join("out.binpack");
+
+        for name in ["errors.txt", "errors.json"] {
+            let report = dir.path().join(name);
+            let options = ProcessOptions {
+                error_report: Some(report.clone()),
+                manifest: None,
+                skip_existing: None,
+                build: BuildOptions {
+                    skip_errors: true,
+                    ..BuildOptions::default()
+                },
+                ..options(dir.path())
+            };
+            let stats =
+                process_pgn_files(&PgnSource::Dirs(vec![pgns.clone()]), &output, &options).unwrap();
+            // the good games of a.pgn are still converted
+            assert_eq!(stats.positions, 4);
+            assert_eq!(stats.skipped_games, 1);
+            assert_eq!(stats.errors.total(), 1);
+
+            let report = std::fs::read_to_string(&report).unwrap();
+            if name.ends_with(".json") {
+                assert!(report.starts_with(&format!(
+                    "[{{\"file\": \"{}\", \"game\": 1, \"message\": \"",
+                    a.display()
+                )));
+                assert!(report.contains(&format!(
+                    "{{\"file\": \"{}\", \"game\": null, ",
+                    b.display()
+                )));
+            } else {
+                let lines: Vec<Vec<&str>> = report
+                    .lines()
+                    .map(|line| line.splitn(3, '\t').collect())
+                    .collect();
+                assert_eq!(lines.len(), 2);
+                assert_eq!(lines[0][..2], [a.to_str().unwrap(), "1"]);
+                assert!(lines[0][2].contains("IllegalSan"), "{}", lines[0][2]);
+                assert_eq!(lines[1][..2], [b.to_str().unwrap(), "-"]);
+            }
+        }
+    }
+
+    #[test]
+    fn verbose_reports_every_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let pgns = dir.path().join("pgns");
+        std::fs::create_dir(&pgns).unwrap();
+        std::fs::write(pgns.join("a.pgn"), GAME).unwrap();
+        std::fs::write(pgns.join("b.pgn"), GAME.repeat(3)).unwrap();
+
+        let options = ProcessOptions {
+            verbose: true,
+            manifest: None,
+            skip_existing: None,
+            ..options(dir.path())
+        };
+        let output = dir.path().join("out.binpack");
+        let stats =
+            process_pgn_files(&PgnSource::Dirs(vec![pgns.clone()]), &output, &options).unwrap();
+
+        let lines: Vec<_> = stats
+            .file_reports
+            .iter()
+            .map(|report| {
+                let line = report.to_string();
+                line[..line.find(" in ").unwrap()].to_string()
+            })
+            .collect();
+        let a = pgns.join("a.pgn").canonicalize().unwrap();
+        let b = pgns.join("b.pgn").canonicalize().unwrap();
+        assert_eq!(
+            lines,
+            [
+                format!("{}: 2 positions, 1 games", a.display()),
+                format!("{}: 6 positions, 3 games", b.display()),
+            ]
+        );
+
+        // quiet runs keep only the aggregate
+        let options = ProcessOptions {
+            verbose: false,
+            ..options
+        };
+        let quiet = process_pgn_files(&PgnSource::Dirs(vec![pgns]), &output, &options).unwrap();
+        assert_eq!(quiet.positions, 8);
+        assert!(quiet.file_reports.is_empty());
+    }
+
+    #[test]
+    fn max_entries_caps_the_output_across_workers() {
+        let dir = tempfile::tempdir().unwrap();
+        let pgns = dir.path().join("pgns");
+        std::fs::create_dir(&pgns).unwrap();
+        for name in ["a.pgn", "b.pgn", "c.pgn", "d.pgn"] {
+            std::fs::write(pgns.join(name), GAME.repeat(5)).unwrap();
+        }
+
+        for (use_memory, writer) in [
+            (true, WriterMode::PerFile),
+            (false, WriterMode::PerFile),
+            (true, WriterMode::Shared),
+        ] {
+            let output = dir.path().join("out.binpack");
+            let options = ProcessOptions {
+                use_memory,
+                writer,
+                append: false,
+                manifest: None,
+                skip_existing: None,
+                max_entries: Some(7),
+                ..options(dir.path())
+            };
+            process_pgn_files(&PgnSource::Dirs(vec![pgns.clone()]), &output, &options).unwrap();
+
+            // 3 whole games and the first move of a fourth
+            let entries = read_entries(std::fs::read(&output).unwrap());
+            assert_eq!(entries.len(), 7, "{writer:?}, memory {use_memory}");
+            let game_starts = entries.iter().filter(|e| e.ply == 0).count();
+            assert_eq!(game_starts, 4);
+        }
+    }
+
+    #[test]
+    fn input_list_processes_only_listed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.pgn", "b.pgn", "c.pgn"] {
+            std::fs::write(dir.path().join(name), GAME).unwrap();
+        }
+        let list = dir.path().join("inputs.txt");
+        std::fs::write(
+            &list,
+            format!(
+                "# nightly batch\n{}\n\n  {}\n",
+                dir.path().join("a.pgn").display(),
+                dir.path().join("c.pgn").display()
+            ),
+        )
+        .unwrap();
+
+        let stats = process_pgn_files(
+            &PgnSource::List(list),
+            &dir.path().join("out.binpack"),
+            &options(dir.path()),
+        )
+        .unwrap();
+
+        let manifest = std::fs::read_to_string(dir.path().join("manifest.txt")).unwrap();
+        let names: Vec<&str> = manifest
+            .lines()
+            .map(|line| line.rsplit(['/', '\\']).next().unwrap())
+            .collect();
+
+        assert_eq!(stats.positions, 4);
+        assert_eq!(names, ["a.pgn", "c.pgn"]);
+    }
+
+    #[test]
+    fn check_reports_only_the_broken_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.pgn"), GAME.repeat(2)).unwrap();
+        std::fs::write(
+            dir.path().join("b.pgn"),
+            "[Result \"1-0\"]\n\n1. e5 {+0.30/12} 1-0\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("c.pgn"), GAME).unwrap();
+
+        let report =
+            check_pgn_files(dir.path(), Backend::Sfbinpack, &BuildOptions::default()).unwrap();
+
+        let failed: Vec<_> = report.iter().filter(|file| file.error.is_some()).collect();
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].path.ends_with("b.pgn"));
+        assert_eq!(report.iter().map(|file| file.games).sum::<u64>(), 3);
+        assert_eq!(report.iter().map(|file| file.positions).sum::<u64>(), 6);
+        assert!(!dir.path().join("out.binpack").exists());
+    }
+
+    #[test]
+    fn check_errors_are_counted_by_category() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8]); 6] = [
+            ("ok.pgn", GAME.as_bytes()),
+            ("san.pgn", b"[Result \"*\"]\n\n1. e5 *\n"),
+            (
+                "fen.pgn",
+                b"[Result \"*\"]\n[FEN \"not a fen\"]\n\n1. e4 *\n",
+            ),
+            (
+                "variant.pgn",
+                b"[Result \"*\"]\n[Variant \"Atomic\"]\n\n1. e4 *\n",
+            ),
+            ("eval.pgn", b"[Result \"*\"]\n\n1. e4 {??} *\n"),
+            (
+                "eval2.pgn",
+                b"[Result \"*\"]\n\n1. e4 {+0.30/12} e5 {!!} *\n",
+            ),
+        ];
+        for (name, data) in files {
+            std::fs::write(dir.path().join(name), data).unwrap();
+        }
+        std::fs::write(
+            dir.path().join("utf8.pgn"),
+            b"[Result \"*\"]\n\n1. e4 {\xff} *\n",
+        )
+        .unwrap();
+
+        let report =
+            check_pgn_files(dir.path(), Backend::Sfbinpack, &BuildOptions::default()).unwrap();
+        let mut errors = ErrorSummary::default();
+        report
+            .iter()
+            .filter_map(|file| file.error.as_deref())
+            .for_each(|error| errors.add(error));
+
+        assert_eq!(errors.total(), 6);
+        assert_eq!(errors.count(ErrorCategory::SanParse), 1);
+        assert_eq!(errors.count(ErrorCategory::FenParse), 1);
+        assert_eq!(errors.count(ErrorCategory::UnsupportedVariant), 1);
+        assert_eq!(errors.count(ErrorCategory::EvalParse), 2);
+        assert_eq!(errors.count(ErrorCategory::Utf8), 1);
+    }
+
+    #[test]
+    fn games_range_counts_games_across_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let pgns = dir.path().join("pgns");
+        std::fs::create_dir(&pgns).unwrap();
+        let game = |mv: &str| format!("[Result \"1-0\"]\n\n1. {mv} {{+0.30/12}} 1-0\n\n");
+        // games 0..3 in a.pgn, 3..5 in b.pgn
+        std::fs::write(pgns.join("a.pgn"), game("e4") + &game("d4") + &game("c4")).unwrap();
+        std::fs::write(pgns.join("b.pgn"), game("Nf3") + &game("g3")).unwrap();
+
+        let output = dir.path().join("out.binpack");
+        let options = ProcessOptions {
+            manifest: None,
+            skip_existing: None,
+            games_range: Some("2:4".parse().unwrap()),
+            ..options(dir.path())
+        };
+        let stats = process_pgn_files(&PgnSource::Dirs(vec![pgns]), &output, &options).unwrap();
+
+        let entries = read_entries(std::fs::read(&output).unwrap());
+        let moves: Vec<String> = entries.iter().map(|e| e.mv.as_uci().to_string()).collect();
+        assert_eq!(stats.positions, 2);
+        assert_eq!(moves, ["c2c4", "g1f3"]);
+        assert!("4:2".parse::<GamesRange>().is_err());
+    }
+
+    #[test]
+    fn concat_validate_rejects_corrupted_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let part = |name: &str, data: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, data).unwrap();
+            path
+        };
+        let valid = write_entries(&game_entries(&[(12, 28, 10), (52, 36, -10)], 0));
+        let output = dir.path().join("out.binpack");
+
+        let parts = [part("a.part", &valid), part("b.part", &valid)];
+        concatenate_files(&parts, &output, false).unwrap();
+        assert_eq!(validate_binpack(&output).unwrap(), 4);
+
+        let parts = [
+            part("a.part", &valid),
+            part("b.part", b"not a binpack block\n"),
+        ];
+        concatenate_files(&parts, &output, false).unwrap();
+        assert!(validate_binpack(&output).is_err());
+    }
+
+    #[test]
+    fn verify_count_checks_the_written_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let pgns = dir.path().join("pgns");
+        std::fs::create_dir(&pgns).unwrap();
+        // the unscored move counts as a position but is not written
+        let unscored = "[Result \"1-0\"]\n\n1. d4 {+0.20/12} d5 1-0\n";
+        std::fs::write(pgns.join("a.pgn"), format!("{GAME}\n{unscored}")).unwrap();
+
+        let output = dir.path().join("out.binpack");
+        let options = ProcessOptions {
+            verify_count: true,
+            manifest: None,
+            skip_existing: None,
+            ..options(dir.path())
+        };
+        let source = PgnSource::Dirs(vec![pgns]);
+        let stats = process_pgn_files(&source, &output, &options).unwrap();
+        assert_eq!((stats.positions, stats.entries), (4, 3));
+        // appending checks the old entries too
+        process_pgn_files(&source, &output, &options).unwrap();
+
+        // a file that lost its last entry
+        let short = dir.path().join("short.binpack");
+        std::fs::write(
+            &short,
+            write_entries(&game_entries(&[(12, 28, 10), (52, 36, -10), (6, 21, 5)], 0)),
+        )
+        .unwrap();
+        verify_entry_count(&short, 3).unwrap();
+        assert!(verify_entry_count(&short, 4).is_err());
+    }
+
+    #[test]
+    fn multiple_directories_are_combined_without_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        std::fs::create_dir_all(first.join("nested")).unwrap();
+        std::fs::create_dir(&second).unwrap();
+        std::fs::write(first.join("a.pgn"), GAME).unwrap();
+        std::fs::write(first.join("nested").join("b.pgn"), GAME).unwrap();
+        std::fs::write(second.join("c.pgn"), GAME).unwrap();
+
+        // the nested directory is also reached through the first one
+        let source = PgnSource::Dirs(vec![first.clone(), second, first.join("nested")]);
+        let options = ProcessOptions {
+            append: false,
+            ..options(dir.path())
+        };
+        let stats = process_pgn_files(&source, &dir.path().join("out.binpack"), &options).unwrap();
+
+        let recorded = std::fs::read_to_string(dir.path().join("manifest.txt")).unwrap();
+        let mut names: Vec<_> = recorded
+            .lines()
+            .map(|line| {
+                Path::new(line)
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        names.sort();
+
+        assert_eq!(stats.positions, 6);
+        assert_eq!(names, ["a.pgn", "b.pgn", "c.pgn"]);
+    }
+
+    #[test]
+    fn empty_directory_error_reports_skipped_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let pgns = dir.path().join("pgns");
+        std::fs::create_dir(&pgns).unwrap();
+        std::fs::write(pgns.join("games.txt"), GAME).unwrap();
+        std::fs::write(pgns.join("games.pgn.bak"), GAME).unwrap();
+
+        let err = process_pgn_files(
+            &PgnSource::Dirs(vec![pgns]),
+            &dir.path().join("out.binpack"),
+            &options(dir.path()),
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.contains("scanned 2 files"), "{err}");
+        assert!(
+            err.contains("2 skipped for not ending in .pgn, .pgn.gz or .pgn.bz2"),
+            "{err}"
+        );
+        assert!(err.contains("--input-list"), "{err}");
+        assert!(!err.contains("--max-file-size-mb"), "{err}");
+    }
+}
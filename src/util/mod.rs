@@ -1 +1,3 @@
+pub mod rng;
+#[allow(clippy::module_inception)]
 pub mod util;
@@ -0,0 +1,47 @@
+/// Small seeded generator (SplitMix64) for reproducible sampling.
+#[derive(Clone, Debug)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `0..n`; `n` must not be zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((u128::from(self.next_u64()) * u128::from(n)) >> 64) as u64
+    }
+}
+
+/// Shuffles `items` in place (Fisher-Yates).
+pub fn shuffle<T>(items: &mut [T], rng: &mut SplitMix64) {
+    for i in (1..items.len()).rev() {
+        let j = rng.below(i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
+}
+
+/// Picks `k` of the indices `0..len` uniformly (reservoir sampling), in ascending order.
+pub fn reservoir_sample(len: usize, k: usize, rng: &mut SplitMix64) -> Vec<usize> {
+    let mut reservoir: Vec<usize> = (0..len.min(k)).collect();
+
+    for i in k..len {
+        let j = rng.below(i as u64 + 1) as usize;
+        if j < k {
+            reservoir[j] = i;
+        }
+    }
+
+    reservoir.sort_unstable();
+    reservoir
+}
@@ -13,62 +13,309 @@ use viriformat::chess::{
     types::Square as ViriSquare,
 };
 
+use regex::Regex;
 use shakmaty::{Move, Role};
 
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use crate::cli::PgnEncoding;
+use crate::wdl::wdl::MATE_SCORE;
+
+/// Decodes a raw PGN tag name, tag value or comment.
+pub fn decode_pgn_text(
+    bytes: &[u8],
+    encoding: PgnEncoding,
+) -> Result<Cow<'_, str>, std::str::Utf8Error> {
+    match encoding {
+        PgnEncoding::Utf8 => std::str::from_utf8(bytes).map(Cow::Borrowed),
+        PgnEncoding::Latin1 => Ok(encoding_rs::WINDOWS_1252
+            .decode_without_bom_handling(bytes)
+            .0),
+    }
+}
+
+/// Parses the evaluation of a PGN move comment in centipawns. Mate scores
+/// `+M<n>`/`-M<n>` become `±(32000 - n)`, so shorter mates keep ranking higher.
+/// Lichess `[%eval ...]` commands are read too; other commands such as `[%clk ...]`
+/// are ignored, and a comment of only those has no eval.
 pub fn parse_eval_cp(comment: &str) -> Result<Option<i16>, &'static str> {
     if (comment == "book") || (comment == "Book") {
         return Ok(Some(0));
     }
 
+    let (commands, rest) = split_commands(comment);
+    if let Some((_, value)) = commands.iter().find(|(name, _)| *name == "eval") {
+        return parse_lichess_eval(value)
+            .map(Some)
+            .ok_or("Unable to parse evaluation");
+    }
+    if !commands.is_empty() && rest.trim().is_empty() {
+        return Ok(None);
+    }
+    let comment = rest.as_str();
+
     if comment == "No result" {
         return Ok(None);
     }
 
     // Matches examples like:
     // {+1.01/26 1.2s} {-0.34/15} {+0.00} {-M21/32 0.5s} {+M21/32 0.5s}
-    for part in comment.split(|c: char| c.is_whitespace() || c == '{' || c == '}') {
-        if part.is_empty() {
+    for p in top_level_words(comment) {
+        if let Some(cp) = parse_eval_word(p) {
+            return Ok(Some(cp));
+        }
+    }
+
+    Err("Unable to parse evaluation")
+}
+
+/// Like [`parse_eval_cp`], but when the comment, including comments nested in it,
+/// holds several `eval/depth` words, the eval searched deepest wins. Comments
+/// without any depth are parsed as by [`parse_eval_cp`].
+pub fn parse_deepest_eval_cp(comment: &str) -> Result<Option<i16>, &'static str> {
+    let mut deepest: Option<(u32, i16)> = None;
+
+    for word in comment.split(|c: char| c.is_whitespace() || c == '{' || c == '}') {
+        let Some((eval, depth)) = word.split_once('/') else {
+            continue;
+        };
+        let Ok(depth) = depth
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect::<String>()
+            .parse::<u32>()
+        else {
             continue;
+        };
+        let Some(cp) = parse_eval_word(eval) else {
+            continue;
+        };
+
+        // ties keep the earlier eval
+        if deepest.is_none_or(|(best, _)| depth > best) {
+            deepest = Some((depth, cp));
         }
-        let p = part.trim_matches(|c| c == '{' || c == '}');
-
-        // mate
-        if p.starts_with("+M") || p.starts_with("-M") {
-            let sign = if p.starts_with("+M") { 1 } else { -1 };
-            if let Ok(n) = p[2..]
-                .chars()
-                .take_while(|c| c.is_ascii_digit())
-                .collect::<String>()
-                .parse::<i32>()
-            {
-                return Ok(Some((32000 - n) as i16 * sign));
-            }
+    }
+
+    match deepest {
+        Some((_, cp)) => Ok(Some(cp)),
+        None => parse_eval_cp(comment),
+    }
+}
+
+/// A user-supplied pattern for eval comments; its named group `cp` captures the
+/// eval in centipawns, or `pawns` in pawns. Tried before the built-in parsers.
+#[derive(Clone, Debug)]
+pub struct EvalRegex {
+    regex: Regex,
+    pawns: bool,
+}
+
+impl EvalRegex {
+    /// The eval in centipawns, or `None` if the comment does not match.
+    pub fn parse(&self, comment: &str) -> Option<i16> {
+        let group = if self.pawns { "pawns" } else { "cp" };
+        let captures = self.regex.captures(comment)?;
+        let value = captures.name(group)?.as_str().parse::<f64>().ok()?;
+        let cp = if self.pawns { value * 100.0 } else { value };
+        let limit = f64::from(MATE_SCORE);
+        Some(cp.round().clamp(-limit, limit) as i16)
+    }
+}
+
+impl FromStr for EvalRegex {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let regex = Regex::new(s).map_err(|e| e.to_string())?;
+        let has = |group| regex.capture_names().flatten().any(|name| name == group);
+
+        match (has("cp"), has("pawns")) {
+            (true, false) => Ok(Self {
+                regex,
+                pawns: false,
+            }),
+            (false, true) => Ok(Self { regex, pawns: true }),
+            _ => Err("the regex needs exactly one of the named groups `cp` or `pawns`".into()),
+        }
+    }
+}
+
+/// Splits the `[%name args]` commands that Lichess embeds in comments, e.g.
+/// `[%eval -0.34] [%clk 0:05:12]`, from the remaining text.
+fn split_commands(comment: &str) -> (Vec<(&str, &str)>, String) {
+    let mut commands = Vec::new();
+    let mut rest = String::new();
+    let mut tail = comment;
+
+    while let Some(start) = tail.find("[%") {
+        let Some(len) = tail[start..].find(']') else {
+            break;
+        };
+        rest.push_str(&tail[..start]);
+        let body = tail[start + 2..start + len].trim();
+        commands.push(body.split_once(char::is_whitespace).unwrap_or((body, "")));
+        tail = &tail[start + len + 1..];
+    }
+    rest.push_str(tail);
+
+    (commands, rest)
+}
+
+/// Remaining clock in seconds from a `[%clk h:mm:ss]` or `[%clk mm:ss]` command,
+/// seconds may carry a fraction.
+pub fn parse_clock(comment: &str) -> Option<f64> {
+    let (commands, _) = split_commands(comment);
+    let (_, value) = commands.into_iter().find(|(name, _)| *name == "clk")?;
+
+    let mut seconds = 0.0;
+    let mut fields = 0;
+    for field in value.trim().split(':') {
+        let field: f64 = field.parse().ok().filter(|v: &f64| *v >= 0.0)?;
+        seconds = seconds * 60.0 + field;
+        fields += 1;
+    }
+    (2..=3).contains(&fields).then_some(seconds)
+}
+
+/// Parses the argument of a `[%eval ...]` command: pawns like `-0.34`, optionally
+/// followed by `,<depth>`, or a mate like `#-3`.
+fn parse_lichess_eval(value: &str) -> Option<i16> {
+    let value = value.split(',').next()?.trim();
+
+    if let Some(mate) = value.strip_prefix('#') {
+        let (sign, n) = match mate.strip_prefix('-') {
+            Some(n) => (-1, n),
+            None => (1, mate.strip_prefix('+').unwrap_or(mate)),
+        };
+        return Some(mate_score(n.parse().ok()?, sign));
+    }
+
+    let pawns = value.parse::<f64>().ok()?;
+    let limit = f64::from(MATE_SCORE);
+    Some((pawns * 100.0).round().clamp(-limit, limit) as i16)
+}
+
+/// Score of a mate in `distance` moves for the side given by `sign`: `±(32000 - n)`,
+/// so that shorter mates score higher in every comment format.
+fn mate_score(distance: i32, sign: i32) -> i16 {
+    ((MATE_SCORE - distance.min(MATE_SCORE)) * sign) as i16
+}
+
+/// Parses one word of a comment as an eval in centipawns, e.g. `+1.01/26` or `-M21/32`.
+fn parse_eval_word(p: &str) -> Option<i16> {
+    // mate
+    if p.starts_with("+M") || p.starts_with("-M") {
+        let sign = if p.starts_with("+M") { 1 } else { -1 };
+        let n = p[2..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect::<String>()
+            .parse::<i32>()
+            .ok()?;
+        return Some(mate_score(n, sign));
+    }
+
+    let num = p.split('/').next().unwrap_or(p);
+    let first = num.chars().next()?;
+    if first != '+' && first != '-' && !first.is_ascii_digit() {
+        return None;
+    }
+
+    let mut cleaned = String::new();
+    for ch in num.chars() {
+        if ch.is_ascii_digit() || ch == '+' || ch == '-' || ch == '.' {
+            cleaned.push(ch);
         } else {
-            let num = p.split('/').next().unwrap_or(p);
-            if let Some(first) = num.chars().next() {
-                if first == '+' || first == '-' || first.is_ascii_digit() {
-                    let mut cleaned = String::new();
-                    for ch in num.chars() {
-                        if ch.is_ascii_digit() || ch == '+' || ch == '-' || ch == '.' {
-                            cleaned.push(ch);
-                        } else {
-                            break;
-                        }
-                    }
-                    if cleaned == "+" || cleaned == "-" {
-                        continue;
-                    }
-                    if let Ok(f) = cleaned.parse::<f32>() {
-                        return Ok(Some((f * 100.0).round() as i16));
-                    }
-                }
+            break;
+        }
+    }
+    if cleaned == "+" || cleaned == "-" {
+        return None;
+    }
+    cleaned
+        .parse::<f32>()
+        .ok()
+        .map(|f| (f * 100.0).round() as i16)
+}
+
+/// Evals per file looked at by [`EvalUnitCheck`].
+pub const EVAL_UNIT_SAMPLES: usize = 256;
+
+/// Fewest evals [`EvalUnitCheck`] needs before judging a file.
+pub const EVAL_UNIT_MIN_SAMPLES: usize = 8;
+
+/// Median eval magnitude in centipawns above which a file is taken to store
+/// centipawns; engine games read as pawns stay far below 10 pawns.
+pub const CENTIPAWN_MEDIAN_THRESHOLD: i32 = 1000;
+
+/// Samples the parsed evals of a file to catch comments written in centipawns,
+/// which [`parse_eval_cp`] reads as pawns and so inflates a hundredfold.
+#[derive(Clone, Debug, Default)]
+pub struct EvalUnitCheck {
+    samples: Vec<i32>,
+}
+
+impl EvalUnitCheck {
+    pub fn add(&mut self, cp: i16) {
+        if self.samples.len() < EVAL_UNIT_SAMPLES {
+            self.samples.push(i32::from(cp).abs());
+        }
+    }
+
+    /// Median magnitude of the sampled evals, if there are enough of them.
+    pub fn median(&self) -> Option<i32> {
+        if self.samples.len() < EVAL_UNIT_MIN_SAMPLES {
+            return None;
+        }
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        Some(sorted[sorted.len() / 2])
+    }
+
+    pub fn looks_like_centipawns(&self) -> bool {
+        self.median()
+            .is_some_and(|median| median > CENTIPAWN_MEDIAN_THRESHOLD)
+    }
+}
+
+/// Splits a comment into whitespace separated words, skipping anything inside
+/// braces embedded in the comment. Comments that still carry their own braces
+/// (`{+0.50/20}`) are read one level deep.
+fn top_level_words(comment: &str) -> Vec<&str> {
+    let comment = comment.trim();
+    let top = usize::from(comment.starts_with('{'));
+
+    let mut words = Vec::new();
+    let mut depth = 0usize;
+    let mut start = None;
+
+    for (i, c) in comment.char_indices() {
+        let separator = c.is_whitespace() || c == '{' || c == '}';
+        if separator {
+            if let Some(s) = start.take() {
+                words.push(&comment[s..i]);
             }
         }
+
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            _ if !separator && depth <= top && start.is_none() => start = Some(i),
+            _ => {}
+        }
     }
 
-    Err("Unable to parse evaluation")
+    if let Some(s) = start {
+        words.push(&comment[s..]);
+    }
+
+    words
 }
 
+/// Converts a shakmaty move to sfbinpack's encoding. Castling is written as the king
+/// capturing its rook, which also covers Chess960.
 pub fn convert_move(mv: &Move, color: SfColor) -> SfMove {
     let mut move_type = SfMoveType::Normal;
     let mut promo_piece = SfPiece::none();
@@ -126,3 +373,101 @@ pub fn convert_move_viriformat(mv: &Move) -> AnyResult<ViriMove> {
 
     Ok(converted)
 }
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::Chess;
+
+    use super::{parse_clock, parse_deepest_eval_cp, parse_eval_cp, EvalRegex};
+    use crate::wdl::wdl::external_cp_to_internal;
+
+    #[test]
+    fn eval_regex_reads_custom_comment_formats() {
+        let cp: EvalRegex = r"score=(?P<cp>-?\d+)cp".parse().unwrap();
+        assert_eq!(cp.parse("d=20 score=-35cp t=1.2"), Some(-35));
+        assert_eq!(cp.parse("book"), None);
+
+        let pawns: EvalRegex = r"\[%eval (?P<pawns>[-+]?\d+(\.\d+)?)\]".parse().unwrap();
+        assert_eq!(pawns.parse("[%eval 0.42] [%clk 0:01:00]"), Some(42));
+
+        assert!("score=(-?\\d+)".parse::<EvalRegex>().is_err());
+        assert!("(?P<cp>\\d+)(?P<pawns>\\d+)".parse::<EvalRegex>().is_err());
+    }
+
+    #[test]
+    fn deepest_eval_wins_over_the_first() {
+        let comment = "{+0.50/10 0.1s {+0.62/24 1.5s} {+0.41/18}}";
+
+        assert_eq!(parse_eval_cp(comment), Ok(Some(50)));
+        assert_eq!(parse_deepest_eval_cp(comment), Ok(Some(62)));
+        assert_eq!(parse_deepest_eval_cp("-M5/30 {+3.10/20}"), Ok(Some(-31995)));
+        // without depths the first eval is used
+        assert_eq!(parse_deepest_eval_cp("+0.25 -0.10"), Ok(Some(25)));
+        assert_eq!(parse_deepest_eval_cp("book"), Ok(Some(0)));
+    }
+
+    #[test]
+    fn mate_distance_survives_conversion() {
+        let pos = Chess::default();
+        let stored = |comment| {
+            let cp = parse_eval_cp(comment).unwrap().unwrap();
+            external_cp_to_internal(i32::from(cp), &pos)
+        };
+
+        assert_eq!(stored("+M3/20 0.5s"), 31997);
+        assert_eq!(stored("+M10/20 0.5s"), 31990);
+        assert!(stored("+M3/20") > stored("+M10/20"));
+        assert!(stored("-M3/20") < stored("-M10/20"));
+    }
+
+    #[test]
+    fn mate_scores_order_by_distance_in_every_format() {
+        let cp = |comment: &str| parse_eval_cp(comment).unwrap().unwrap();
+
+        for n in [1, 5, 20] {
+            assert_eq!(cp(&format!("+M{n}/30")), cp(&format!("[%eval #{n}]")));
+            assert_eq!(cp(&format!("-M{n}/30")), cp(&format!("[%eval #-{n}]")));
+        }
+        assert!(cp("[%eval #1]") > cp("+M5/30") && cp("+M5/30") > cp("[%eval #20]"));
+        assert!(cp("[%eval #-1]") < cp("-M5/30") && cp("-M5/30") < cp("[%eval #-20]"));
+        // even a long mate outranks any regular eval
+        assert!(cp("+M200/30") > cp("+99.00/30"));
+    }
+
+    #[test]
+    fn lichess_eval_commands_are_read_and_clocks_ignored() {
+        assert_eq!(
+            parse_eval_cp(" [%eval -0.34] [%clk 0:05:12] "),
+            Ok(Some(-34))
+        );
+        assert_eq!(parse_eval_cp("[%clk 0:05:12] [%eval 1.5]"), Ok(Some(150)));
+        assert_eq!(parse_eval_cp("[%eval 0.17,23]"), Ok(Some(17)));
+        assert_eq!(
+            parse_eval_cp("[%eval #-3] [%clk 0:00:09]"),
+            Ok(Some(-31997))
+        );
+        assert_eq!(parse_eval_cp("[%eval #2]"), Ok(Some(31998)));
+        // a clock alone is no eval, and not an eval of 0
+        assert_eq!(parse_eval_cp("[%clk 0:05:12]"), Ok(None));
+        assert_eq!(parse_eval_cp("[%clk 0:05:12] +0.25/10"), Ok(Some(25)));
+        assert!(parse_eval_cp("[%eval ??] [%clk 0:05:12]").is_err());
+    }
+
+    #[test]
+    fn clocks_read_hours_or_minutes() {
+        assert_eq!(parse_clock("[%eval 0.2] [%clk 1:05:12]"), Some(3912.0));
+        assert_eq!(parse_clock("[%clk 05:12]"), Some(312.0));
+        assert_eq!(parse_clock("[%clk 0:00:09.5]"), Some(9.5));
+        assert_eq!(parse_clock("[%clk 12]"), None);
+        assert_eq!(parse_clock("+0.20/10 1.2s"), None);
+    }
+
+    #[test]
+    fn embedded_braces_do_not_hide_the_eval() {
+        assert_eq!(parse_eval_cp("+0.50/20 (see {diagram})"), Ok(Some(50)));
+        assert_eq!(parse_eval_cp("{+0.50/20 (see {diagram})}"), Ok(Some(50)));
+        assert_eq!(parse_eval_cp("(see {-3.00/5}) +0.50/20"), Ok(Some(50)));
+        assert_eq!(parse_eval_cp("+0.50/20} 1.2s"), Ok(Some(50)));
+        assert_eq!(parse_eval_cp("{book} {-0.34/15}"), Ok(Some(-34)));
+    }
+}
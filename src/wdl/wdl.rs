@@ -1,10 +1,17 @@
 use shakmaty::{Chess, Position, Role};
 
+/// Scores at or beyond this magnitude are treated as mate scores and never rescaled.
+pub const MATE_SCORE_THRESHOLD: i32 = 29000;
+
+/// Score of a mate on the board; mate in n is stored as `MATE_SCORE - n`. Stored
+/// scores never exceed it, which keeps them clear of the VALUE_NONE sentinel.
+pub const MATE_SCORE: i32 = 32000;
+
 // Reverse of Stockfish to_cp(): internal_value = external_cp * a / 100
 pub fn external_cp_to_internal(external_cp: i32, pos: &Chess) -> i16 {
-    // If this looks like a mate score already (huge magnitude), keep as-is (clamped to i16)
-    if external_cp.abs() >= 29000 {
-        return external_cp.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+    // If this looks like a mate score already (huge magnitude), keep as-is (clamped to the mate range)
+    if external_cp.abs() >= MATE_SCORE_THRESHOLD {
+        return external_cp.clamp(-MATE_SCORE, MATE_SCORE) as i16;
     }
     let a = win_rate_a(pos);
     let internal = (external_cp as f64) * a / 100.0;
@@ -13,11 +20,29 @@ pub fn external_cp_to_internal(external_cp: i32, pos: &Chess) -> i16 {
     } else {
         (internal - 0.5).ceil()
     };
-    rounded.clamp(i16::MIN as f64, i16::MAX as f64) as i16
+    // large evals must not turn into mate scores
+    let limit = f64::from(MATE_SCORE_THRESHOLD - 1);
+    rounded.clamp(-limit, limit) as i16
+}
+
+/// Inverse of [`external_cp_to_internal`]: the centipawns Stockfish's to_cp() reports
+/// for an internal score. Mate scores are kept (clamped to the mate range).
+pub fn internal_to_external_cp(internal: i32, pos: &Chess) -> i16 {
+    if internal.abs() >= MATE_SCORE_THRESHOLD {
+        return internal.clamp(-MATE_SCORE, MATE_SCORE) as i16;
+    }
+    let cp = (internal as f64) * 100.0 / win_rate_a(pos);
+    let rounded = if cp.is_sign_positive() {
+        (cp + 0.5).floor()
+    } else {
+        (cp - 0.5).ceil()
+    };
+    let limit = f64::from(MATE_SCORE_THRESHOLD - 1);
+    rounded.clamp(-limit, limit) as i16
 }
 
 // Compute material like Stockfish: sum piece values (P=1 N=3 B=3 R=5 Q=9) both sides.
-fn material_count(pos: &Chess) -> i32 {
+pub fn material_count(pos: &Chess) -> i32 {
     let board = pos.board();
     fn count(board: &shakmaty::Board, role: Role) -> i32 {
         board.by_role(role).count() as i32
@@ -29,11 +54,101 @@ fn material_count(pos: &Chess) -> i32 {
         + 9 * count(board, Role::Queen)
 }
 
-// Polynomial producing 'a' parameter (b unused here) as per WinRateParams.
-fn win_rate_a(pos: &Chess) -> f64 {
+// Polynomials producing the 'a' and 'b' parameters as per WinRateParams.
+fn win_rate_params(pos: &Chess) -> (f64, f64) {
     let material = material_count(pos);
     let m = (material.clamp(17, 78) as f64) / 58.0;
     // Coefficients from Stockfish
     const AS: [f64; 4] = [-13.50030198, 40.92780883, -36.82753545, 386.83004070];
-    (((AS[0] * m + AS[1]) * m + AS[2]) * m) + AS[3]
+    const BS: [f64; 4] = [96.53354896, -165.79058388, 90.89679019, 49.29561889];
+    let a = (((AS[0] * m + AS[1]) * m + AS[2]) * m) + AS[3];
+    let b = (((BS[0] * m + BS[1]) * m + BS[2]) * m) + BS[3];
+    (a, b)
+}
+
+fn win_rate_a(pos: &Chess) -> f64 {
+    win_rate_params(pos).0
+}
+
+/// Win, draw and loss probabilities for the side to move given an internal score,
+/// following Stockfish's win_rate_model. Mate scores are certain wins or losses.
+pub fn wdl_probabilities(internal: i32, pos: &Chess) -> (f64, f64, f64) {
+    if internal.abs() >= MATE_SCORE_THRESHOLD {
+        return if internal > 0 {
+            (1.0, 0.0, 0.0)
+        } else {
+            (0.0, 0.0, 1.0)
+        };
+    }
+
+    let (a, b) = win_rate_params(pos);
+    let win_rate = |v: f64| 1.0 / (1.0 + ((a - v) / b).exp());
+    let win = win_rate(internal as f64);
+    let loss = win_rate(-(internal as f64));
+    (win, 1.0 - win - loss, loss)
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::Chess;
+
+    use shakmaty::fen::Fen;
+
+    use super::{
+        external_cp_to_internal, internal_to_external_cp, wdl_probabilities, MATE_SCORE_THRESHOLD,
+    };
+    use crate::analytics::VALUE_NONE_SCORE;
+    use crate::util::util::parse_eval_cp;
+
+    #[test]
+    fn internal_scores_survive_a_cp_roundtrip() {
+        let endgame: Chess = "8/5k2/8/8/3R4/8/2K5/8 w - - 0 1"
+            .parse::<Fen>()
+            .unwrap()
+            .into_position(shakmaty::CastlingMode::Standard)
+            .unwrap();
+
+        for pos in [Chess::default(), endgame] {
+            for internal in (-2000..=2000).step_by(7) {
+                let cp = internal_to_external_cp(internal, &pos);
+                let back = i32::from(external_cp_to_internal(i32::from(cp), &pos));
+                // one centipawn is worth a few internal units
+                assert!((back - internal).abs() <= 2, "{internal} -> {cp} -> {back}");
+                // and centipawns come back exactly
+                assert_eq!(internal_to_external_cp(back, &pos), cp);
+            }
+        }
+        assert_eq!(internal_to_external_cp(31990, &Chess::default()), 31990);
+    }
+
+    #[test]
+    fn probabilities_follow_the_win_rate_model() {
+        let pos = Chess::default();
+
+        // the cp normalization maps +1.00 to a 50% win rate
+        let internal = external_cp_to_internal(100, &pos) as i32;
+        let (win, draw, loss) = wdl_probabilities(internal, &pos);
+        assert!((win - 0.5).abs() < 0.01, "win = {win}");
+        assert!((win + draw + loss - 1.0).abs() < 1e-9);
+        assert!(draw > loss);
+
+        let (win, draw, loss) = wdl_probabilities(0, &pos);
+        assert!((win - loss).abs() < 1e-9);
+        assert!((win + draw + loss - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn large_evals_stay_clear_of_value_none() {
+        let pos = Chess::default();
+
+        for comment in ["+320.02/30", "-320.02/30", "+400.00/30", "+150.00/30"] {
+            let cp = parse_eval_cp(comment).unwrap().unwrap();
+            let stored = i32::from(external_cp_to_internal(i32::from(cp), &pos));
+            assert_ne!(stored.abs(), VALUE_NONE_SCORE, "{comment}");
+            assert!(stored.abs() <= 32000, "{comment}: {stored}");
+        }
+
+        let stored = i32::from(external_cp_to_internal(20000, &pos));
+        assert_eq!(stored, MATE_SCORE_THRESHOLD - 1);
+    }
 }
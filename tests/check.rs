@@ -0,0 +1,28 @@
+use std::process::Command;
+
+#[test]
+fn check_parses_evals_like_the_conversion() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("games.pgn"),
+        "[Result \"1-0\"]\n\n1. e4 {ev=31} e5 {ev=-20} 1-0\n",
+    )
+    .unwrap();
+
+    let check = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_pgn2binpack"))
+            .arg("--check")
+            .arg(dir.path())
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    assert!(!check(&[]).status.success());
+
+    let output = check(&["--eval-regex", r"ev=(?P<cp>-?\d+)"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("ok"), "{stdout}");
+    assert!(stdout.contains("1 games, 2 positions"), "{stdout}");
+}
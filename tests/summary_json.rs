@@ -0,0 +1,74 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn run(args: &[&Path]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_pgn2binpack"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn unique_summary_json_is_all_of_stdout() {
+    let dir = tempfile::tempdir().unwrap();
+    let pgns = dir.path().join("pgns");
+    std::fs::create_dir(&pgns).unwrap();
+    // the second game repeats the start position of the first
+    std::fs::write(
+        pgns.join("games.pgn"),
+        "[Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {-0.20/12} 2. Nf3 {+0.25/12} 1-0\n\n\
+         [Result \"*\"]\n\n1. d4 {+0.20/12} *\n",
+    )
+    .unwrap();
+    let binpack = dir.path().join("out.binpack");
+    run(&[&pgns, Path::new("--output"), &binpack]);
+
+    let output = run(&[Path::new("--unique"), &binpack, Path::new("--summary-json")]);
+    let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(summary["command"], "unique");
+    assert_eq!(summary["file"], binpack.display().to_string());
+    assert_eq!(summary["unique"], 3);
+    assert!(summary["elapsed_ms"].is_u64());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unique positions"));
+}
+
+#[test]
+fn view_summary_json_needs_a_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let pgns = dir.path().join("pgns");
+    std::fs::create_dir(&pgns).unwrap();
+    std::fs::write(
+        pgns.join("games.pgn"),
+        "[Result \"1-0\"]\n\n1. e4 {+0.30/12} e5 {-0.20/12} 1-0\n",
+    )
+    .unwrap();
+    let binpack = dir.path().join("out.binpack");
+    run(&[&pgns, Path::new("--output"), &binpack]);
+
+    // the entries own stdout, so JSON there would not parse
+    let output = Command::new(env!("CARGO_BIN_EXE_pgn2binpack"))
+        .args([Path::new("--view"), &binpack, Path::new("--summary-json")])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--summary-json needs a FILE"));
+
+    let json = dir.path().join("summary.json");
+    run(&[
+        Path::new("--view"),
+        &binpack,
+        Path::new("--summary-json"),
+        &json,
+    ]);
+    let summary: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&json).unwrap()).unwrap();
+    assert_eq!(summary["command"], "view");
+    assert_eq!(summary["positions"], 2);
+}